@@ -0,0 +1,127 @@
+//! Forward pass of a whole population, run with `cargo bench`
+
+use std::{hint::black_box, time::Instant};
+
+use ai_snake::ai_snake::neural_network::{
+    architecture::Architecture, batch::BatchedNetwork, ActivationFunction, NeuralNetwork, Scratch,
+};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+const POPULATION: usize = 3000;
+const INPUT: usize = 16;
+const OUTPUT: usize = 4;
+const ROUNDS: usize = 50;
+
+/// The layout used before weights were flattened: `weights[input][output]`, one `Vec` per layer
+struct NestedLayer {
+    weights: Vec<Vec<f64>>,
+    biases: Vec<f64>,
+    activation: ActivationFunction,
+}
+
+fn nested(network: &NeuralNetwork) -> Vec<NestedLayer> {
+    network
+        .layers
+        .iter()
+        .map(|layer| NestedLayer {
+            weights: (0..layer.input_dim)
+                .map(|i| (0..layer.output_dim).map(|o| layer.weight(i, o)).collect())
+                .collect(),
+            biases: if layer.use_bias {
+                layer.biases().to_vec()
+            } else {
+                vec![0.0; layer.output_dim]
+            },
+            activation: layer.activation(),
+        })
+        .collect()
+}
+
+fn nested_forward(layers: &[NestedLayer], mut input: Vec<f64>) -> Vec<f64> {
+    for layer in layers {
+        let mut output = layer.biases.clone();
+        (0..output.len()).for_each(|j| {
+            (0..input.len()).for_each(|k| {
+                output[j] += input[k] * layer.weights[k][j];
+            });
+        });
+        layer.activation.apply(&mut output);
+        input = output;
+    }
+    let sum: f64 = input.iter().map(|x| x.abs()).sum();
+    if sum > 0.0 {
+        input.iter_mut().for_each(|x| *x /= sum);
+    }
+    input
+}
+
+/// Average time of one forward pass of the whole population, in milliseconds
+fn time(mut pass: impl FnMut()) -> f64 {
+    pass();
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        pass();
+    }
+    start.elapsed().as_secs_f64() * 1000. / ROUNDS as f64
+}
+
+fn main() {
+    let mut rng = ChaCha8Rng::seed_from_u64(0);
+    let architecture = Architecture::default();
+    let networks: Vec<NeuralNetwork> = (0..POPULATION)
+        .map(|_| architecture.build(INPUT, OUTPUT, &mut rng))
+        .collect();
+    let inputs: Vec<f64> = (0..POPULATION * INPUT).map(|_| rng.gen()).collect();
+    let inputs_f32: Vec<f32> = inputs.iter().map(|&x| x as f32).collect();
+
+    let nested_networks: Vec<_> = networks.iter().map(nested).collect();
+    let mut scratch = Scratch::default();
+    let mut batch = BatchedNetwork::<f64>::new(&networks).unwrap();
+    let mut batch_f32 = BatchedNetwork::<f32>::new(&networks).unwrap();
+
+    let baseline = time(|| {
+        for (layers, input) in nested_networks.iter().zip(inputs.chunks(INPUT)) {
+            black_box(nested_forward(layers, input.to_vec()));
+        }
+    });
+    let results = [
+        (
+            "NeuralNetwork::forward",
+            time(|| {
+                for (network, input) in networks.iter().zip(inputs.chunks(INPUT)) {
+                    black_box(network.forward(input.to_vec()));
+                }
+            }),
+        ),
+        (
+            "NeuralNetwork::forward_with",
+            time(|| {
+                for (network, input) in networks.iter().zip(inputs.chunks(INPUT)) {
+                    black_box(network.forward_with(input, &mut scratch));
+                }
+            }),
+        ),
+        (
+            "BatchedNetwork<f64>",
+            time(|| {
+                black_box(batch.forward(&inputs));
+            }),
+        ),
+        (
+            "BatchedNetwork<f32>",
+            time(|| {
+                black_box(batch_f32.forward(&inputs_f32));
+            }),
+        ),
+    ];
+
+    println!(
+        "{POPULATION} networks, {architecture_layers} hidden layers, {ROUNDS} rounds",
+        architecture_layers = architecture.hidden_layers.len()
+    );
+    println!("{:<28} {:>8.3} ms", "nested weights (before)", baseline);
+    for (name, ms) in results {
+        println!("{name:<28} {ms:>8.3} ms  x{:.2}", baseline / ms);
+    }
+}
@@ -0,0 +1,182 @@
+use std::{
+    fmt, fs,
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    neural_network::{genetic::GeneticModel, persistence::BrainError},
+    simulation::Configuration,
+    ui::AppConfig,
+};
+
+/// Version written in every checkpoint, bumped when the layout changes
+pub const CHECKPOINT_FORMAT_VERSION: u32 = 1;
+
+const CHECKPOINT_MAGIC: &[u8; 4] = b"SNKC";
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(io::Error),
+    Binary(bincode::Error),
+    UnsupportedVersion(u32),
+    Brain { model: usize, error: BrainError },
+}
+
+#[derive(Serialize)]
+struct CheckpointRef<'a> {
+    app_config: &'a AppConfig,
+    simulation: &'a GeneticModel,
+}
+
+#[derive(Deserialize)]
+struct Checkpoint {
+    app_config: AppConfig,
+    simulation: GeneticModel,
+}
+
+/// Writes the whole run: hyperparameters, history, every model mid-game and the RNG state
+pub fn save_checkpoint(
+    path: &Path,
+    app_config: &AppConfig,
+    sim_config: &Configuration,
+) -> Result<(), CheckpointError> {
+    let mut bytes = CHECKPOINT_MAGIC.to_vec();
+    bytes.extend_from_slice(&CHECKPOINT_FORMAT_VERSION.to_le_bytes());
+    bincode::serialize_into(
+        &mut bytes,
+        &CheckpointRef {
+            app_config,
+            simulation: &sim_config.simulation,
+        },
+    )
+    .map_err(CheckpointError::Binary)?;
+
+    fs::File::create(path)
+        .and_then(|mut file| file.write_all(&bytes))
+        .map_err(CheckpointError::Io)
+}
+
+/// Reads a checkpoint written by [`save_checkpoint`], ready to resume
+pub fn load_checkpoint(path: &Path) -> Result<(AppConfig, Configuration), CheckpointError> {
+    let bytes = fs::read(path).map_err(CheckpointError::Io)?;
+    let mut reader = bytes.as_slice();
+    let mut magic = [0; 4];
+    let mut version = [0; 4];
+    reader.read_exact(&mut magic).map_err(CheckpointError::Io)?;
+    if &magic != CHECKPOINT_MAGIC {
+        return Err(CheckpointError::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a checkpoint file",
+        )));
+    }
+    reader
+        .read_exact(&mut version)
+        .map_err(CheckpointError::Io)?;
+    let version = u32::from_le_bytes(version);
+    if version != CHECKPOINT_FORMAT_VERSION {
+        return Err(CheckpointError::UnsupportedVersion(version));
+    }
+
+    let checkpoint: Checkpoint =
+        bincode::deserialize_from(reader).map_err(CheckpointError::Binary)?;
+    for (i, model) in checkpoint.simulation.population.iter().enumerate() {
+        model
+            .brain
+            .validate()
+            .map_err(|error| CheckpointError::Brain { model: i, error })?;
+    }
+
+    let sim_config = Configuration::resume(checkpoint.simulation, &checkpoint.app_config);
+    Ok((checkpoint.app_config, sim_config))
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheckpointError::Io(e) => write!(f, "could not access checkpoint: {e}"),
+            CheckpointError::Binary(e) => write!(f, "invalid checkpoint: {e}"),
+            CheckpointError::UnsupportedVersion(v) => write!(
+                f,
+                "unsupported checkpoint version {v} (expected {CHECKPOINT_FORMAT_VERSION})"
+            ),
+            CheckpointError::Brain { model, error } => write!(f, "model {model}: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("{name}_{}.bin", std::process::id()))
+    }
+
+    /// Steps until `generation` is reached and `moves` moves of it are played
+    fn advance(
+        sim_config: &mut Configuration,
+        app_config: &mut AppConfig,
+        generation: u64,
+        moves: u64,
+    ) {
+        while app_config.generation_number < generation || app_config.current_moves < moves {
+            if sim_config.step(app_config) {
+                sim_config.next_generation(app_config);
+            }
+        }
+    }
+
+    fn brains(sim_config: &Configuration) -> Vec<String> {
+        sim_config
+            .simulation
+            .population
+            .iter()
+            .map(|model| serde_json::to_string(&model.brain).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn resumed_runs_pick_up_where_they_stopped() {
+        let mut app_config = AppConfig {
+            grid_size: 10,
+            vision_range: 10,
+            population_size: 20,
+            allowed_moves: 40,
+            seed: 3,
+            ..AppConfig::default()
+        };
+        let mut sim_config = Configuration::new(&app_config);
+        // in the middle of a generation
+        advance(&mut sim_config, &mut app_config, 1, 10);
+
+        let path = path("checkpoint");
+        save_checkpoint(&path, &app_config, &sim_config).unwrap();
+        let loaded = load_checkpoint(&path);
+        fs::remove_file(&path).unwrap();
+        let (mut resumed_config, mut resumed) = loaded.unwrap();
+        assert_eq!(resumed_config.current_moves, app_config.current_moves);
+
+        advance(&mut sim_config, &mut app_config, 4, 0);
+        advance(&mut resumed, &mut resumed_config, 4, 0);
+        assert_eq!(resumed_config.score_history, app_config.score_history);
+        assert_eq!(brains(&resumed), brains(&sim_config));
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let path = path("old_checkpoint");
+        let mut bytes = CHECKPOINT_MAGIC.to_vec();
+        bytes.extend_from_slice(&(CHECKPOINT_FORMAT_VERSION + 1).to_le_bytes());
+        fs::write(&path, bytes).unwrap();
+        let loaded = load_checkpoint(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(
+            matches!(loaded, Err(CheckpointError::UnsupportedVersion(v)) if v == CHECKPOINT_FORMAT_VERSION + 1)
+        );
+    }
+}
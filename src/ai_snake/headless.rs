@@ -0,0 +1,458 @@
+use std::path::PathBuf;
+
+use super::{
+    checkpoint::{load_checkpoint, save_checkpoint},
+    neural_network::{
+        brain::{Brain, BrainKind},
+        dqn::{Dqn, DqnConfig},
+        imitation::{clone_behaviour, Dataset, ImitationConfig},
+        island::IslandSettings,
+        persistence::{BrainError, BrainFormat},
+        sensor::{PlaneView, SensorConfig},
+    },
+    simulation::Configuration,
+    ui::AppConfig,
+};
+
+const USAGE: &str = "Usage: ai_snake train [OPTIONS]
+
+Options:
+    --config <path>           read the options from a JSON config file, the options
+                              given after it take precedence
+    --generations <n>         stop after n generations (default: run forever)
+    --grid-size <n>           width and height of each grid
+    --population-size <n>     number of snakes per generation
+    --allowed-moves <n>       moves allowed before evolution
+    --mutation-factor <x>     mutation factor, divided by the size of an island
+    --mutation <name>         reset, gaussian, scaled or sign-flip
+    --mutation-sigma <x>      standard deviation of the gaussian and scaled mutations
+    --mutation-schedule <name>
+                              constant, decay, 1/5th-rule or self-adaptive
+    --mutation-decay <x>      factor applied every generation by the decay schedule
+    --fitness <expr>          what models are selected on, a weighted sum of food,
+                              steps, exponential, approach, revisits and timeout,
+                              e.g. food+0.01*steps-timeout (default: food)
+    --episodes <n>            games played by every model each generation
+    --common-seeds            give the whole population the same food in each episode
+    --aggregation <name>      mean, median or worst, how the fitness of the episodes
+                              of a model are combined
+    --optimiser <name>        genetic, openai-es or cma-es, the evolution strategies
+                              only apply to layered brains, cma-es adapts a diagonal
+                              covariance above 100 weights
+    --es-sigma <x>            standard deviation of the evolution strategy samples
+    --es-learning-rate <x>    step of the OpenAI-ES centre
+    --selection <name>        threshold, tournament, roulette, rank or truncation
+    --keep-x-best <x>         threshold: kept if fitness >= (1 - x) * best_fitness,
+                              truncation: fraction of the population kept
+    --tournament-size <n>     models drawn per tournament
+    --elitism <n>             best models copied unchanged into the next generation
+    --speciation              split layered networks into species with fitness sharing
+    --species-target <n>      species count the layered threshold is adjusted towards
+    --islands <n>             sub-populations evolving on their own
+    --migration-interval <n>  generations between migrations, 0 never migrates
+    --migration-size <n>      best models each island sends to each destination
+    --migration-topology <name>
+                              ring or fully-connected
+    --island-mutation-factor <i>=<x>
+                              mutation factor of island i, counted from 0
+    --island-selection <i>=<name>
+                              selection strategy of island i
+    --crossover <name>        merge-all, uniform, single-point, per-layer or blend,
+                              NEAT genomes always use their own crossover
+    --brain <kind>            layered or neat
+    --add-connection <x>      NEAT: probability to gain a connection each generation
+    --add-node <x>            NEAT: probability to gain a node each generation
+    --compatibility-threshold <x>
+                              NEAT: distance under which genomes share a species
+    --dqn                     train a layered brain by deep Q-learning before evolving,
+                              every snake starts from it
+    --dqn-episodes <n>        episodes played by the Q-learning trainer
+    --dqn-environments <n>    grids played at the same time by the trainer
+    --dqn-batch-size <n>      transitions per gradient step
+    --dqn-replay-size <n>     transitions kept in the replay buffer
+    --dqn-gamma <x>           discount of future rewards
+    --dqn-learning-rate <x>   step size of the Adam optimiser
+    --dqn-target-sync <n>     gradient steps between two target network updates
+    --imitate <path>          fit a layered brain to a dataset recorded with
+                              `ai_snake play --record`, every snake starts from it
+    --imitation-epochs <n>    passes over the dataset
+    --imitation-batch-size <n>
+                              demonstrations per gradient step
+    --imitation-learning-rate <x>
+                              step size of the Adam optimiser
+    --vision-range <n>        vision range of the snakes (default: grid size)
+    --sensors <list>          what the snakes perceive, a comma separated list of rays,
+                              head-direction, tail-direction, length, food-vector,
+                              wall-distance, occupancy and planes (default: rays),
+                              planes alone keep their shape for convolutions
+    --window <n>              side of the occupancy window, in cells
+    --planes <view>           board or window, what the body, head, food and
+                              wall planes cover, turned by --frame
+    --plane-size <n>          side of the planes, in cells (default: the whole
+                              board with the board view, 9 otherwise)
+    --frame <name>            absolute or egocentric, egocentric sensors turn with
+                              the head so that it always faces up
+    --actions <name>          absolute, one output per direction, or relative, turn
+                              left, go straight or turn right, best paired with
+                              --frame egocentric
+    --food-amount <n>         food on each grid
+    --threads <n>             threads stepping the population, 0 uses every core
+    --seed <n>                seed of the run (default: random)
+    --print-input             print I/O for model #0
+    --load-brain <path>       start every snake from a saved brain
+    --save-brain <path>       save the brain of model #0 when training ends,
+                              as JSON if the path ends in .json, binary otherwise
+    --resume <path>           resume a checkpoint, whose settings replace the ones
+                              above except --generations and --threads
+    --hall-of-fame-size <n>   best brains ever seen kept in the hall of fame
+    --hall-of-fame <dir>      export the hall of fame as JSON when training ends
+    --checkpoint <path>       save a checkpoint of the whole run when training ends
+    --checkpoint-every <n>    also save the checkpoint every n generations
+    -h, --help                print this message";
+
+#[derive(Default)]
+struct TrainOptions {
+    generations: Option<u64>,
+    load_brain: Option<PathBuf>,
+    save_brain: Option<PathBuf>,
+    resume: Option<PathBuf>,
+    checkpoint: Option<PathBuf>,
+    checkpoint_every: Option<u64>,
+    hall_of_fame: Option<PathBuf>,
+    /// Deep Q-learning run before the first generation
+    dqn: Option<DqnConfig>,
+    /// Dataset the first generation is fitted to
+    imitate: Option<PathBuf>,
+    imitation: ImitationConfig,
+}
+
+/// Entry point of `ai_snake train`, runs the simulation without any window
+pub fn run(args: &[String]) -> Result<(), String> {
+    let Some((mut app_config, options)) = parse_args(args)? else {
+        println!("{USAGE}");
+        return Ok(());
+    };
+    let mut sim_config = if let Some(path) = &options.resume {
+        let (loaded, sim_config) = load_checkpoint(path).map_err(|e| e.to_string())?;
+        app_config.resume(loaded);
+        println!("{}", app_config.file_status);
+        sim_config
+    } else {
+        Configuration::new(&app_config)
+    };
+    if let Some(brain) = options
+        .load_brain
+        .as_deref()
+        .map(Brain::load)
+        .transpose()
+        .map_err(|e: BrainError| e.to_string())?
+    {
+        let input_size = app_config.sensors.input_size();
+        let output_size = app_config.actions.size();
+        if brain.input_dim() != input_size || brain.output_dim() != output_size {
+            return Err(format!(
+                "brain maps {} inputs to {} outputs, snakes need {} to {}",
+                brain.input_dim(),
+                brain.output_dim(),
+                input_size,
+                output_size
+            ));
+        }
+        // the loaded brain decides how the population evolves
+        app_config.architecture.kind = brain.kind();
+        sim_config.simulation.set_brains(&brain);
+    }
+    if (options.dqn.is_some() || options.imitate.is_some())
+        && app_config.architecture.kind != BrainKind::Layered
+    {
+        return Err("deep Q-learning and imitation only train layered brains".to_string());
+    }
+    if let Some(path) = &options.imitate {
+        let dataset = Dataset::load(path).map_err(|e| e.to_string())?;
+        if dataset.sensors != app_config.sensors {
+            return Err(format!(
+                "the dataset was recorded with the sensors {}, not {}",
+                dataset.sensors, app_config.sensors
+            ));
+        }
+        if dataset.vision_range != app_config.vision_range {
+            println!(
+                "Warning: the dataset was recorded with a vision range of {}, not {}",
+                dataset.vision_range, app_config.vision_range
+            );
+        }
+        let left_out = dataset
+            .demonstrations
+            .iter()
+            .filter(|demonstration| demonstration.action(app_config.actions).is_none())
+            .count();
+        if left_out > 0 {
+            println!(
+                "Warning: {left_out} demonstrations left out, {} actions cannot reproduce them",
+                app_config.actions
+            );
+        }
+        let network = clone_behaviour(
+            &app_config.architecture,
+            &dataset,
+            app_config.actions,
+            &options.imitation,
+            app_config.seed,
+            |report| {
+                println!(
+                    "[Imitation {}] Loss: {:.4}, Accuracy: {:.1}%",
+                    report.epoch,
+                    report.loss,
+                    report.accuracy * 100.
+                )
+            },
+        );
+        sim_config.simulation.set_brains(&network.into());
+    }
+    if let Some(config) = options.dqn {
+        let mut dqn = Dqn::new(
+            &app_config.architecture,
+            app_config.sensors.input_shape(),
+            app_config.actions,
+            config,
+            app_config.seed,
+        );
+        dqn.train(&app_config, |report| {
+            println!(
+                "[DQN {}] Average: {:.2}, Best: {}, Epsilon: {:.2}, Loss: {:.4}",
+                report.episodes, report.mean_score, report.best_score, report.epsilon, report.loss
+            )
+        });
+        sim_config.simulation.set_brains(&dqn.network.into());
+    }
+
+    let mut checkpoint_result = Ok(());
+    train(
+        &mut sim_config,
+        &mut app_config,
+        options.generations,
+        |sim_config, app_config| {
+            if let (Some(path), Some(every)) = (&options.checkpoint, options.checkpoint_every) {
+                if checkpoint_result.is_ok() && app_config.generation_number % every == 0 {
+                    checkpoint_result = save_checkpoint(path, app_config, sim_config);
+                }
+            }
+        },
+    );
+    checkpoint_result.map_err(|e| e.to_string())?;
+
+    if let Some(path) = &options.checkpoint {
+        save_checkpoint(path, &app_config, &sim_config).map_err(|e| e.to_string())?;
+        println!("Saved checkpoint to {}", path.display());
+    }
+    if let Some(path) = options.save_brain {
+        if let Some(model) = sim_config.simulation.population.first() {
+            model
+                .brain
+                .save(&path, BrainFormat::from_path(&path))
+                .map_err(|e| e.to_string())?;
+            println!("Saved brain to {}", path.display());
+        }
+    }
+    if let Some(dir) = options.hall_of_fame {
+        let paths = sim_config
+            .simulation
+            .hall_of_fame
+            .export(&dir, BrainFormat::Json)
+            .map_err(|e| e.to_string())?;
+        println!("Exported {} brains to {}", paths.len(), dir.display());
+    }
+    Ok(())
+}
+
+/// Runs generations until `generations` is reached, or forever if it is `None`,
+/// calling `on_generation` after each evolution
+pub fn train(
+    sim_config: &mut Configuration,
+    app_config: &mut AppConfig,
+    generations: Option<u64>,
+    mut on_generation: impl FnMut(&Configuration, &AppConfig),
+) {
+    while generations.is_none_or(|g| app_config.generation_number < g) {
+        if sim_config.step(app_config) {
+            sim_config.next_generation(app_config);
+            on_generation(sim_config, app_config);
+        }
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Option<(AppConfig, TrainOptions)>, String> {
+    let mut app_config = AppConfig::default();
+    let mut options = TrainOptions::default();
+    let mut vision_range = None;
+    let mut grid_size_given = false;
+    let mut plane_size = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("missing value for {arg}\n\n{USAGE}"))
+        };
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "--config" => {
+                let path = PathBuf::from(value()?);
+                app_config = AppConfig::load(&path)
+                    .map_err(|e| format!("could not read {}: {e}", path.display()))?;
+            }
+            "--generations" => options.generations = Some(parse(arg, value()?)?),
+            "--grid-size" => {
+                app_config.grid_size = parse(arg, value()?)?;
+                grid_size_given = true;
+            }
+            "--population-size" => app_config.population_size = parse(arg, value()?)?,
+            "--allowed-moves" => app_config.allowed_moves = parse(arg, value()?)?,
+            "--mutation-factor" => app_config.mutation_factor = parse(arg, value()?)?,
+            "--mutation" => app_config.mutation_operator = parse(arg, value()?)?,
+            "--mutation-sigma" => app_config.mutation_sigma = parse(arg, value()?)?,
+            "--mutation-schedule" => app_config.mutation_schedule = parse(arg, value()?)?,
+            "--mutation-decay" => app_config.mutation_decay = parse(arg, value()?)?,
+            "--fitness" => app_config.fitness = parse(arg, value()?)?,
+            "--episodes" => app_config.episodes = parse(arg, value()?)?,
+            "--common-seeds" => app_config.common_seeds = true,
+            "--aggregation" => app_config.aggregation = parse(arg, value()?)?,
+            "--optimiser" => app_config.optimiser = parse(arg, value()?)?,
+            "--es-sigma" => app_config.es_sigma = parse(arg, value()?)?,
+            "--es-learning-rate" => app_config.es_learning_rate = parse(arg, value()?)?,
+            "--selection" => app_config.selection = parse(arg, value()?)?,
+            "--keep-x-best" => app_config.keep_x_best = parse(arg, value()?)?,
+            "--tournament-size" => app_config.tournament_size = parse(arg, value()?)?,
+            "--elitism" => app_config.elitism = parse(arg, value()?)?,
+            "--hall-of-fame-size" => app_config.hall_of_fame_size = parse(arg, value()?)?,
+            "--hall-of-fame" => options.hall_of_fame = Some(value()?.into()),
+            "--speciation" => app_config.speciation = true,
+            "--species-target" => app_config.species_target = parse(arg, value()?)?,
+            "--islands" => app_config.islands = parse(arg, value()?)?,
+            "--migration-interval" => app_config.migration_interval = parse(arg, value()?)?,
+            "--migration-size" => app_config.migration_size = parse(arg, value()?)?,
+            "--migration-topology" => app_config.migration_topology = parse(arg, value()?)?,
+            "--island-mutation-factor" => {
+                let (island, factor) = parse_island(arg, value()?)?;
+                island_settings(&mut app_config, island).mutation_factor = Some(factor);
+            }
+            "--island-selection" => {
+                let (island, selection) = parse_island(arg, value()?)?;
+                island_settings(&mut app_config, island).selection = Some(selection);
+            }
+            "--crossover" => app_config.crossover = parse(arg, value()?)?,
+            "--brain" => app_config.architecture.kind = parse(arg, value()?)?,
+            "--add-connection" => app_config.neat.add_connection = parse(arg, value()?)?,
+            "--add-node" => app_config.neat.add_node = parse(arg, value()?)?,
+            "--compatibility-threshold" => {
+                app_config.neat.compatibility_threshold = parse(arg, value()?)?
+            }
+            "--dqn" => {
+                options.dqn.get_or_insert_with(DqnConfig::default);
+            }
+            "--dqn-episodes" => dqn(&mut options).episodes = parse(arg, value()?)?,
+            "--dqn-environments" => dqn(&mut options).environments = parse(arg, value()?)?,
+            "--dqn-batch-size" => dqn(&mut options).batch_size = parse(arg, value()?)?,
+            "--dqn-replay-size" => dqn(&mut options).replay_capacity = parse(arg, value()?)?,
+            "--dqn-gamma" => dqn(&mut options).gamma = parse(arg, value()?)?,
+            "--dqn-learning-rate" => dqn(&mut options).learning_rate = parse(arg, value()?)?,
+            "--dqn-target-sync" => dqn(&mut options).target_sync = parse(arg, value()?)?,
+            "--imitate" => options.imitate = Some(value()?.into()),
+            "--imitation-epochs" => options.imitation.epochs = parse(arg, value()?)?,
+            "--imitation-batch-size" => options.imitation.batch_size = parse(arg, value()?)?,
+            "--imitation-learning-rate" => options.imitation.learning_rate = parse(arg, value()?)?,
+            "--vision-range" => vision_range = Some(parse(arg, value()?)?),
+            "--sensors" => {
+                app_config.sensors.enabled = parse::<SensorConfig>(arg, value()?)?.enabled
+            }
+            "--window" => app_config.sensors.window = parse(arg, value()?)?,
+            "--planes" => app_config.sensors.planes = parse(arg, value()?)?,
+            "--frame" => app_config.sensors.frame = parse(arg, value()?)?,
+            "--actions" => app_config.actions = parse(arg, value()?)?,
+            "--plane-size" => plane_size = Some(parse(arg, value()?)?),
+            "--food-amount" => app_config.food_amount = parse(arg, value()?)?,
+            "--threads" => app_config.threads = parse(arg, value()?)?,
+            "--seed" => app_config.seed = parse(arg, value()?)?,
+            "--print-input" => app_config.print_input = true,
+            "--load-brain" => options.load_brain = Some(value()?.into()),
+            "--save-brain" => options.save_brain = Some(value()?.into()),
+            "--resume" => options.resume = Some(value()?.into()),
+            "--checkpoint" => options.checkpoint = Some(value()?.into()),
+            "--checkpoint-every" => {
+                let every = parse(arg, value()?)?;
+                if every == 0 {
+                    return Err(format!("{arg} must be at least 1\n\n{USAGE}"));
+                }
+                options.checkpoint_every = Some(every);
+            }
+            _ => return Err(format!("unknown argument {arg}\n\n{USAGE}")),
+        }
+    }
+    if options.dqn.is_some() && options.imitate.is_some() {
+        return Err("--dqn and --imitate both train the first brain, give only one".to_string());
+    }
+    // the vision range follows the grid size unless given
+    if let Some(vision_range) = vision_range {
+        app_config.vision_range = vision_range;
+    } else if grid_size_given {
+        app_config.vision_range = app_config.grid_size as i64;
+    }
+    // the board view shows the whole board, walls included, unless given
+    if let Some(plane_size) = plane_size {
+        app_config.sensors.plane_size = plane_size;
+    } else if app_config.sensors.planes == PlaneView::Board {
+        app_config.sensors.show_board(app_config.grid_size);
+    }
+
+    Ok(Some((app_config, options)))
+}
+
+fn parse<T: std::str::FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value for {arg}: {value}"))
+}
+
+/// Parses `<island>=<value>`
+fn parse_island<T: std::str::FromStr>(arg: &str, value: &str) -> Result<(usize, T), String> {
+    let (island, setting) = value
+        .split_once('=')
+        .ok_or_else(|| format!("invalid value for {arg}: {value}, expected <island>=<value>"))?;
+    Ok((parse(arg, island)?, parse(arg, setting)?))
+}
+
+fn island_settings(app_config: &mut AppConfig, island: usize) -> &mut IslandSettings {
+    if app_config.island_settings.len() <= island {
+        app_config
+            .island_settings
+            .resize_with(island + 1, Default::default);
+    }
+    &mut app_config.island_settings[island]
+}
+
+/// Settings of the deep Q-learning trainer, giving any of them enables it
+fn dqn(options: &mut TrainOptions) -> &mut DqnConfig {
+    options.dqn.get_or_insert_with(DqnConfig::default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn checkpoints_are_saved_at_least_every_generation() {
+        let error = parse_args(&args(&["--checkpoint-every", "0"]))
+            .err()
+            .unwrap();
+        assert!(error.starts_with("--checkpoint-every must be at least 1"));
+
+        let (_, options) = parse_args(&args(&["--checkpoint-every", "5"]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(options.checkpoint_every, Some(5));
+    }
+}
@@ -1,5 +1,7 @@
 pub mod ai_snake_plugin;
-mod neural_network;
-mod simulation;
+pub mod checkpoint;
+pub mod headless;
+pub mod neural_network;
+pub mod simulation;
 mod simulation_rendering;
-mod ui;
+pub mod ui;
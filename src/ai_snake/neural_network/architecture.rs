@@ -0,0 +1,327 @@
+use std::fmt;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    brain::{Brain, BrainKind},
+    neat::{NeatConfig, NeatGenome},
+    ActivationFunction, Layer, NeuralNetwork, Shape,
+};
+
+/// Shape of the brains of a run, input and output sizes are given when building
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Architecture {
+    /// NEAT genomes ignore the hidden layers and start without hidden nodes
+    #[serde(default)]
+    pub kind: BrainKind,
+    pub hidden_layers: Vec<LayerSpec>,
+    pub output_activation: ActivationFunction,
+    pub output_bias: bool,
+    pub init: InitScheme,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LayerSpec {
+    /// Units of dense layers, filters of convolutions, unused by the other kinds
+    pub width: usize,
+    pub activation: ActivationFunction,
+    pub use_bias: bool,
+    #[serde(default)]
+    pub kind: LayerType,
+    /// Side of the kernels of convolutions, of the blocks of poolings
+    #[serde(default = "default_kernel")]
+    pub kernel: usize,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum LayerType {
+    #[default]
+    Dense,
+    Conv2d,
+    MaxPool,
+    Flatten,
+}
+
+fn default_kernel() -> usize {
+    3
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum InitScheme {
+    /// Weights and biases uniform in [-1, 1]
+    Uniform,
+    /// Weights uniform in ±sqrt(6 / (input + output)), zero biases
+    Xavier,
+    /// Weights uniform in ±sqrt(6 / input), zero biases
+    He,
+}
+
+impl InitScheme {
+    pub const ALL: [InitScheme; 3] = [InitScheme::Uniform, InitScheme::Xavier, InitScheme::He];
+}
+
+impl LayerType {
+    pub const ALL: [LayerType; 4] = [
+        LayerType::Dense,
+        LayerType::Conv2d,
+        LayerType::MaxPool,
+        LayerType::Flatten,
+    ];
+}
+
+impl Default for Architecture {
+    fn default() -> Self {
+        Architecture {
+            kind: BrainKind::Layered,
+            hidden_layers: vec![LayerSpec::default(), LayerSpec::default()],
+            output_activation: ActivationFunction::Softmax,
+            output_bias: true,
+            init: InitScheme::Uniform,
+        }
+    }
+}
+
+impl Default for LayerSpec {
+    fn default() -> Self {
+        LayerSpec {
+            width: 16,
+            activation: ActivationFunction::Identity,
+            use_bias: true,
+            kind: LayerType::Dense,
+            kernel: default_kernel(),
+        }
+    }
+}
+
+impl Architecture {
+    /// NEAT genomes read `input` flat
+    pub fn new_brain(
+        &self,
+        input: Shape,
+        output_dim: usize,
+        neat: &NeatConfig,
+        rng: &mut impl Rng,
+    ) -> Brain {
+        match self.kind {
+            BrainKind::Layered => Brain::Layered(self.build_from(input, output_dim, rng)),
+            BrainKind::Neat => Brain::Neat(NeatGenome::minimal(
+                input.len(),
+                output_dim,
+                neat.hidden_activation,
+                self.output_activation,
+                rng,
+            )),
+        }
+    }
+
+    pub fn build(&self, input_dim: usize, output_dim: usize, rng: &mut impl Rng) -> NeuralNetwork {
+        self.build_from(input_dim.into(), output_dim, rng)
+    }
+
+    /// Builds a brain reading planes of `input`, which convolutions and poolings slide over.
+    /// Dense layers read whatever comes before them flat
+    pub fn build_from(&self, input: Shape, output_dim: usize, rng: &mut impl Rng) -> NeuralNetwork {
+        let mut brain = NeuralNetwork::new();
+        let mut shape = input;
+        for spec in self.hidden_layers.iter() {
+            let layer = match spec.kind {
+                LayerType::Dense => {
+                    self.init
+                        .layer(shape.len(), spec.width, spec.activation, spec.use_bias, rng)
+                }
+                LayerType::Conv2d => self.init.conv2d(shape, spec, rng),
+                LayerType::MaxPool => {
+                    let largest = shape.height.min(shape.width).max(1);
+                    Layer::max_pool(shape, spec.kernel.clamp(1, largest))
+                }
+                LayerType::Flatten => Layer::flatten(shape.len()),
+            };
+            shape = layer.output_shape();
+            brain.add_layer(layer);
+        }
+        brain.add_layer(self.init.layer(
+            shape.len(),
+            output_dim,
+            self.output_activation,
+            self.output_bias,
+            rng,
+        ));
+        brain
+    }
+}
+
+impl InitScheme {
+    fn layer(
+        &self,
+        input_dim: usize,
+        output_dim: usize,
+        activation: ActivationFunction,
+        use_bias: bool,
+        rng: &mut impl Rng,
+    ) -> Layer {
+        let limit = match self {
+            InitScheme::Uniform => 1.,
+            InitScheme::Xavier => (6. / (input_dim + output_dim) as f64).sqrt(),
+            InitScheme::He => (6. / input_dim as f64).sqrt(),
+        };
+        let mut weights = vec![0.0; output_dim * input_dim];
+        for i in 0..input_dim {
+            for o in 0..output_dim {
+                weights[o * input_dim + i] = (rng.gen::<f64>() * 2. - 1.) * limit;
+            }
+        }
+
+        let mut biases = vec![0.; output_dim];
+        if *self == InitScheme::Uniform {
+            for b in biases.iter_mut() {
+                *b = rng.gen::<f64>() * 2. - 1.;
+            }
+        }
+
+        Layer::new(input_dim, output_dim, weights, biases, activation).with_bias(use_bias)
+    }
+
+    fn conv2d(&self, input: Shape, spec: &LayerSpec, rng: &mut impl Rng) -> Layer {
+        let kernel = spec.kernel.max(1);
+        let fan_in = input.channels * kernel * kernel;
+        let fan_out = spec.width * kernel * kernel;
+        let limit = match self {
+            InitScheme::Uniform => 1.,
+            InitScheme::Xavier => (6. / (fan_in + fan_out) as f64).sqrt(),
+            InitScheme::He => (6. / fan_in as f64).sqrt(),
+        };
+        let weights = (0..spec.width * fan_in)
+            .map(|_| (rng.gen::<f64>() * 2. - 1.) * limit)
+            .collect();
+
+        let mut biases = vec![0.; spec.width];
+        if *self == InitScheme::Uniform {
+            for b in biases.iter_mut() {
+                *b = rng.gen::<f64>() * 2. - 1.;
+            }
+        }
+
+        Layer::conv2d(input, spec.width, kernel, weights, biases, spec.activation)
+            .with_bias(spec.use_bias)
+    }
+}
+
+impl fmt::Display for LayerType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LayerType::Dense => write!(f, "dense"),
+            LayerType::Conv2d => write!(f, "conv2d"),
+            LayerType::MaxPool => write!(f, "max pool"),
+            LayerType::Flatten => write!(f, "flatten"),
+        }
+    }
+}
+
+impl fmt::Display for InitScheme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InitScheme::Uniform => write!(f, "uniform"),
+            InitScheme::Xavier => write!(f, "xavier"),
+            InitScheme::He => write!(f, "he"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+    use crate::ai_snake::neural_network::{
+        model::ActionSpace,
+        sensor::{SensorConfig, SensorKind},
+    };
+
+    fn dims(network: &NeuralNetwork) -> Vec<(usize, usize, ActivationFunction)> {
+        network
+            .layers
+            .iter()
+            .map(|layer| (layer.input_dim, layer.output_dim, layer.activation()))
+            .collect()
+    }
+
+    #[test]
+    fn default_is_the_16_16_4_stack() {
+        let network = Architecture::default().build(16, 4, &mut ChaCha8Rng::seed_from_u64(0));
+        assert_eq!(
+            dims(&network),
+            vec![
+                (16, 16, ActivationFunction::Identity),
+                (16, 16, ActivationFunction::Identity),
+                (16, 4, ActivationFunction::Softmax),
+            ]
+        );
+    }
+
+    #[test]
+    fn sizes_follow_the_sensors_and_the_actions() {
+        let mut sensors = SensorConfig::default();
+        sensors.set_enabled(SensorKind::FoodVector, true);
+        let architecture = Architecture {
+            hidden_layers: vec![LayerSpec {
+                width: 8,
+                activation: ActivationFunction::Relu,
+                ..LayerSpec::default()
+            }],
+            ..Architecture::default()
+        };
+        let brain = architecture.new_brain(
+            sensors.input_shape(),
+            ActionSpace::Relative.size(),
+            &NeatConfig::default(),
+            &mut ChaCha8Rng::seed_from_u64(0),
+        );
+        assert_eq!(
+            dims(brain.layered().unwrap()),
+            vec![
+                (18, 8, ActivationFunction::Relu),
+                (8, 3, ActivationFunction::Softmax),
+            ]
+        );
+    }
+
+    #[test]
+    fn scaled_schemes_keep_the_weights_small_and_the_biases_zero() {
+        for init in [InitScheme::Xavier, InitScheme::He] {
+            let architecture = Architecture {
+                init,
+                ..Architecture::default()
+            };
+            let network = architecture.build(16, 4, &mut ChaCha8Rng::seed_from_u64(0));
+            let layer = &network.layers[0];
+            let limit = match init {
+                InitScheme::Xavier => (6. / 32f64).sqrt(),
+                _ => (6. / 16f64).sqrt(),
+            };
+            assert!(layer.weights.iter().all(|w| w.abs() <= limit));
+            assert!(layer.biases().iter().all(|&b| b == 0.));
+        }
+    }
+
+    #[test]
+    fn config_files_keep_the_architecture() {
+        let architecture = Architecture {
+            hidden_layers: vec![LayerSpec {
+                width: 5,
+                activation: ActivationFunction::Tanh,
+                use_bias: false,
+                ..LayerSpec::default()
+            }],
+            init: InitScheme::He,
+            ..Architecture::default()
+        };
+        let json = serde_json::to_string(&architecture).unwrap();
+        let loaded: Architecture = serde_json::from_str(&json).unwrap();
+        let build = |architecture: &Architecture| {
+            architecture.build(16, 4, &mut ChaCha8Rng::seed_from_u64(0))
+        };
+        assert!(build(&loaded) == build(&architecture));
+    }
+}
@@ -0,0 +1,426 @@
+use super::{
+    conv_taps, pool_argmax, sigmoid, ActivationFunction, Layer, LayerKind, NeuralNetwork,
+    ELU_ALPHA, LEAKY_RELU_SLOPE,
+};
+
+/// Values of a forward pass kept to propagate gradients back, the output is not normalized
+#[derive(Clone, Default)]
+pub struct Trace {
+    /// Input of each layer
+    inputs: Vec<Vec<f64>>,
+    /// Weighted sums of each layer, before the activation
+    sums: Vec<Vec<f64>>,
+    output: Vec<f64>,
+}
+
+/// Gradient of a loss with regard to the weights and biases of every layer
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gradients {
+    pub layers: Vec<LayerGradients>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayerGradients {
+    /// Same layout as [`super::Layer::weights`]
+    pub weights: Vec<f64>,
+    pub biases: Vec<f64>,
+}
+
+/// Adam, stochastic gradient descent with per-parameter step sizes
+#[derive(Clone)]
+pub struct Adam {
+    pub learning_rate: f64,
+    pub beta1: f64,
+    pub beta2: f64,
+    pub epsilon: f64,
+    steps: i32,
+    /// Running averages of the gradients and of their squares
+    first: Gradients,
+    second: Gradients,
+}
+
+impl Trace {
+    pub fn output(&self) -> &[f64] {
+        &self.output
+    }
+}
+
+impl ActivationFunction {
+    /// Turns `grad`, the gradient with regard to the `output` of the activation,
+    /// into the gradient with regard to its input `sum`
+    pub fn backward(&self, sum: &[f64], output: &[f64], grad: &mut [f64]) {
+        let derivative = |f: &dyn Fn(f64, f64) -> f64, grad: &mut [f64]| {
+            grad.iter_mut()
+                .zip(sum.iter().zip(output))
+                .for_each(|(g, (&x, &y))| *g *= f(x, y));
+        };
+        match self {
+            ActivationFunction::Relu => derivative(&|x, _| if x > 0. { 1. } else { 0. }, grad),
+            ActivationFunction::Sigmoid => derivative(&|_, y| y * (1. - y), grad),
+            ActivationFunction::Softmax => {
+                // the jacobian is diag(y) - y yᵀ
+                let dot: f64 = grad.iter().zip(output).map(|(g, y)| g * y).sum();
+                grad.iter_mut()
+                    .zip(output)
+                    .for_each(|(g, y)| *g = y * (*g - dot));
+            }
+            ActivationFunction::Identity => (),
+            ActivationFunction::Tanh => derivative(&|_, y| 1. - y * y, grad),
+            ActivationFunction::LeakyRelu => {
+                derivative(&|x, _| if x < 0. { LEAKY_RELU_SLOPE } else { 1. }, grad)
+            }
+            ActivationFunction::Elu => {
+                derivative(&|x, y| if x < 0. { y + ELU_ALPHA } else { 1. }, grad)
+            }
+            ActivationFunction::Swish => derivative(
+                &|x, y| {
+                    let s = sigmoid(x);
+                    s + y * (1. - s)
+                },
+                grad,
+            ),
+            // flat everywhere except at 0, nothing flows through a step
+            ActivationFunction::Step => derivative(&|_, _| 0., grad),
+        }
+    }
+}
+
+impl Gradients {
+    pub fn zeros(network: &NeuralNetwork) -> Self {
+        Gradients {
+            layers: network
+                .layers
+                .iter()
+                .map(|layer| LayerGradients {
+                    weights: vec![0.; layer.weights.len()],
+                    biases: vec![0.; layer.biases.len()],
+                })
+                .collect(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for layer in &mut self.layers {
+            layer.weights.fill(0.);
+            layer.biases.fill(0.);
+        }
+    }
+
+    pub fn scale(&mut self, factor: f64) {
+        for layer in &mut self.layers {
+            layer.weights.iter_mut().for_each(|g| *g *= factor);
+            layer.biases.iter_mut().for_each(|g| *g *= factor);
+        }
+    }
+}
+
+impl NeuralNetwork {
+    /// Forward pass keeping what [`NeuralNetwork::backward`] needs, without normalizing the output
+    pub fn forward_trace(&self, input: &[f64], trace: &mut Trace) {
+        trace.inputs.resize(self.layers.len(), vec![]);
+        trace.sums.resize(self.layers.len(), vec![]);
+        trace.output.clear();
+        trace.output.extend_from_slice(input);
+        for (i, layer) in self.layers.iter().enumerate() {
+            std::mem::swap(&mut trace.inputs[i], &mut trace.output);
+            let sum = &mut trace.sums[i];
+            layer.weighted_sum(&trace.inputs[i], sum);
+            trace.output.clear();
+            trace.output.extend_from_slice(sum);
+            layer.activation.apply(&mut trace.output);
+        }
+    }
+
+    /// Adds to `gradients` the gradient of a loss whose gradient with regard to the output
+    /// of `trace` is `output_grad`
+    pub fn backward(&self, trace: &Trace, output_grad: &[f64], gradients: &mut Gradients) {
+        let mut grad = output_grad.to_vec();
+        for (i, layer) in self.layers.iter().enumerate().rev() {
+            let output = if i + 1 == self.layers.len() {
+                &trace.output
+            } else {
+                &trace.inputs[i + 1]
+            };
+            layer.activation.backward(&trace.sums[i], output, &mut grad);
+
+            let mut input_grad = vec![0.; layer.input_dim];
+            layer.backward_sum(
+                &trace.inputs[i],
+                &grad,
+                &mut gradients.layers[i],
+                &mut input_grad,
+            );
+            grad = input_grad;
+        }
+    }
+}
+
+impl Layer {
+    /// Turns `grad`, the gradient with regard to the weighted sums, into gradients of the
+    /// parameters and of the `input`
+    fn backward_sum(
+        &self,
+        input: &[f64],
+        grad: &[f64],
+        gradients: &mut LayerGradients,
+        input_grad: &mut [f64],
+    ) {
+        match self.kind {
+            LayerKind::Dense => {
+                for (o, &g) in grad.iter().enumerate() {
+                    let row = o * self.input_dim..(o + 1) * self.input_dim;
+                    for ((w_grad, w), (x, x_grad)) in gradients.weights[row.clone()]
+                        .iter_mut()
+                        .zip(&self.weights[row])
+                        .zip(input.iter().zip(input_grad.iter_mut()))
+                    {
+                        *w_grad += g * x;
+                        *x_grad += g * w;
+                    }
+                    if self.use_bias {
+                        gradients.biases[o] += g;
+                    }
+                }
+            }
+            LayerKind::Conv2d {
+                input: shape,
+                kernel,
+                ..
+            } => {
+                let out = self.output_shape();
+                let row_len = self.row_len();
+                let planes = grad.chunks_exact((out.height * out.width).max(1));
+                for (f, plane) in planes.enumerate() {
+                    let offset = f * row_len;
+                    for (y, line) in plane.chunks_exact(out.width.max(1)).enumerate() {
+                        for (x, &g) in line.iter().enumerate() {
+                            for (w, i) in conv_taps(shape, kernel, y, x) {
+                                gradients.weights[offset + w] += g * input[i];
+                                input_grad[i] += g * self.weights[offset + w];
+                            }
+                            if self.use_bias {
+                                gradients.biases[f] += g;
+                            }
+                        }
+                    }
+                }
+            }
+            LayerKind::MaxPool { input: shape, size } => {
+                let out = self.output_shape();
+                for (o, &g) in grad.iter().enumerate() {
+                    let (c, rest) = (o / (out.height * out.width), o % (out.height * out.width));
+                    let (y, x) = (rest / out.width, rest % out.width);
+                    // only the largest value of each block gets a gradient
+                    input_grad[pool_argmax(shape, size, input, (c, y, x))] += g;
+                }
+            }
+            LayerKind::Flatten => input_grad.copy_from_slice(grad),
+        }
+    }
+}
+
+impl Adam {
+    pub fn new(network: &NeuralNetwork, learning_rate: f64) -> Self {
+        Adam {
+            learning_rate,
+            beta1: 0.9,
+            beta2: 0.999,
+            epsilon: 1e-8,
+            steps: 0,
+            first: Gradients::zeros(network),
+            second: Gradients::zeros(network),
+        }
+    }
+
+    /// Moves `network` against `gradients`, disabled biases stay as they are
+    pub fn step(&mut self, network: &mut NeuralNetwork, gradients: &Gradients) {
+        self.steps += 1;
+        let first_correction = 1. - self.beta1.powi(self.steps);
+        let second_correction = 1. - self.beta2.powi(self.steps);
+        let (beta1, beta2) = (self.beta1, self.beta2);
+        let (learning_rate, epsilon) = (self.learning_rate, self.epsilon);
+        let update = |param: &mut f64, grad: f64, first: &mut f64, second: &mut f64| {
+            *first = beta1 * *first + (1. - beta1) * grad;
+            *second = beta2 * *second + (1. - beta2) * grad * grad;
+            let first = *first / first_correction;
+            let second = *second / second_correction;
+            *param -= learning_rate * first / (second.sqrt() + epsilon);
+        };
+        for (i, layer) in network.layers.iter_mut().enumerate() {
+            let (grads, first, second) = (
+                &gradients.layers[i],
+                &mut self.first.layers[i],
+                &mut self.second.layers[i],
+            );
+            for (k, weight) in layer.weights.iter_mut().enumerate() {
+                update(
+                    weight,
+                    grads.weights[k],
+                    &mut first.weights[k],
+                    &mut second.weights[k],
+                );
+            }
+            if layer.use_bias {
+                for (k, bias) in layer.biases.iter_mut().enumerate() {
+                    update(
+                        bias,
+                        grads.biases[k],
+                        &mut first.biases[k],
+                        &mut second.biases[k],
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+    use crate::ai_snake::neural_network::{
+        architecture::{Architecture, LayerSpec, LayerType},
+        Shape,
+    };
+
+    fn network(hidden: ActivationFunction, output: ActivationFunction) -> NeuralNetwork {
+        let architecture = Architecture {
+            hidden_layers: vec![LayerSpec {
+                width: 5,
+                activation: hidden,
+                use_bias: true,
+                ..LayerSpec::default()
+            }],
+            output_activation: output,
+            ..Architecture::default()
+        };
+        architecture.build(3, 4, &mut ChaCha8Rng::seed_from_u64(1))
+    }
+
+    /// Weighted sum of the outputs, so every output gets a different gradient
+    fn loss(network: &NeuralNetwork, input: &[f64]) -> f64 {
+        let mut trace = Trace::default();
+        network.forward_trace(input, &mut trace);
+        trace
+            .output()
+            .iter()
+            .enumerate()
+            .map(|(i, y)| (i as f64 + 1.) * y)
+            .sum()
+    }
+
+    /// Compares the gradients of [`loss`] against central differences
+    fn assert_gradients_match(network: &NeuralNetwork, input: &[f64], name: &str) {
+        let mut trace = Trace::default();
+        network.forward_trace(input, &mut trace);
+        let grad: Vec<f64> = (1..=network.output_dim()).map(|i| i as f64).collect();
+        let mut gradients = Gradients::zeros(network);
+        network.backward(&trace, &grad, &mut gradients);
+
+        let h = 1e-6;
+        for (l, layer) in network.layers.iter().enumerate() {
+            for k in 0..layer.weights.len() + layer.biases.len() {
+                let nudged = |delta: f64| {
+                    let mut network = network.clone();
+                    let layer = &mut network.layers[l];
+                    if k < layer.weights.len() {
+                        layer.weights[k] += delta;
+                    } else {
+                        layer.biases[k - layer.weights.len()] += delta;
+                    }
+                    loss(&network, input)
+                };
+                let numerical = (nudged(h) - nudged(-h)) / (2. * h);
+                let grads = &gradients.layers[l];
+                let analytical = if k < grads.weights.len() {
+                    grads.weights[k]
+                } else {
+                    grads.biases[k - grads.weights.len()]
+                };
+                assert!(
+                    (numerical - analytical).abs() < 1e-5,
+                    "{name}: {numerical} != {analytical}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn gradients_match_finite_differences() {
+        for activation in ActivationFunction::ALL {
+            if activation == ActivationFunction::Step {
+                continue;
+            }
+            let network = network(activation, activation);
+            assert_gradients_match(&network, &[0.3, -0.7, 0.9], &activation.to_string());
+        }
+    }
+
+    #[test]
+    fn convolution_gradients_match_finite_differences() {
+        let spec = |kind, width, kernel| LayerSpec {
+            width,
+            activation: ActivationFunction::Tanh,
+            kind,
+            kernel,
+            ..LayerSpec::default()
+        };
+        let architecture = Architecture {
+            hidden_layers: vec![
+                spec(LayerType::Conv2d, 3, 3),
+                spec(LayerType::MaxPool, 0, 2),
+                spec(LayerType::Flatten, 0, 0),
+            ],
+            output_activation: ActivationFunction::Tanh,
+            ..Architecture::default()
+        };
+        let input = Shape {
+            channels: 2,
+            height: 4,
+            width: 4,
+        };
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let network = architecture.build_from(input, 4, &mut rng);
+        let values: Vec<f64> = (0..input.len())
+            .map(|_| rng.gen::<f64>() * 2. - 1.)
+            .collect();
+        assert_gradients_match(&network, &values, "conv2d");
+    }
+
+    #[test]
+    fn trace_matches_forward_before_normalizing() {
+        let network = network(ActivationFunction::Tanh, ActivationFunction::Relu);
+        let mut trace = Trace::default();
+        network.forward_trace(&[0.1, 0.2, 0.3], &mut trace);
+        let sum: f64 = trace.output().iter().map(|y| y.abs()).sum();
+        let normalized: Vec<f64> = trace.output().iter().map(|y| y / sum).collect();
+        assert_eq!(network.forward(vec![0.1, 0.2, 0.3]), normalized);
+    }
+
+    #[test]
+    fn adam_fits_a_target() {
+        let mut network = network(ActivationFunction::Tanh, ActivationFunction::Identity);
+        let mut adam = Adam::new(&network, 0.01);
+        let mut trace = Trace::default();
+        let mut gradients = Gradients::zeros(&network);
+        let (input, target) = ([0.5, -0.5, 0.2], [1., -1., 0.5, 0.]);
+        for _ in 0..500 {
+            network.forward_trace(&input, &mut trace);
+            let grad: Vec<f64> = trace
+                .output()
+                .iter()
+                .zip(&target)
+                .map(|(y, t)| y - t)
+                .collect();
+            gradients.clear();
+            network.backward(&trace, &grad, &mut gradients);
+            adam.step(&mut network, &gradients);
+        }
+        network.forward_trace(&input, &mut trace);
+        for (y, t) in trace.output().iter().zip(&target) {
+            assert!((y - t).abs() < 1e-3, "{y} != {t}");
+        }
+    }
+}
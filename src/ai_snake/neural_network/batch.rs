@@ -0,0 +1,327 @@
+use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub};
+
+use super::{normalize, ActivationFunction, LayerKind, NeuralNetwork};
+
+/// Floating point type a forward pass can run in
+pub trait Scalar:
+    Copy
+    + PartialOrd
+    + Send
+    + Sync
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + Neg<Output = Self>
+    + AddAssign
+    + MulAssign
+    + DivAssign
+{
+    const ZERO: Self;
+    const ONE: Self;
+    const NEG_INFINITY: Self;
+
+    fn from_f64(x: f64) -> Self;
+    fn to_f64(self) -> f64;
+    fn exp(self) -> Self;
+    fn exp_m1(self) -> Self;
+    fn tanh(self) -> Self;
+    fn abs(self) -> Self;
+    fn max(self, other: Self) -> Self;
+}
+
+macro_rules! impl_scalar {
+    ($t:ident) => {
+        impl Scalar for $t {
+            const ZERO: Self = 0.0;
+            const ONE: Self = 1.0;
+            const NEG_INFINITY: Self = $t::NEG_INFINITY;
+
+            fn from_f64(x: f64) -> Self {
+                x as $t
+            }
+            fn to_f64(self) -> f64 {
+                self as f64
+            }
+            fn exp(self) -> Self {
+                $t::exp(self)
+            }
+            fn exp_m1(self) -> Self {
+                $t::exp_m1(self)
+            }
+            fn tanh(self) -> Self {
+                $t::tanh(self)
+            }
+            fn abs(self) -> Self {
+                $t::abs(self)
+            }
+            fn max(self, other: Self) -> Self {
+                $t::max(self, other)
+            }
+        }
+    };
+}
+
+impl_scalar!(f32);
+impl_scalar!(f64);
+
+/// Networks sharing a topology packed side by side, to evaluate a whole population in one call
+pub struct BatchedNetwork<T: Scalar = f64> {
+    layers: Vec<BatchedLayer<T>>,
+    networks: usize,
+    current: Vec<T>,
+    next: Vec<T>,
+}
+
+struct BatchedLayer<T> {
+    input_dim: usize,
+    output_dim: usize,
+    /// `[network][output][input]`, each network keeps the row-major layout of `Layer`
+    weights: Vec<T>,
+    /// `[network][output]`, zero for networks with disabled biases
+    biases: Vec<T>,
+    activation: ActivationFunction,
+}
+
+impl<T: Scalar> BatchedNetwork<T> {
+    /// `None` when the networks differ in layer sizes or activations, or are not fully dense
+    pub fn new<'a>(networks: impl IntoIterator<Item = &'a NeuralNetwork>) -> Option<Self> {
+        let mut networks = networks.into_iter().peekable();
+        let first = networks.peek()?;
+        let mut batch = BatchedNetwork {
+            layers: first
+                .layers
+                .iter()
+                .map(|layer| BatchedLayer {
+                    input_dim: layer.input_dim,
+                    output_dim: layer.output_dim,
+                    weights: vec![],
+                    biases: vec![],
+                    activation: layer.activation,
+                })
+                .collect(),
+            networks: 0,
+            current: vec![],
+            next: vec![],
+        };
+        for network in networks {
+            if !batch.fits(network) {
+                return None;
+            }
+            for (packed, layer) in batch.layers.iter_mut().zip(&network.layers) {
+                packed
+                    .weights
+                    .extend(layer.weights.iter().map(|&w| T::from_f64(w)));
+                packed.biases.extend(layer.biases.iter().map(|&b| {
+                    if layer.use_bias {
+                        T::from_f64(b)
+                    } else {
+                        T::ZERO
+                    }
+                }));
+            }
+            batch.networks += 1;
+        }
+        Some(batch)
+    }
+
+    pub fn len(&self) -> usize {
+        self.networks
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.networks == 0
+    }
+
+    pub fn input_dim(&self) -> usize {
+        self.layers.first().map_or(0, |layer| layer.input_dim)
+    }
+
+    pub fn output_dim(&self) -> usize {
+        self.layers.last().map_or(0, |layer| layer.output_dim)
+    }
+
+    /// Replaces the weights of network `index`, returns false if its topology does not fit
+    pub fn update(&mut self, index: usize, network: &NeuralNetwork) -> bool {
+        if index >= self.networks || !self.fits(network) {
+            return false;
+        }
+        for (packed, layer) in self.layers.iter_mut().zip(&network.layers) {
+            let size = packed.input_dim * packed.output_dim;
+            for (p, &w) in packed.weights[index * size..(index + 1) * size]
+                .iter_mut()
+                .zip(&layer.weights)
+            {
+                *p = T::from_f64(w);
+            }
+            for (p, &b) in packed.biases[index * packed.output_dim..(index + 1) * packed.output_dim]
+                .iter_mut()
+                .zip(&layer.biases)
+            {
+                *p = if layer.use_bias {
+                    T::from_f64(b)
+                } else {
+                    T::ZERO
+                };
+            }
+        }
+        true
+    }
+
+    /// Runs network `i` on `inputs[i * input_dim..(i + 1) * input_dim]`, outputs are laid out the same way
+    pub fn forward(&mut self, inputs: &[T]) -> &[T] {
+        assert_eq!(
+            inputs.len(),
+            self.networks * self.input_dim(),
+            "expected one input per network"
+        );
+        self.current.clear();
+        self.current.extend_from_slice(inputs);
+
+        for layer in &self.layers {
+            self.next.clear();
+            self.next.resize(self.networks * layer.output_dim, T::ZERO);
+            let size = layer.input_dim * layer.output_dim;
+            for n in 0..self.networks {
+                let input = &self.current[n * layer.input_dim..(n + 1) * layer.input_dim];
+                let weights = &layer.weights[n * size..(n + 1) * size];
+                let output = &mut self.next[n * layer.output_dim..(n + 1) * layer.output_dim];
+                for (o, out) in output.iter_mut().enumerate() {
+                    let row = &weights[o * layer.input_dim..(o + 1) * layer.input_dim];
+                    *out = row
+                        .iter()
+                        .zip(input)
+                        .fold(layer.biases[n * layer.output_dim + o], |acc, (&w, &x)| {
+                            acc + x * w
+                        });
+                }
+                layer.activation.apply(output);
+            }
+            std::mem::swap(&mut self.current, &mut self.next);
+        }
+
+        let output_dim = self.output_dim();
+        if output_dim > 0 {
+            self.current.chunks_mut(output_dim).for_each(normalize);
+        }
+        &self.current
+    }
+
+    fn fits(&self, network: &NeuralNetwork) -> bool {
+        network.layers.len() == self.layers.len()
+            && self
+                .layers
+                .iter()
+                .zip(&network.layers)
+                .all(|(packed, layer)| {
+                    layer.kind == LayerKind::Dense
+                        && packed.input_dim == layer.input_dim
+                        && packed.output_dim == layer.output_dim
+                        && packed.activation == layer.activation
+                })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+    use crate::ai_snake::neural_network::{
+        architecture::{Architecture, LayerSpec},
+        Scratch,
+    };
+
+    fn population(count: usize) -> Vec<NeuralNetwork> {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        (0..count)
+            .map(|_| Architecture::default().build(16, 4, &mut rng))
+            .collect()
+    }
+
+    fn inputs(count: usize) -> Vec<f64> {
+        (0..count * 16).map(|i| (i % 7) as f64 / 7.).collect()
+    }
+
+    #[test]
+    fn matches_network_forward() {
+        let networks = population(5);
+        let inputs = inputs(5);
+        let mut batch = BatchedNetwork::<f64>::new(&networks).unwrap();
+        let outputs = batch.forward(&inputs).to_vec();
+
+        for (n, network) in networks.iter().enumerate() {
+            let expected = network.forward(inputs[n * 16..(n + 1) * 16].to_vec());
+            assert_eq!(&outputs[n * 4..(n + 1) * 4], expected.as_slice());
+        }
+    }
+
+    #[test]
+    fn matches_forward_with_for_each_member() {
+        let architecture = Architecture {
+            hidden_layers: vec![
+                LayerSpec {
+                    activation: ActivationFunction::Tanh,
+                    ..LayerSpec::default()
+                },
+                LayerSpec {
+                    width: 8,
+                    activation: ActivationFunction::LeakyRelu,
+                    use_bias: false,
+                    ..LayerSpec::default()
+                },
+            ],
+            ..Architecture::default()
+        };
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let networks: Vec<NeuralNetwork> = (0..6)
+            .map(|_| architecture.build(16, 4, &mut rng))
+            .collect();
+        let inputs = inputs(6);
+        let mut batch = BatchedNetwork::<f64>::new(&networks).unwrap();
+        let outputs = batch.forward(&inputs).to_vec();
+
+        let mut scratch = Scratch::default();
+        for (n, network) in networks.iter().enumerate() {
+            let expected = network.forward_with(&inputs[n * 16..(n + 1) * 16], &mut scratch);
+            assert_eq!(&outputs[n * 4..(n + 1) * 4], &expected[..]);
+        }
+    }
+
+    #[test]
+    fn f32_is_close_to_f64() {
+        let networks = population(5);
+        let inputs = inputs(5);
+        let expected = BatchedNetwork::<f64>::new(&networks)
+            .unwrap()
+            .forward(&inputs)
+            .to_vec();
+        let inputs: Vec<f32> = inputs.iter().map(|&x| x as f32).collect();
+        let mut batch = BatchedNetwork::<f32>::new(&networks).unwrap();
+
+        for (o, e) in batch.forward(&inputs).iter().zip(&expected) {
+            assert!((o.to_f64() - e).abs() < 1e-4, "{o} != {e}");
+        }
+    }
+
+    #[test]
+    fn update_replaces_one_network() {
+        let networks = population(3);
+        let inputs = inputs(3);
+        let mut batch = BatchedNetwork::<f64>::new(&networks).unwrap();
+        assert!(batch.update(1, &networks[0]));
+
+        let outputs = batch.forward(&inputs);
+        let expected = networks[0].forward(inputs[16..32].to_vec());
+        assert_eq!(&outputs[4..8], expected.as_slice());
+    }
+
+    #[test]
+    fn rejects_other_topologies() {
+        let mut networks = population(2);
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        networks.push(Architecture::default().build(16, 3, &mut rng));
+        assert!(BatchedNetwork::<f64>::new(&networks).is_none());
+    }
+}
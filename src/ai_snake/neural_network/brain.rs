@@ -0,0 +1,136 @@
+use std::{fmt, str::FromStr};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    mutation::Mutation,
+    neat::{Innovations, NeatConfig, NeatGenome},
+    NeuralNetwork, Scratch,
+};
+
+/// Brain of a snake, either a fixed stack of layers or an evolving NEAT genome
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub enum Brain {
+    Layered(NeuralNetwork),
+    Neat(NeatGenome),
+}
+
+/// Which kind of brain a run evolves
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum BrainKind {
+    #[default]
+    Layered,
+    Neat,
+}
+
+impl BrainKind {
+    pub const ALL: [BrainKind; 2] = [BrainKind::Layered, BrainKind::Neat];
+}
+
+impl Brain {
+    pub fn kind(&self) -> BrainKind {
+        match self {
+            Brain::Layered(_) => BrainKind::Layered,
+            Brain::Neat(_) => BrainKind::Neat,
+        }
+    }
+
+    pub fn input_dim(&self) -> usize {
+        match self {
+            Brain::Layered(network) => network.input_dim(),
+            Brain::Neat(genome) => genome.inputs,
+        }
+    }
+
+    pub fn output_dim(&self) -> usize {
+        match self {
+            Brain::Layered(network) => network.output_dim(),
+            Brain::Neat(genome) => genome.outputs,
+        }
+    }
+
+    pub fn forward(&self, input: Vec<f64>) -> Vec<f64> {
+        self.forward_with(&input, &mut Scratch::default()).to_vec()
+    }
+
+    pub fn forward_with<'a>(&self, input: &[f64], scratch: &'a mut Scratch) -> &'a mut [f64] {
+        match self {
+            Brain::Layered(network) => network.forward_with(input, scratch),
+            Brain::Neat(genome) => genome.forward_with(input, scratch),
+        }
+    }
+
+    /// NEAT genomes may also grow, recording new genes in `innovations`
+    pub fn mutate(
+        &mut self,
+        mutation: &Mutation,
+        neat: &NeatConfig,
+        innovations: &mut Innovations,
+        rng: &mut impl Rng,
+    ) {
+        match self {
+            Brain::Layered(network) => network.mutate(mutation, rng),
+            Brain::Neat(genome) => genome.mutate(mutation, neat, innovations, rng),
+        }
+    }
+
+    /// Root mean square difference of the genes of layered networks, compatibility of
+    /// NEAT genomes. Brains that cannot be compared are infinitely far apart
+    pub fn distance(&self, other: &Brain, neat: &NeatConfig) -> f64 {
+        match (self, other) {
+            (Brain::Layered(a), Brain::Layered(b)) => {
+                let count = a.genes().count();
+                if count == 0 || count != b.genes().count() {
+                    return f64::INFINITY;
+                }
+                let sum: f64 = a.genes().zip(b.genes()).map(|(x, y)| (x - y).powi(2)).sum();
+                (sum / count as f64).sqrt()
+            }
+            (Brain::Neat(a), Brain::Neat(b)) => a.compatibility(b, neat),
+            _ => f64::INFINITY,
+        }
+    }
+
+    pub fn layered(&self) -> Option<&NeuralNetwork> {
+        match self {
+            Brain::Layered(network) => Some(network),
+            Brain::Neat(_) => None,
+        }
+    }
+}
+
+impl From<NeuralNetwork> for Brain {
+    fn from(network: NeuralNetwork) -> Self {
+        Brain::Layered(network)
+    }
+}
+
+impl fmt::Display for Brain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Brain::Layered(network) => write!(f, "{network}"),
+            Brain::Neat(genome) => write!(f, "{genome}"),
+        }
+    }
+}
+
+impl fmt::Display for BrainKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BrainKind::Layered => write!(f, "layered"),
+            BrainKind::Neat => write!(f, "neat"),
+        }
+    }
+}
+
+impl FromStr for BrainKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        BrainKind::ALL
+            .into_iter()
+            .find(|kind| kind.to_string() == s)
+            .ok_or(())
+    }
+}
@@ -0,0 +1,162 @@
+use std::{fmt, str::FromStr};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use super::NeuralNetwork;
+
+/// How the brain of an offspring is built from its parents
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Crossover {
+    /// Every survivor is averaged into one brain given to the whole population
+    MergeAll,
+    /// Each gene comes from either parent with the same probability
+    #[default]
+    Uniform,
+    /// Genes before a random cut come from the first parent, the others from the second
+    SinglePoint,
+    /// Each layer is taken whole from either parent
+    PerLayer,
+    /// Weighted average of both parents, with a random weight per offspring
+    Blend,
+}
+
+impl Crossover {
+    pub const ALL: [Crossover; 5] = [
+        Crossover::MergeAll,
+        Crossover::Uniform,
+        Crossover::SinglePoint,
+        Crossover::PerLayer,
+        Crossover::Blend,
+    ];
+
+    /// Combines two parents sharing a topology, `MergeAll` is handled by `GeneticModel`
+    pub fn cross(
+        &self,
+        first: &NeuralNetwork,
+        second: &NeuralNetwork,
+        rng: &mut impl Rng,
+    ) -> NeuralNetwork {
+        let mut child = first.clone();
+        match self {
+            Crossover::MergeAll => (),
+            Crossover::Uniform => {
+                for (gene, &other) in child.genes_mut().zip(second.genes()) {
+                    if rng.gen_bool(0.5) {
+                        *gene = other;
+                    }
+                }
+            }
+            Crossover::SinglePoint => {
+                let cut = rng.gen_range(0..=child.genes().count());
+                for (gene, &other) in child.genes_mut().zip(second.genes()).skip(cut) {
+                    *gene = other;
+                }
+            }
+            Crossover::PerLayer => {
+                for (layer, other) in child.layers.iter_mut().zip(&second.layers) {
+                    if rng.gen_bool(0.5) {
+                        *layer = other.clone();
+                    }
+                }
+            }
+            Crossover::Blend => {
+                let alpha = rng.gen::<f64>();
+                for (gene, &other) in child.genes_mut().zip(second.genes()) {
+                    *gene = alpha * *gene + (1. - alpha) * other;
+                }
+                if let (Some(sigma), Some(other)) = (first.sigma, second.sigma) {
+                    child.sigma = Some(alpha * sigma + (1. - alpha) * other);
+                }
+            }
+        }
+        child
+    }
+}
+
+impl fmt::Display for Crossover {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Crossover::MergeAll => write!(f, "merge all"),
+            Crossover::Uniform => write!(f, "uniform"),
+            Crossover::SinglePoint => write!(f, "single point"),
+            Crossover::PerLayer => write!(f, "per layer"),
+            Crossover::Blend => write!(f, "blend"),
+        }
+    }
+}
+
+/// Parses the displayed name, with dashes instead of spaces
+impl FromStr for Crossover {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        Crossover::ALL
+            .into_iter()
+            .find(|crossover| crossover.to_string().replace(' ', "-") == s)
+            .ok_or(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+    use crate::ai_snake::neural_network::{ActivationFunction, Layer};
+
+    fn constant(value: f64) -> NeuralNetwork {
+        let mut network = NeuralNetwork::new();
+        for _ in 0..3 {
+            network.add_layer(Layer::new(
+                2,
+                2,
+                vec![value; 4],
+                vec![value; 2],
+                ActivationFunction::Identity,
+            ));
+        }
+        network
+    }
+
+    #[test]
+    fn genes_come_from_the_parents() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        for crossover in [
+            Crossover::Uniform,
+            Crossover::SinglePoint,
+            Crossover::PerLayer,
+        ] {
+            let child = crossover.cross(&constant(0.), &constant(1.), &mut rng);
+            assert!(child.genes().all(|&g| g == 0. || g == 1.));
+        }
+    }
+
+    #[test]
+    fn single_point_keeps_genes_in_order() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        for _ in 0..10 {
+            let child = Crossover::SinglePoint.cross(&constant(0.), &constant(1.), &mut rng);
+            let genes: Vec<f64> = child.genes().copied().collect();
+            assert!(genes.windows(2).all(|w| w[0] <= w[1]));
+        }
+    }
+
+    #[test]
+    fn blend_stays_between_the_parents() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let child = Crossover::Blend.cross(&constant(-1.), &constant(1.), &mut rng);
+        let first = *child.genes().next().unwrap();
+        assert!((-1. ..=1.).contains(&first));
+        assert!(child.genes().all(|&g| g == first));
+    }
+
+    #[test]
+    fn parses_displayed_names() {
+        for crossover in Crossover::ALL {
+            let name = crossover.to_string().replace(' ', "-");
+            assert_eq!(name.parse(), Ok(crossover));
+        }
+    }
+}
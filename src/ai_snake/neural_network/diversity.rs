@@ -0,0 +1,198 @@
+use std::collections::HashSet;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use super::{model::Model, neat::NeatConfig, NeuralNetwork, Scratch};
+
+/// Inputs every brain is asked about to compare behaviours
+const PROBES: usize = 64;
+
+/// Most pairs of NEAT genomes compared each generation, larger populations are sampled
+const DISTANCE_PAIRS: usize = 1000;
+
+/// How spread out a population is, measured before it breeds
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct Diversity {
+    /// Root mean square of the distance between two brains of the population, estimated
+    /// from a sample of pairs in large NEAT populations
+    pub mean_distance: f64,
+    pub species: usize,
+    /// Distinct sequences of moves picked on the same probe inputs
+    pub unique_behaviours: usize,
+}
+
+impl Diversity {
+    /// Species are counted while breeding, they are left at 0
+    pub fn measure(population: &[Model], neat: &NeatConfig) -> Self {
+        Diversity {
+            mean_distance: mean_distance(population, neat),
+            species: 0,
+            unique_behaviours: unique_behaviours(population),
+        }
+    }
+}
+
+fn mean_distance(population: &[Model], neat: &NeatConfig) -> f64 {
+    let n = population.len();
+    if n < 2 {
+        return 0.;
+    }
+    let networks: Option<Vec<&NeuralNetwork>> = population
+        .iter()
+        .map(|model| model.brain.layered())
+        .collect();
+    if let Some(networks) = networks {
+        let genes = networks[0].genes().count();
+        if genes > 0
+            && networks
+                .iter()
+                .all(|network| network.genes().count() == genes)
+        {
+            return layered_mean_distance(&networks, genes);
+        }
+    }
+    // NEAT genomes do not line up, every pair is compared in small populations only
+    let pairs: Vec<(usize, usize)> = if n * (n - 1) / 2 <= DISTANCE_PAIRS {
+        (0..n)
+            .flat_map(|i| (i + 1..n).map(move |j| (i, j)))
+            .collect()
+    } else {
+        // the same pairs every generation, so the estimate does not jitter on its own
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        (0..DISTANCE_PAIRS)
+            .map(|_| {
+                let i = rng.gen_range(0..n);
+                (i, (i + rng.gen_range(1..n)) % n)
+            })
+            .collect()
+    };
+    let (mut sum, mut compared) = (0., 0);
+    for (i, j) in pairs {
+        let distance = population[i].brain.distance(&population[j].brain, neat);
+        if distance.is_finite() {
+            sum += distance * distance;
+            compared += 1;
+        }
+    }
+    if compared == 0 {
+        0.
+    } else {
+        (sum / compared as f64).sqrt()
+    }
+}
+
+/// Squared differences summed over all pairs are `n²` times the variance of each gene,
+/// which avoids comparing every pair
+fn layered_mean_distance(networks: &[&NeuralNetwork], genes: usize) -> f64 {
+    let n = networks.len() as f64;
+    let mut sums = vec![0.; genes];
+    let mut squares = vec![0.; genes];
+    for network in networks {
+        for (k, &gene) in network.genes().enumerate() {
+            sums[k] += gene;
+            squares[k] += gene * gene;
+        }
+    }
+    let variance: f64 = sums
+        .iter()
+        .zip(&squares)
+        .map(|(sum, square)| (square / n - (sum / n).powi(2)).max(0.))
+        .sum();
+    (2. * n / (n - 1.) * variance / genes as f64).sqrt()
+}
+
+fn unique_behaviours(population: &[Model]) -> usize {
+    // inputs uniform in [0, 1], the range of most sensors
+    let mut rng = ChaCha8Rng::seed_from_u64(0);
+    let input_size = population
+        .first()
+        .map_or(0, |model| model.brain.input_dim());
+    let probes: Vec<Vec<f64>> = (0..PROBES)
+        .map(|_| (0..input_size).map(|_| rng.gen()).collect())
+        .collect();
+    let mut scratch = Scratch::default();
+    let behaviours: HashSet<Vec<usize>> = population
+        .iter()
+        .map(|model| {
+            probes
+                .iter()
+                .map(|probe| {
+                    let output = model.brain.forward_with(probe, &mut scratch);
+                    (0..output.len())
+                        .max_by(|&a, &b| output[a].total_cmp(&output[b]))
+                        .unwrap_or(0)
+                })
+                .collect()
+        })
+        .collect();
+    behaviours.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai_snake::neural_network::{
+        brain::Brain, neat::NeatGenome, testing::population, ActivationFunction,
+    };
+
+    /// Root mean square of the distance over every pair
+    fn every_pair(population: &[Model], neat: &NeatConfig) -> f64 {
+        let mut sum = 0.;
+        let mut pairs = 0;
+        for i in 0..population.len() {
+            for j in i + 1..population.len() {
+                sum += population[i]
+                    .brain
+                    .distance(&population[j].brain, neat)
+                    .powi(2);
+                pairs += 1;
+            }
+        }
+        (sum / pairs as f64).sqrt()
+    }
+
+    #[test]
+    fn variance_matches_every_pair() {
+        let population = population(&[0; 6]);
+        let neat = NeatConfig::default();
+        let expected = every_pair(&population, &neat);
+        assert!((mean_distance(&population, &neat) - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn large_neat_populations_are_sampled() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let mut population = population(&[0; 60]);
+        for model in &mut population {
+            model.brain = Brain::Neat(NeatGenome::minimal(
+                16,
+                4,
+                ActivationFunction::Relu,
+                ActivationFunction::Softmax,
+                &mut rng,
+            ));
+        }
+        let neat = NeatConfig::default();
+        let expected = every_pair(&population, &neat);
+        // 1770 pairs, more than are compared
+        let estimate = mean_distance(&population, &neat);
+        assert!((estimate - expected).abs() < 0.05 * expected);
+        // 780 pairs, all compared
+        let small = &population[..40];
+        assert!((mean_distance(small, &neat) - every_pair(small, &neat)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn clones_are_not_diverse() {
+        let mut population = population(&[0; 4]);
+        let brain = population[0].brain.clone();
+        for model in &mut population {
+            model.brain = brain.clone();
+        }
+        let diversity = Diversity::measure(&population, &NeatConfig::default());
+        assert!(diversity.mean_distance < 1e-6);
+        assert_eq!(diversity.unique_behaviours, 1);
+    }
+}
@@ -0,0 +1,394 @@
+use rand::{seq::index, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::ai_snake::ui::AppConfig;
+
+use super::{
+    architecture::Architecture,
+    backprop::{Adam, Gradients, Trace},
+    brain::Brain,
+    model::{ActionSpace, Model},
+    ActivationFunction, NeuralNetwork, Shape,
+};
+
+/// Reward of eating, dying costs as much and every other move is free
+const FOOD_REWARD: f64 = 1.;
+
+/// Settings of the deep Q-learning trainer
+#[derive(Clone, Debug, PartialEq)]
+pub struct DqnConfig {
+    pub episodes: usize,
+    /// Snakes played at the same time, each on its own grid
+    pub environments: usize,
+    /// Transitions per gradient step
+    pub batch_size: usize,
+    /// Most recent transitions kept in the replay buffer
+    pub replay_capacity: usize,
+    /// Discount of future rewards
+    pub gamma: f64,
+    pub learning_rate: f64,
+    pub epsilon_start: f64,
+    pub epsilon_end: f64,
+    /// Fraction of the episodes over which epsilon decays linearly
+    pub exploration: f64,
+    /// Gradient steps between two copies of the network into the target network
+    pub target_sync: usize,
+    /// Episodes summed up in each report
+    pub report_every: usize,
+}
+
+/// Move played from `state`, `next` is `None` when the snake died
+struct Transition {
+    state: Vec<f64>,
+    action: usize,
+    reward: f64,
+    next: Option<(Vec<f64>, Option<usize>)>,
+}
+
+/// Keeps the most recent transitions, the oldest is overwritten when full
+struct ReplayBuffer {
+    capacity: usize,
+    transitions: Vec<Transition>,
+    oldest: usize,
+}
+
+/// Scores of the episodes played since the previous report
+#[derive(Clone, Debug)]
+pub struct DqnReport {
+    pub episodes: usize,
+    pub mean_score: f64,
+    pub best_score: u32,
+    pub epsilon: f64,
+    /// Mean Huber loss of the gradient steps
+    pub loss: f64,
+}
+
+/// Deep Q-network: one network estimates the value of each action, learning from
+/// replayed transitions against a target network copied every `target_sync` steps
+pub struct Dqn {
+    pub network: NeuralNetwork,
+    target: NeuralNetwork,
+    actions: ActionSpace,
+    adam: Adam,
+    replay: ReplayBuffer,
+    config: DqnConfig,
+    rng: ChaCha8Rng,
+    learn_steps: usize,
+    trace: Trace,
+    gradients: Gradients,
+}
+
+impl Default for DqnConfig {
+    fn default() -> Self {
+        DqnConfig {
+            episodes: 2000,
+            environments: 16,
+            batch_size: 32,
+            replay_capacity: 50_000,
+            gamma: 0.95,
+            learning_rate: 0.001,
+            epsilon_start: 1.,
+            epsilon_end: 0.05,
+            exploration: 0.5,
+            target_sync: 500,
+            report_every: 100,
+        }
+    }
+}
+
+impl ReplayBuffer {
+    fn new(capacity: usize) -> Self {
+        ReplayBuffer {
+            capacity: capacity.max(1),
+            transitions: vec![],
+            oldest: 0,
+        }
+    }
+
+    fn push(&mut self, transition: Transition) {
+        if self.transitions.len() < self.capacity {
+            self.transitions.push(transition);
+        } else {
+            self.transitions[self.oldest] = transition;
+            self.oldest = (self.oldest + 1) % self.capacity;
+        }
+    }
+}
+
+impl Dqn {
+    /// The architecture is kept except for the output activation: Q-values are unbounded
+    pub fn new(
+        architecture: &Architecture,
+        input: Shape,
+        actions: ActionSpace,
+        config: DqnConfig,
+        seed: u64,
+    ) -> Self {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let architecture = Architecture {
+            output_activation: ActivationFunction::Identity,
+            ..architecture.clone()
+        };
+        let network = architecture.build_from(input, actions.size(), &mut rng);
+        Dqn {
+            target: network.clone(),
+            actions,
+            adam: Adam::new(&network, config.learning_rate),
+            replay: ReplayBuffer::new(config.replay_capacity),
+            gradients: Gradients::zeros(&network),
+            trace: Trace::default(),
+            network,
+            config,
+            rng,
+            learn_steps: 0,
+        }
+    }
+
+    /// Linear decay from `epsilon_start` to `epsilon_end` once `episodes` are played
+    pub fn epsilon(&self, episodes: usize) -> f64 {
+        let decay = self.config.episodes as f64 * self.config.exploration;
+        let progress = if decay > 0. {
+            (episodes as f64 / decay).min(1.)
+        } else {
+            1.
+        };
+        self.config.epsilon_start + (self.config.epsilon_end - self.config.epsilon_start) * progress
+    }
+
+    /// Action of highest value, never `reverse`
+    pub fn greedy(&mut self, state: &[f64], reverse: Option<usize>) -> usize {
+        self.network.forward_trace(state, &mut self.trace);
+        best_action(self.trace.output(), reverse).0
+    }
+
+    fn explore(&mut self, state: &[f64], reverse: Option<usize>, epsilon: f64) -> usize {
+        if self.rng.gen::<f64>() < epsilon {
+            let actions: Vec<usize> = (0..self.actions.size())
+                .filter(|&action| Some(action) != reverse)
+                .collect();
+            actions[self.rng.gen_range(0..actions.len())]
+        } else {
+            self.greedy(state, reverse)
+        }
+    }
+
+    /// One gradient step on a batch drawn from the replay buffer, returns the mean loss
+    fn learn(&mut self) -> Option<f64> {
+        let batch_size = self.config.batch_size.max(1);
+        if self.replay.transitions.len() < batch_size {
+            return None;
+        }
+        let batch = index::sample(&mut self.rng, self.replay.transitions.len(), batch_size);
+        self.gradients.clear();
+        let mut loss = 0.;
+        for i in batch {
+            let transition = &self.replay.transitions[i];
+            let mut target = transition.reward;
+            if let Some((next, reverse)) = &transition.next {
+                self.target.forward_trace(next, &mut self.trace);
+                target += self.config.gamma * best_action(self.trace.output(), *reverse).1;
+            }
+            self.network
+                .forward_trace(&transition.state, &mut self.trace);
+            let error = self.trace.output()[transition.action] - target;
+            // Huber loss, its gradient is the error clipped to ±1
+            loss += if error.abs() <= 1. {
+                error * error / 2.
+            } else {
+                error.abs() - 0.5
+            };
+            let mut output_grad = vec![0.; self.actions.size()];
+            output_grad[transition.action] = error.clamp(-1., 1.);
+            self.network
+                .backward(&self.trace, &output_grad, &mut self.gradients);
+        }
+        self.gradients.scale(1. / batch_size as f64);
+        self.adam.step(&mut self.network, &self.gradients);
+
+        self.learn_steps += 1;
+        if self
+            .learn_steps
+            .is_multiple_of(self.config.target_sync.max(1))
+        {
+            self.target = self.network.clone();
+        }
+        Some(loss / batch_size as f64)
+    }
+
+    /// Plays `episodes` on grids set up like the ones of `app_config`, learning after every move
+    pub fn train(&mut self, app_config: &AppConfig, mut on_report: impl FnMut(&DqnReport)) {
+        let size = app_config.grid_size;
+        let sensors = app_config.sensors.build(app_config.vision_range);
+        let mut environments: Vec<Model> = (0..self.config.environments.max(1))
+            .map(|id| {
+                let brain = Brain::Layered(NeuralNetwork::new());
+                let rng = Model::rng(app_config.seed, id);
+                let mut model = Model::new(size, size, app_config.allowed_moves, id, brain, rng);
+                model.actions = self.actions;
+                model.reset(app_config.allowed_moves, app_config.food_amount);
+                model
+            })
+            .collect();
+
+        let mut episodes = 0;
+        let mut scores = vec![];
+        let (mut loss, mut learn_steps) = (0., 0);
+        while episodes < self.config.episodes {
+            let epsilon = self.epsilon(episodes);
+            for model in &mut environments {
+                let Some(state) = model.compute_input(&sensors) else {
+                    continue;
+                };
+                let reverse = model.reverse_action();
+                let action = self.explore(&state, reverse, epsilon);
+                let score = model.score;
+                model.act(action);
+
+                let alive = model.universe.get_snake(0).is_some();
+                let reward = if !alive {
+                    -FOOD_REWARD
+                } else if model.score > score {
+                    FOOD_REWARD
+                } else {
+                    0.
+                };
+                // running out of moves ends the episode, but the state still has a value
+                let next = model
+                    .compute_input(&sensors)
+                    .map(|next| (next, model.reverse_action()));
+                self.replay.push(Transition {
+                    state,
+                    action,
+                    reward,
+                    next,
+                });
+                if let Some(step_loss) = self.learn() {
+                    loss += step_loss;
+                    learn_steps += 1;
+                }
+
+                if !alive || model.moves_left == 0 {
+                    if alive {
+                        model.universe.kill_snake(0);
+                    }
+                    scores.push(model.score);
+                    model.reset(app_config.allowed_moves, app_config.food_amount);
+                    episodes += 1;
+                }
+            }
+            if scores.len() >= self.config.report_every.max(1) || episodes >= self.config.episodes {
+                on_report(&DqnReport {
+                    episodes,
+                    mean_score: scores.iter().sum::<u32>() as f64 / scores.len().max(1) as f64,
+                    best_score: scores.iter().copied().max().unwrap_or(0),
+                    epsilon,
+                    loss: loss / learn_steps.max(1) as f64,
+                });
+                scores.clear();
+                (loss, learn_steps) = (0., 0);
+            }
+        }
+    }
+}
+
+/// Index and value of the highest output other than `reverse`
+fn best_action(output: &[f64], reverse: Option<usize>) -> (usize, f64) {
+    output
+        .iter()
+        .copied()
+        .enumerate()
+        .filter(|&(action, _)| Some(action) != reverse)
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .unwrap_or((0, 0.))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai_snake::neural_network::sensor::SensorConfig;
+
+    fn dqn(config: DqnConfig) -> Dqn {
+        let actions = ActionSpace::default();
+        Dqn::new(
+            &Architecture::default(),
+            input_size().into(),
+            actions,
+            config,
+            3,
+        )
+    }
+
+    fn input_size() -> usize {
+        SensorConfig::default().input_size()
+    }
+
+    #[test]
+    fn replay_overwrites_the_oldest() {
+        let mut replay = ReplayBuffer::new(2);
+        for action in 0..3 {
+            replay.push(Transition {
+                state: vec![],
+                action,
+                reward: 0.,
+                next: None,
+            });
+        }
+        let actions: Vec<usize> = replay.transitions.iter().map(|t| t.action).collect();
+        assert_eq!(actions, vec![2, 1]);
+    }
+
+    #[test]
+    fn learns_the_value_of_a_terminal_move() {
+        let mut dqn = dqn(DqnConfig {
+            batch_size: 4,
+            learning_rate: 0.01,
+            ..DqnConfig::default()
+        });
+        let state: Vec<f64> = (0..input_size()).map(|i| i as f64 / 16.).collect();
+        for _ in 0..4 {
+            dqn.replay.push(Transition {
+                state: state.clone(),
+                action: 2,
+                reward: FOOD_REWARD,
+                next: None,
+            });
+        }
+        for _ in 0..500 {
+            dqn.learn();
+        }
+        dqn.network.forward_trace(&state, &mut dqn.trace);
+        assert!((dqn.trace.output()[2] - FOOD_REWARD).abs() < 1e-2);
+    }
+
+    #[test]
+    fn never_turns_back() {
+        let mut dqn = dqn(DqnConfig::default());
+        let state = vec![0.5; input_size()];
+        for reverse in 0..ActionSpace::default().size() {
+            assert_ne!(dqn.greedy(&state, Some(reverse)), reverse);
+            for _ in 0..20 {
+                assert_ne!(dqn.explore(&state, Some(reverse), 1.), reverse);
+            }
+        }
+    }
+
+    #[test]
+    fn training_reports_every_episode() {
+        let app_config = AppConfig {
+            grid_size: 8,
+            vision_range: 8,
+            allowed_moves: 20,
+            ..AppConfig::default()
+        };
+        let mut dqn = dqn(DqnConfig {
+            episodes: 6,
+            environments: 2,
+            report_every: 3,
+            ..DqnConfig::default()
+        });
+        let mut reported = 0;
+        dqn.train(&app_config, |report| reported = report.episodes);
+        assert!(reported >= 6);
+        assert!(dqn.replay.transitions.len() >= 6);
+    }
+}
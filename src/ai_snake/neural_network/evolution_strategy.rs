@@ -0,0 +1,555 @@
+use std::{fmt, str::FromStr};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use super::mutation::gaussian;
+
+/// How the next generation is built from the fitness of the last one
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Optimiser {
+    /// Selection, crossover and mutation
+    #[default]
+    Genetic,
+    /// Gradient estimated from antithetic gaussian perturbations of a centre
+    OpenAiEs,
+    /// Covariance matrix adaptation, only of the variance of each weight above
+    /// [`FULL_COVARIANCE_LIMIT`] weights
+    CmaEs,
+}
+
+/// Most weights CMA-ES adapts a full covariance matrix for. Its eigendecomposition is cubic
+/// in the number of weights and runs between two frames, so larger networks fall back to
+/// the diagonal covariance of sep-CMA-ES, linear in the number of weights
+pub const FULL_COVARIANCE_LIMIT: usize = 100;
+
+/// Parameters of the evolution strategies that can change during a run
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StrategyConfig {
+    /// Standard deviation of the OpenAI-ES samples, CMA-ES only starts from it
+    pub sigma: f64,
+    /// Step of the OpenAI-ES centre along its estimated gradient
+    pub learning_rate: f64,
+}
+
+/// State of an evolution strategy: the population samples parameters around a centre,
+/// which then moves towards the samples that scored best
+#[derive(Clone, Serialize, Deserialize)]
+pub enum EvolutionStrategy {
+    OpenAi(OpenAiEs),
+    Cma(Box<CmaEs>),
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct OpenAiEs {
+    centre: Vec<f64>,
+    population: usize,
+    /// Perturbation of each antithetic pair of the last samples
+    noise: Vec<Vec<f64>>,
+    /// Standard deviation of the last samples
+    sigma: f64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CmaEs {
+    dimension: usize,
+    population: usize,
+    weights: Vec<f64>,
+    mu_eff: f64,
+    cc: f64,
+    cs: f64,
+    c1: f64,
+    c_mu: f64,
+    damps: f64,
+    chi_n: f64,
+    mean: Vec<f64>,
+    sigma: f64,
+    pc: Vec<f64>,
+    ps: Vec<f64>,
+    /// Only the diagonal of the covariance is adapted
+    separable: bool,
+    /// Covariance matrix, row-major, or its diagonal when separable
+    covariance: Vec<f64>,
+    /// Eigenvectors of the covariance as columns, row-major, empty when separable
+    basis: Vec<f64>,
+    /// Square roots of the eigenvalues of the covariance
+    scales: Vec<f64>,
+    generation: u64,
+    eigen_generation: u64,
+    /// `basis * scales * z` of the last samples, before scaling by sigma
+    steps: Vec<Vec<f64>>,
+}
+
+impl Optimiser {
+    pub const ALL: [Optimiser; 3] = [Optimiser::Genetic, Optimiser::OpenAiEs, Optimiser::CmaEs];
+}
+
+impl EvolutionStrategy {
+    /// `None` for the genetic algorithm, which keeps no centre
+    pub fn new(
+        optimiser: Optimiser,
+        centre: Vec<f64>,
+        sigma: f64,
+        population: usize,
+    ) -> Option<Self> {
+        match optimiser {
+            Optimiser::Genetic => None,
+            Optimiser::OpenAiEs => Some(EvolutionStrategy::OpenAi(OpenAiEs {
+                centre,
+                population,
+                noise: vec![],
+                sigma,
+            })),
+            Optimiser::CmaEs => Some(EvolutionStrategy::Cma(Box::new(CmaEs::new(
+                centre, sigma, population,
+            )))),
+        }
+    }
+
+    pub fn optimiser(&self) -> Optimiser {
+        match self {
+            EvolutionStrategy::OpenAi(_) => Optimiser::OpenAiEs,
+            EvolutionStrategy::Cma(_) => Optimiser::CmaEs,
+        }
+    }
+
+    pub fn centre(&self) -> &[f64] {
+        match self {
+            EvolutionStrategy::OpenAi(es) => &es.centre,
+            EvolutionStrategy::Cma(es) => &es.mean,
+        }
+    }
+
+    /// Number of samples per generation
+    pub fn population(&self) -> usize {
+        match self {
+            EvolutionStrategy::OpenAi(es) => es.population,
+            EvolutionStrategy::Cma(es) => es.population,
+        }
+    }
+
+    /// Parameters of each model of the next generation
+    pub fn sample(&mut self, config: &StrategyConfig, rng: &mut impl Rng) -> Vec<Vec<f64>> {
+        match self {
+            EvolutionStrategy::OpenAi(es) => es.sample(config.sigma, rng),
+            EvolutionStrategy::Cma(es) => es.sample(rng),
+        }
+    }
+
+    /// Moves the centre with the fitness of the last samples, in the same order
+    pub fn update(&mut self, scores: &[f64], config: &StrategyConfig) {
+        match self {
+            EvolutionStrategy::OpenAi(es) => es.update(scores, config.learning_rate),
+            EvolutionStrategy::Cma(es) => es.update(scores),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            EvolutionStrategy::OpenAi(es) => format!("{} (sigma={})", self.optimiser(), es.sigma),
+            EvolutionStrategy::Cma(es) => format!("{} (sigma={:.4})", self.optimiser(), es.sigma),
+        }
+    }
+}
+
+impl OpenAiEs {
+    /// Pairs `centre ± sigma * noise`, plus the centre itself when the population is odd
+    fn sample(&mut self, sigma: f64, rng: &mut impl Rng) -> Vec<Vec<f64>> {
+        self.sigma = sigma;
+        self.noise = (0..self.population / 2)
+            .map(|_| self.centre.iter().map(|_| gaussian(rng)).collect())
+            .collect();
+        let mut samples = Vec::with_capacity(self.population);
+        for noise in &self.noise {
+            for sign in [1., -1.] {
+                samples.push(
+                    self.centre
+                        .iter()
+                        .zip(noise)
+                        .map(|(x, e)| x + sign * sigma * e)
+                        .collect(),
+                );
+            }
+        }
+        if self.population % 2 == 1 {
+            samples.push(self.centre.clone());
+        }
+        samples
+    }
+
+    /// Gradient ascent on the centered ranks, which ignore the scale of the scores
+    fn update(&mut self, scores: &[f64], learning_rate: f64) {
+        if scores.len() != self.population || self.noise.is_empty() {
+            return;
+        }
+        let utilities = centered_ranks(scores);
+        let factor = learning_rate / (2 * self.noise.len()) as f64 / self.sigma;
+        for (pair, noise) in self.noise.iter().enumerate() {
+            let utility = utilities[2 * pair] - utilities[2 * pair + 1];
+            for (x, e) in self.centre.iter_mut().zip(noise) {
+                *x += factor * utility * e;
+            }
+        }
+    }
+}
+
+impl CmaEs {
+    /// Separable above [`FULL_COVARIANCE_LIMIT`] weights
+    fn new(mean: Vec<f64>, sigma: f64, population: usize) -> Self {
+        let separable = mean.len() > FULL_COVARIANCE_LIMIT;
+        CmaEs::with_covariance(mean, sigma, population, separable)
+    }
+
+    /// Default settings of Hansen's tutorial, with half the population as parents.
+    /// A separable covariance learns `(n + 2) / 3` times faster, as in Ros and Hansen's sep-CMA-ES
+    fn with_covariance(mean: Vec<f64>, sigma: f64, population: usize, separable: bool) -> Self {
+        let n = mean.len();
+        let nf = n as f64;
+        let mu = (population / 2).max(1);
+        let mut weights: Vec<f64> = (0..mu)
+            .map(|i| (mu as f64 + 0.5).ln() - (i as f64 + 1.).ln())
+            .collect();
+        let sum: f64 = weights.iter().sum();
+        weights.iter_mut().for_each(|w| *w /= sum);
+        let mu_eff = 1. / weights.iter().map(|w| w * w).sum::<f64>();
+
+        let cc = (4. + mu_eff / nf) / (nf + 4. + 2. * mu_eff / nf);
+        let cs = (mu_eff + 2.) / (nf + mu_eff + 5.);
+        let speed = if separable { (nf + 2.) / 3. } else { 1. };
+        let c1 = (speed * 2. / ((nf + 1.3).powi(2) + mu_eff)).min(1.);
+        let c_mu =
+            (1. - c1).min(speed * 2. * (mu_eff - 2. + 1. / mu_eff) / ((nf + 2.).powi(2) + mu_eff));
+        let damps = 1. + 2. * (((mu_eff - 1.) / (nf + 1.)).sqrt() - 1.).max(0.) + cs;
+        let chi_n = nf.sqrt() * (1. - 1. / (4. * nf) + 1. / (21. * nf * nf));
+        CmaEs {
+            dimension: n,
+            population,
+            weights,
+            mu_eff,
+            cc,
+            cs,
+            c1,
+            c_mu,
+            damps,
+            chi_n,
+            mean,
+            sigma,
+            pc: vec![0.; n],
+            ps: vec![0.; n],
+            separable,
+            covariance: if separable { vec![1.; n] } else { identity(n) },
+            basis: if separable { vec![] } else { identity(n) },
+            scales: vec![1.; n],
+            generation: 0,
+            eigen_generation: 0,
+            steps: vec![],
+        }
+    }
+
+    fn sample(&mut self, rng: &mut impl Rng) -> Vec<Vec<f64>> {
+        let n = self.dimension;
+        self.steps = (0..self.population)
+            .map(|_| {
+                let z: Vec<f64> = (0..n).map(|j| self.scales[j] * gaussian(rng)).collect();
+                if self.separable {
+                    return z;
+                }
+                (0..n)
+                    .map(|i| (0..n).map(|j| self.basis[i * n + j] * z[j]).sum())
+                    .collect()
+            })
+            .collect();
+        self.steps
+            .iter()
+            .map(|step: &Vec<f64>| {
+                self.mean
+                    .iter()
+                    .zip(step)
+                    .map(|(m, y)| m + self.sigma * y)
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn update(&mut self, scores: &[f64]) {
+        if scores.len() != self.population || self.steps.len() != self.population {
+            return;
+        }
+        let n = self.dimension;
+        let nf = n as f64;
+        let mut order: Vec<usize> = (0..scores.len()).collect();
+        order.sort_by(|&a, &b| scores[b].total_cmp(&scores[a]));
+        let parents: Vec<&Vec<f64>> = order
+            .iter()
+            .take(self.weights.len())
+            .map(|&i| &self.steps[i])
+            .collect();
+
+        let mut step = vec![0.; n];
+        for (w, y) in self.weights.iter().zip(&parents) {
+            for (s, y) in step.iter_mut().zip(y.iter()) {
+                *s += w * y;
+            }
+        }
+        for (m, s) in self.mean.iter_mut().zip(&step) {
+            *m += self.sigma * s;
+        }
+
+        // C^-1/2 * step = B * D^-1 * B^T * step, B is the identity when separable
+        let whitened: Vec<f64> = if self.separable {
+            step.iter().zip(&self.scales).map(|(s, d)| s / d).collect()
+        } else {
+            let rotated: Vec<f64> = (0..n)
+                .map(|j| {
+                    (0..n).map(|i| self.basis[i * n + j] * step[i]).sum::<f64>() / self.scales[j]
+                })
+                .collect();
+            (0..n)
+                .map(|i| (0..n).map(|j| self.basis[i * n + j] * rotated[j]).sum())
+                .collect()
+        };
+        let cs_factor = (self.cs * (2. - self.cs) * self.mu_eff).sqrt();
+        for (p, w) in self.ps.iter_mut().zip(&whitened) {
+            *p = (1. - self.cs) * *p + cs_factor * w;
+        }
+        let ps_norm = self.ps.iter().map(|p| p * p).sum::<f64>().sqrt();
+        let hsig = ps_norm
+            / (1. - (1. - self.cs).powi(2 * (self.generation as i32 + 1)))
+                .max(f64::MIN_POSITIVE)
+                .sqrt()
+            / self.chi_n
+            < 1.4 + 2. / (nf + 1.);
+        let hsig = if hsig { 1. } else { 0. };
+        let cc_factor = (self.cc * (2. - self.cc) * self.mu_eff).sqrt();
+        for (p, s) in self.pc.iter_mut().zip(&step) {
+            *p = (1. - self.cc) * *p + hsig * cc_factor * s;
+        }
+
+        let keep = 1. - self.c1 - self.c_mu;
+        let correction = (1. - hsig) * self.cc * (2. - self.cc);
+        let adapt = |c: f64, i: usize, j: usize| {
+            let rank_mu: f64 = self
+                .weights
+                .iter()
+                .zip(&parents)
+                .map(|(w, y)| w * y[i] * y[j])
+                .sum();
+            keep * c + self.c1 * (self.pc[i] * self.pc[j] + correction * c) + self.c_mu * rank_mu
+        };
+        if self.separable {
+            for i in 0..n {
+                self.covariance[i] = adapt(self.covariance[i], i, i);
+            }
+        } else {
+            for i in 0..n {
+                for j in 0..=i {
+                    let value = adapt(self.covariance[i * n + j], i, j);
+                    self.covariance[i * n + j] = value;
+                    self.covariance[j * n + i] = value;
+                }
+            }
+        }
+        self.sigma *= ((self.cs / self.damps) * (ps_norm / self.chi_n - 1.)).exp();
+        self.sigma = self.sigma.clamp(1e-10, 1e3);
+        self.generation += 1;
+
+        if self.separable {
+            self.scales = self
+                .covariance
+                .iter()
+                .map(|v| v.max(1e-20).sqrt())
+                .collect();
+            return;
+        }
+        // the decomposition is cubic, it is only refreshed once the covariance moved enough
+        let lag = (self.generation - self.eigen_generation) as f64;
+        if lag > 1. / ((self.c1 + self.c_mu) * nf * 10.) {
+            self.eigen_generation = self.generation;
+            let (values, vectors) = symmetric_eigen(self.covariance.clone(), n);
+            self.scales = values.iter().map(|v| v.max(1e-20).sqrt()).collect();
+            self.basis = vectors;
+        }
+    }
+}
+
+/// Ranks scaled to [-0.5, 0.5], tied scores sharing their average rank
+fn centered_ranks(scores: &[f64]) -> Vec<f64> {
+    let mut order: Vec<usize> = (0..scores.len()).collect();
+    order.sort_by(|&a, &b| scores[a].total_cmp(&scores[b]));
+    let mut ranks = vec![0.; scores.len()];
+    let mut start = 0;
+    while start < order.len() {
+        let end = start
+            + order[start..]
+                .iter()
+                .take_while(|&&i| scores[i] == scores[order[start]])
+                .count();
+        let rank = (start + end - 1) as f64 / 2.;
+        for &i in &order[start..end] {
+            ranks[i] = rank;
+        }
+        start = end;
+    }
+    let top = (scores.len().max(2) - 1) as f64;
+    ranks.iter().map(|r| r / top - 0.5).collect()
+}
+
+fn identity(n: usize) -> Vec<f64> {
+    let mut matrix = vec![0.; n * n];
+    for i in 0..n {
+        matrix[i * n + i] = 1.;
+    }
+    matrix
+}
+
+/// Eigenvalues and eigenvectors, as the columns of a row-major matrix, of the symmetric
+/// `n x n` matrix `a`, by cyclic Jacobi rotations
+fn symmetric_eigen(mut a: Vec<f64>, n: usize) -> (Vec<f64>, Vec<f64>) {
+    let mut v = identity(n);
+    let norm: f64 = a.iter().map(|x| x * x).sum();
+    for _ in 0..50 {
+        let off: f64 = (0..n)
+            .flat_map(|i| (i + 1..n).map(move |j| (i, j)))
+            .map(|(i, j)| a[i * n + j] * a[i * n + j])
+            .sum();
+        if off <= 1e-24 * norm {
+            break;
+        }
+        for p in 0..n {
+            for q in p + 1..n {
+                let apq = a[p * n + q];
+                if apq == 0. {
+                    continue;
+                }
+                let theta = (a[q * n + q] - a[p * n + p]) / (2. * apq);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.).sqrt());
+                let c = 1. / (t * t + 1.).sqrt();
+                let s = t * c;
+                for k in 0..n {
+                    let (akp, akq) = (a[k * n + p], a[k * n + q]);
+                    a[k * n + p] = c * akp - s * akq;
+                    a[k * n + q] = s * akp + c * akq;
+                }
+                for k in 0..n {
+                    let (apk, aqk) = (a[p * n + k], a[q * n + k]);
+                    a[p * n + k] = c * apk - s * aqk;
+                    a[q * n + k] = s * apk + c * aqk;
+                }
+                for k in 0..n {
+                    let (vkp, vkq) = (v[k * n + p], v[k * n + q]);
+                    v[k * n + p] = c * vkp - s * vkq;
+                    v[k * n + q] = s * vkp + c * vkq;
+                }
+            }
+        }
+    }
+    ((0..n).map(|i| a[i * n + i]).collect(), v)
+}
+
+impl fmt::Display for Optimiser {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Optimiser::Genetic => write!(f, "genetic"),
+            Optimiser::OpenAiEs => write!(f, "openai-es"),
+            Optimiser::CmaEs => write!(f, "cma-es"),
+        }
+    }
+}
+
+impl FromStr for Optimiser {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        Optimiser::ALL
+            .into_iter()
+            .find(|optimiser| optimiser.to_string() == s)
+            .ok_or(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+
+    /// Higher the closer to (1, -2, 0.5)
+    fn score(x: &[f64]) -> u32 {
+        let target = [1., -2., 0.5];
+        let distance: f64 = x.iter().zip(target).map(|(x, t)| (x - t).powi(2)).sum();
+        (10_000. / (1. + distance)) as u32
+    }
+
+    fn optimise(optimiser: Optimiser, generations: usize) -> Vec<f64> {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let config = StrategyConfig {
+            sigma: 0.3,
+            learning_rate: 0.5,
+        };
+        let mut es = EvolutionStrategy::new(optimiser, vec![0.; 3], 0.3, 20).unwrap();
+        for _ in 0..generations {
+            let samples = es.sample(&config, &mut rng);
+            let scores: Vec<f64> = samples.iter().map(|x| score(x) as f64).collect();
+            es.update(&scores, &config);
+        }
+        es.centre().to_vec()
+    }
+
+    #[test]
+    fn strategies_climb_a_quadratic() {
+        for optimiser in [Optimiser::OpenAiEs, Optimiser::CmaEs] {
+            let centre = optimise(optimiser, 200);
+            assert!(score(&centre) > 9_900, "{optimiser}: {centre:?}");
+        }
+    }
+
+    #[test]
+    fn separable_cma_es_climbs_a_quadratic() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let mut es = CmaEs::with_covariance(vec![0.; 3], 0.3, 20, true);
+        for _ in 0..200 {
+            let samples = es.sample(&mut rng);
+            let scores: Vec<f64> = samples.iter().map(|x| score(x) as f64).collect();
+            es.update(&scores);
+        }
+        assert!(score(&es.mean) > 9_900, "{:?}", es.mean);
+    }
+
+    #[test]
+    fn large_networks_only_adapt_the_diagonal() {
+        let small = CmaEs::new(vec![0.; FULL_COVARIANCE_LIMIT], 0.3, 10);
+        assert!(!small.separable);
+        let large = CmaEs::new(vec![0.; FULL_COVARIANCE_LIMIT + 1], 0.3, 10);
+        assert!(large.separable);
+        assert_eq!(large.covariance.len(), FULL_COVARIANCE_LIMIT + 1);
+    }
+
+    #[test]
+    fn ties_share_their_rank() {
+        assert_eq!(centered_ranks(&[3., 0., 3., 7.]), vec![0., -0.5, 0., 0.5]);
+    }
+
+    #[test]
+    fn eigen_decomposition_rebuilds_the_matrix() {
+        let n = 4;
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let mut a = vec![0.; n * n];
+        for i in 0..n {
+            for j in 0..=i {
+                let x = rng.gen::<f64>() - 0.5;
+                a[i * n + j] = x;
+                a[j * n + i] = x;
+            }
+        }
+        let (values, vectors) = symmetric_eigen(a.clone(), n);
+        for i in 0..n {
+            for j in 0..n {
+                let rebuilt: f64 = (0..n)
+                    .map(|k| vectors[i * n + k] * values[k] * vectors[j * n + k])
+                    .sum();
+                assert!((rebuilt - a[i * n + j]).abs() < 1e-9);
+            }
+        }
+    }
+}
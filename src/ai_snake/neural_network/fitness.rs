@@ -0,0 +1,312 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// What a snake did during one game, measured while it plays
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct EpisodeStats {
+    pub food: u32,
+    /// Moves made before dying or running out of moves
+    pub steps: u64,
+    /// Moves ending on a cell already visited since the last food
+    pub revisits: u64,
+    /// Moves towards the closest food minus moves away from it
+    pub approach: i64,
+    pub timed_out: bool,
+}
+
+/// One measure of a game, weighted in a [`FitnessFunction`]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum FitnessTerm {
+    /// Food eaten, the game score
+    Food,
+    /// Moves survived
+    Steps,
+    /// `2^food - 1`, rewarding long snakes much more than short ones
+    Exponential,
+    /// Moves towards the closest food minus moves away from it
+    Approach,
+    /// Cells visited twice between two foods, a sign of looping
+    Revisits,
+    /// 1 if the snake ran out of moves
+    Timeout,
+}
+
+/// How the fitness of the episodes played by a model in one generation are combined
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Aggregation {
+    #[default]
+    Mean,
+    Median,
+    /// The lowest fitness, favouring brains that never play badly
+    Worst,
+}
+
+/// Weighted sum of terms, penalties have negative weights
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FitnessFunction {
+    pub terms: Vec<(FitnessTerm, f64)>,
+}
+
+impl FitnessTerm {
+    pub const ALL: [FitnessTerm; 6] = [
+        FitnessTerm::Food,
+        FitnessTerm::Steps,
+        FitnessTerm::Exponential,
+        FitnessTerm::Approach,
+        FitnessTerm::Revisits,
+        FitnessTerm::Timeout,
+    ];
+
+    pub fn measure(&self, stats: &EpisodeStats) -> f64 {
+        match self {
+            FitnessTerm::Food => stats.food as f64,
+            FitnessTerm::Steps => stats.steps as f64,
+            FitnessTerm::Exponential => 2f64.powi(stats.food as i32) - 1.,
+            FitnessTerm::Approach => stats.approach as f64,
+            FitnessTerm::Revisits => stats.revisits as f64,
+            FitnessTerm::Timeout => f64::from(u8::from(stats.timed_out)),
+        }
+    }
+}
+
+impl Aggregation {
+    pub const ALL: [Aggregation; 3] = [Aggregation::Mean, Aggregation::Median, Aggregation::Worst];
+
+    /// 0 without episodes
+    pub fn aggregate(&self, fitness: &[f64]) -> f64 {
+        if fitness.is_empty() {
+            return 0.;
+        }
+        match self {
+            Aggregation::Mean => fitness.iter().sum::<f64>() / fitness.len() as f64,
+            Aggregation::Median => {
+                let mut sorted = fitness.to_vec();
+                sorted.sort_by(f64::total_cmp);
+                let middle = sorted.len() / 2;
+                if sorted.len().is_multiple_of(2) {
+                    (sorted[middle - 1] + sorted[middle]) / 2.
+                } else {
+                    sorted[middle]
+                }
+            }
+            Aggregation::Worst => fitness.iter().copied().fold(f64::INFINITY, f64::min),
+        }
+    }
+}
+
+/// Population variance, 0 for less than two values
+pub fn variance(values: &[f64]) -> f64 {
+    if values.len() < 2 {
+        return 0.;
+    }
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len() as f64
+}
+
+/// The food eaten, as the game score
+impl Default for FitnessFunction {
+    fn default() -> Self {
+        FitnessFunction {
+            terms: vec![(FitnessTerm::Food, 1.)],
+        }
+    }
+}
+
+impl FitnessFunction {
+    pub fn evaluate(&self, stats: &EpisodeStats) -> f64 {
+        self.terms
+            .iter()
+            .map(|(term, weight)| weight * term.measure(stats))
+            .sum()
+    }
+
+    pub fn weight(&self, term: FitnessTerm) -> f64 {
+        self.terms
+            .iter()
+            .filter(|(t, _)| *t == term)
+            .map(|(_, weight)| weight)
+            .sum()
+    }
+
+    /// Replaces the weight of `term`, a zero weight removes it
+    pub fn set_weight(&mut self, term: FitnessTerm, weight: f64) {
+        match self.terms.iter().position(|(t, _)| *t == term) {
+            Some(i) if weight == 0. => {
+                self.terms.remove(i);
+            }
+            Some(i) => self.terms[i].1 = weight,
+            None if weight != 0. => self.terms.push((term, weight)),
+            None => (),
+        }
+    }
+}
+
+impl fmt::Display for Aggregation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Aggregation::Mean => write!(f, "mean"),
+            Aggregation::Median => write!(f, "median"),
+            Aggregation::Worst => write!(f, "worst"),
+        }
+    }
+}
+
+impl FromStr for Aggregation {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        Aggregation::ALL
+            .into_iter()
+            .find(|aggregation| aggregation.to_string() == s)
+            .ok_or(())
+    }
+}
+
+impl fmt::Display for FitnessTerm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FitnessTerm::Food => write!(f, "food"),
+            FitnessTerm::Steps => write!(f, "steps"),
+            FitnessTerm::Exponential => write!(f, "exponential"),
+            FitnessTerm::Approach => write!(f, "approach"),
+            FitnessTerm::Revisits => write!(f, "revisits"),
+            FitnessTerm::Timeout => write!(f, "timeout"),
+        }
+    }
+}
+
+impl FromStr for FitnessTerm {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        FitnessTerm::ALL
+            .into_iter()
+            .find(|term| term.to_string() == s)
+            .ok_or(())
+    }
+}
+
+/// Written as `food+0.01*steps-timeout`
+impl fmt::Display for FitnessFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.terms.is_empty() {
+            return write!(f, "0");
+        }
+        for (i, (term, weight)) in self.terms.iter().enumerate() {
+            let sign = if *weight < 0. {
+                "-"
+            } else if i > 0 {
+                "+"
+            } else {
+                ""
+            };
+            if weight.abs() == 1. {
+                write!(f, "{sign}{term}")?;
+            } else {
+                write!(f, "{sign}{}*{term}", weight.abs())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses the displayed form, weights default to 1
+impl FromStr for FitnessFunction {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let s: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+        if s == "0" {
+            return Ok(FitnessFunction { terms: vec![] });
+        }
+        // split before every sign, except the ones of exponents such as 1e-3
+        let mut parts = vec![];
+        let mut start = 0;
+        let bytes = s.as_bytes();
+        for (i, &c) in bytes.iter().enumerate().skip(1) {
+            let exponent = matches!(bytes[i - 1], b'e' | b'E')
+                && i >= 2
+                && (bytes[i - 2].is_ascii_digit() || bytes[i - 2] == b'.');
+            if (c == b'+' || c == b'-') && !exponent {
+                parts.push(&s[start..i]);
+                start = i;
+            }
+        }
+        parts.push(&s[start..]);
+
+        let mut fitness = FitnessFunction { terms: vec![] };
+        for part in parts {
+            let (sign, part) = match part.as_bytes().first() {
+                Some(b'-') => (-1., &part[1..]),
+                Some(b'+') => (1., &part[1..]),
+                _ => (1., part),
+            };
+            let (weight, term) = match part.split_once('*') {
+                Some((weight, term)) => (weight.parse::<f64>().map_err(|_| ())?, term),
+                None => (1., part),
+            };
+            fitness.terms.push((term.parse()?, sign * weight));
+        }
+        Ok(fitness)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weighted_sum_of_terms() {
+        let stats = EpisodeStats {
+            food: 3,
+            steps: 40,
+            revisits: 5,
+            approach: -2,
+            timed_out: true,
+        };
+        let fitness: FitnessFunction = "food+0.5*steps+exponential-2*revisits-timeout+approach"
+            .parse()
+            .unwrap();
+        assert_eq!(fitness.evaluate(&stats), 3. + 20. + 7. - 10. - 1. - 2.);
+        assert_eq!(FitnessFunction::default().evaluate(&stats), 3.);
+    }
+
+    #[test]
+    fn parses_what_it_displays() {
+        for text in [
+            "food",
+            "food+0.01*steps-timeout",
+            "-0.5*revisits+1e-3*steps",
+            "0",
+        ] {
+            let fitness: FitnessFunction = text.parse().unwrap();
+            assert_eq!(fitness.to_string().parse::<FitnessFunction>(), Ok(fitness));
+        }
+        assert_eq!(
+            "1e-3*steps".parse::<FitnessFunction>().unwrap().terms,
+            vec![(FitnessTerm::Steps, 1e-3)]
+        );
+        assert!("food+speed".parse::<FitnessFunction>().is_err());
+    }
+
+    #[test]
+    fn aggregations_of_episodes() {
+        let fitness = [4., 1., 10., 3.];
+        assert_eq!(Aggregation::Mean.aggregate(&fitness), 4.5);
+        assert_eq!(Aggregation::Median.aggregate(&fitness), 3.5);
+        assert_eq!(Aggregation::Median.aggregate(&fitness[..3]), 4.);
+        assert_eq!(Aggregation::Worst.aggregate(&fitness), 1.);
+        assert_eq!(variance(&[1., 3.]), 1.);
+        assert_eq!(variance(&[5.]), 0.);
+    }
+
+    #[test]
+    fn zero_weights_remove_terms() {
+        let mut fitness = FitnessFunction::default();
+        fitness.set_weight(FitnessTerm::Steps, 0.1);
+        fitness.set_weight(FitnessTerm::Food, 0.);
+        assert_eq!(fitness.terms, vec![(FitnessTerm::Steps, 0.1)]);
+        assert_eq!(fitness.weight(FitnessTerm::Timeout), 0.);
+    }
+}
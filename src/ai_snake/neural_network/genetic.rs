@@ -1,79 +1,445 @@
-use std::fmt;
+use std::{fmt, ops::Range};
+
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
 
 use crate::ai_snake::simulation::GridConfiguration;
 
-use super::{model::Model, NeuralNetwork};
+use super::{
+    brain::{Brain, BrainKind},
+    crossover::Crossover,
+    diversity::Diversity,
+    evolution_strategy::{EvolutionStrategy, Optimiser, StrategyConfig},
+    fitness::{variance, Aggregation, FitnessFunction},
+    hall_of_fame::HallOfFame,
+    island::{self, IslandSettings, MigrationTopology},
+    model::Model,
+    mutation::{Mutation, MutationConfig},
+    neat::{Innovations, NeatConfig, NeatGenome},
+    selection::SelectionConfig,
+    speciation::Speciation,
+    NeuralNetwork,
+};
+
+/// Settings [`GeneticModel::evolve`] breeds a generation with
+#[derive(Clone)]
+pub struct EvolutionConfig {
+    /// Generation being evolved
+    pub generation: u64,
+    /// What the models are selected on
+    pub fitness: FitnessFunction,
+    /// How the fitness of the episodes of a model are combined
+    pub aggregation: Aggregation,
+    pub brain: BrainKind,
+    pub neat: NeatConfig,
+    /// Champions kept in the hall of fame
+    pub hall_of_fame_size: usize,
+    /// Best models of each island copied unchanged into the next generation
+    pub elitism: usize,
+    /// Weight-space distance under which layered networks share a species, `None` keeps
+    /// them in a single species
+    pub species_threshold: Option<f64>,
+    pub selection: SelectionConfig,
+    pub crossover: Crossover,
+    pub mutation: MutationConfig,
+    pub optimiser: Optimiser,
+    pub strategy: StrategyConfig,
+    /// Sub-populations evolving on their own, 1 evolves the population as a whole
+    pub islands: usize,
+    /// Generations between two migrations, 0 never migrates
+    pub migration_interval: u64,
+    /// Best models each island sends to each of its destinations
+    pub migration_size: usize,
+    pub migration_topology: MigrationTopology,
+    /// Overrides of each island, missing islands use the settings above
+    pub island_settings: Vec<IslandSettings>,
+}
 
+#[derive(Serialize, Deserialize)]
 pub struct GeneticModel {
     pub population: Vec<Model>,
+    pub rng: ChaCha8Rng,
+    pub hall_of_fame: HallOfFame,
+    /// Genes created so far by the NEAT genomes of the population
+    pub innovations: Innovations,
+    /// Species of each island in the last generation, empty when islands are not split
+    pub speciation: Vec<Speciation>,
+    /// Diversity of the last generation before it bred
+    pub diversity: Diversity,
+    /// Best and average score of each island in the last generation
+    pub island_scores: Vec<(u32, u32)>,
+    /// Best and average fitness of the last generation
+    pub fitness: (f64, f64),
+    /// Mean over the models of the variance of the fitness of their episodes
+    pub fitness_variance: f64,
+    /// Centre sampled by the population when an evolution strategy is used instead of the GA
+    pub strategy: Option<EvolutionStrategy>,
 }
 
 impl GeneticModel {
+    /// Every model draws its brain, food and mutations from its own stream of `seed`,
+    /// so a run only depends on the seed and the configuration
     pub fn new(
         grid_config: &GridConfiguration,
         allowed_moves_before_evolution: u64,
         population_count: u64,
-        brain: Vec<NeuralNetwork>,
+        seed: u64,
+        mut new_brain: impl FnMut(&mut ChaCha8Rng) -> Brain,
     ) -> Self {
         let mut population: Vec<Model> = Vec::new();
         (0..population_count).for_each(|i| {
+            let mut rng = Model::rng(seed, i as usize);
+            let brain = new_brain(&mut rng);
             population.push(Model::new(
                 grid_config.width,
                 grid_config.height,
                 allowed_moves_before_evolution,
                 i as usize,
-                brain[i as usize].clone(),
+                brain,
+                rng,
             ));
         });
-        GeneticModel { population }
+        let mut genetic_model = GeneticModel {
+            population,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            hall_of_fame: HallOfFame::default(),
+            innovations: Innovations::default(),
+            speciation: vec![],
+            diversity: Diversity::default(),
+            island_scores: vec![],
+            fitness: (0., 0.),
+            fitness_variance: 0.,
+            strategy: None,
+        };
+        genetic_model.observe_innovations();
+        genetic_model
     }
 
-    fn mutate_population(&mut self, mutation_factor: f64) {
+    /// Gives every model a copy of `brain`, e.g. one loaded from disk
+    pub fn set_brains(&mut self, brain: &Brain) {
         for model in &mut self.population {
-            model.brain.mutate(mutation_factor);
+            model.brain = brain.clone();
         }
+        self.observe_innovations();
     }
 
-    pub fn evolve(&mut self, keep_x_best: f64, mutation_factor: f64) -> (u32, u32, u32) {
-        let mut best_score = 0;
-        let mut average_score = 0;
-        for i in 0..self.population.len() {
-            if self.population[i].score > best_score {
-                best_score = self.population[i].score;
+    /// Registers the genes of NEAT genomes built outside of mutations
+    fn observe_innovations(&mut self) {
+        for model in &self.population {
+            if let Brain::Neat(genome) = &model.brain {
+                self.innovations.observe(genome);
             }
-            average_score += self.population[i].score;
         }
-        average_score = (average_score as f32 / self.population.len() as f32) as u32;
+    }
+
+    /// Mutates the models of `island` but its first `elites`
+    fn mutate_island(
+        &mut self,
+        mutation: &Mutation,
+        config: &EvolutionConfig,
+        island: Range<usize>,
+        elites: usize,
+    ) {
+        for model in self.population[island].iter_mut().skip(elites) {
+            model.brain.mutate(
+                mutation,
+                &config.neat,
+                &mut self.innovations,
+                &mut model.rng,
+            );
+        }
+    }
+
+    /// Scores the population with the fitness function aggregated over the episodes of each
+    /// model, the score being the mean food eaten, then breeds the next population,
+    /// island by island, and mutates it.
+    /// Every `migration_interval` generations the best models of each island first replace
+    /// the worst models of the islands it is linked to
+    pub fn evolve(&mut self, config: &EvolutionConfig) -> (u32, u32, u32) {
+        if self.population.is_empty() {
+            return (0, 0, 0);
+        }
+        let mut variances = 0.;
+        for model in &mut self.population {
+            let episodes: Vec<f64> = model
+                .episodes()
+                .map(|stats| config.fitness.evaluate(stats))
+                .collect();
+            model.fitness = config.aggregation.aggregate(&episodes);
+            variances += variance(&episodes);
+            let food: u32 = model.episodes().map(|stats| stats.food).sum();
+            model.score = food / episodes.len() as u32;
+        }
+        self.fitness_variance = variances / self.population.len() as f64;
+        let scores: Vec<u32> = self.population.iter().map(|model| model.score).collect();
+        let best_score = scores.iter().copied().max().unwrap_or(0);
+        let average_score = (scores.iter().sum::<u32>() as f32 / scores.len() as f32) as u32;
+        let fitness: Vec<f64> = self.population.iter().map(|model| model.fitness).collect();
+        self.fitness = (
+            fitness.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            fitness.iter().sum::<f64>() / fitness.len() as f64,
+        );
+
+        self.hall_of_fame.record(
+            config.generation,
+            &self.population,
+            config.hall_of_fame_size,
+        );
+        let islands = island::ranges(self.population.len(), config.islands);
+        self.island_scores = islands
+            .iter()
+            .map(|island| {
+                let scores = &scores[island.clone()];
+                let best = scores.iter().copied().max().unwrap_or(0);
+                (best, scores.iter().sum::<u32>() / scores.len() as u32)
+            })
+            .collect();
+        let diversity = Diversity::measure(&self.population, &config.neat);
+        if config.optimiser != Optimiser::Genetic && config.brain == BrainKind::Layered {
+            self.island_scores = vec![(best_score, average_score)];
+            self.diversity = Diversity {
+                species: 1,
+                ..diversity
+            };
+            let sampled = self.step_strategy(&fitness, config);
+            return (best_score, average_score, sampled);
+        }
+        self.strategy = None;
+
+        let interval = config.migration_interval;
+        if islands.len() > 1 && interval > 0 && (config.generation + 1).is_multiple_of(interval) {
+            island::migrate(
+                &mut self.population,
+                &islands,
+                config.migration_topology,
+                config.migration_size,
+            );
+        }
+        let fitness: Vec<f64> = self.population.iter().map(|model| model.fitness).collect();
+
+        self.speciation
+            .resize_with(islands.len(), Speciation::default);
+        let mut parents = vec![];
+        let mut species = 0;
+        for (i, island) in islands.into_iter().enumerate() {
+            let settings = config.island_settings.get(i).copied().unwrap_or_default();
+            species += self.evolve_island(i, island, &fitness, config, settings, &mut parents);
+        }
+        self.diversity = Diversity {
+            species,
+            ..diversity
+        };
+        parents.sort_unstable();
+        parents.dedup();
+        (best_score, average_score, parents.len() as u32)
+    }
+
+    /// Breeds and mutates the next generation of an island with its `settings`, keeping its
+    /// `elitism` best models. Species breed among themselves, returns how many there are
+    fn evolve_island(
+        &mut self,
+        index: usize,
+        island: Range<usize>,
+        fitness: &[f64],
+        config: &EvolutionConfig,
+        settings: IslandSettings,
+        parents: &mut Vec<usize>,
+    ) -> usize {
+        let mut ranked: Vec<usize> = island.clone().collect();
+        ranked.sort_by(|&a, &b| fitness[b].total_cmp(&fitness[a]));
+        let elites: Vec<Brain> = ranked
+            .iter()
+            .take(config.elitism)
+            .map(|&i| self.population[i].brain.clone())
+            .collect();
+
+        let offspring = island.len() - elites.len();
+        let threshold = match config.brain {
+            BrainKind::Neat => Some(config.neat.compatibility_threshold),
+            BrainKind::Layered => config.species_threshold,
+        };
+        let speciation = &mut self.speciation[index];
+        let (groups, quotas) = if let Some(threshold) = threshold {
+            let neat = &config.neat;
+            speciation.speciate(
+                &self.population[island.clone()],
+                threshold,
+                |a, b| a.distance(b, neat),
+                &mut self.rng,
+            );
+            let groups: Vec<Vec<usize>> = speciation
+                .species
+                .iter()
+                .map(|species| species.members.iter().map(|&i| island.start + i).collect())
+                .collect();
+            (
+                groups,
+                speciation.quotas(&fitness[island.clone()], offspring),
+            )
+        } else {
+            speciation.species.clear();
+            (vec![island.clone().collect()], vec![offspring])
+        };
+
+        let selection = SelectionConfig {
+            strategy: settings.selection(config.selection.strategy),
+            ..config.selection
+        };
+        let mut brains = Vec::with_capacity(offspring);
+        for (group, &quota) in groups.iter().zip(&quotas) {
+            brains.extend(self.breed(group, quota, fitness, &selection, config, parents));
+        }
+        for (model, brain) in self.population[island.clone()]
+            .iter_mut()
+            .zip(elites.iter().cloned().chain(brains))
+        {
+            model.brain = brain;
+        }
 
-        let mut models_to_merge = vec![];
-        for i in 0..(self.population.len()) {
-            if self.population[i].score >= ((1. - keep_x_best) * best_score as f64) as u32 {
-                models_to_merge.push(i);
+        let mutation = settings.mutation(config.mutation);
+        let mutation = Mutation::from_config(&mutation, island.len());
+        self.mutate_island(&mutation, config, island, elites.len());
+        groups.len()
+    }
+
+    /// Updates the evolution strategy with the fitness of its samples, then gives every model
+    /// a new sample. A strategy starts from the best brain of the generation it replaces.
+    /// Returns the number of samples the centre was moved with
+    fn step_strategy(&mut self, fitness: &[f64], config: &EvolutionConfig) -> u32 {
+        let Some(template) = self.population[0].brain.layered().cloned() else {
+            return 0;
+        };
+        let genes = template.genes().count();
+        let mut used = fitness.len() as u32;
+        let strategy = match self.strategy.take() {
+            Some(mut strategy)
+                if strategy.optimiser() == config.optimiser
+                    && strategy.centre().len() == genes
+                    && strategy.population() == self.population.len() =>
+            {
+                strategy.update(fitness, &config.strategy);
+                strategy
+            }
+            _ => {
+                used = 0;
+                let best = (0..fitness.len())
+                    .max_by(|&a, &b| fitness[a].total_cmp(&fitness[b]))
+                    .unwrap_or(0);
+                let centre = match self.population[best].brain.layered() {
+                    Some(network) if network.genes().count() == genes => {
+                        network.genes().copied().collect()
+                    }
+                    _ => template.genes().copied().collect(),
+                };
+                let Some(strategy) = EvolutionStrategy::new(
+                    config.optimiser,
+                    centre,
+                    config.strategy.sigma,
+                    self.population.len(),
+                ) else {
+                    return 0;
+                };
+                strategy
             }
+        };
+        let strategy = self.strategy.insert(strategy);
+        for (model, sample) in self
+            .population
+            .iter_mut()
+            .zip(strategy.sample(&config.strategy, &mut self.rng))
+        {
+            let mut brain = template.clone();
+            for (gene, value) in brain.genes_mut().zip(sample) {
+                *gene = value;
+            }
+            model.brain = Brain::Layered(brain);
         }
-        let brain_merged = self.merge_brains(&models_to_merge);
+        used
+    }
 
-        for i in 0..self.population.len() {
-            self.population[i].brain = brain_merged.clone();
+    /// Breeds `count` children from the models of `group`, adding the parents used to `parents`
+    fn breed(
+        &mut self,
+        group: &[usize],
+        count: usize,
+        fitness: &[f64],
+        selection: &SelectionConfig,
+        config: &EvolutionConfig,
+        parents: &mut Vec<usize>,
+    ) -> Vec<Brain> {
+        let group_fitness: Vec<f64> = group.iter().map(|&i| fitness[i]).collect();
+        let selector = selection.selector(&group_fitness);
+        if config.crossover == Crossover::MergeAll && config.brain == BrainKind::Layered {
+            let merged: Vec<usize> = match selector.pool() {
+                Some(pool) => pool.iter().map(|&i| group[i]).collect(),
+                None => (0..group.len())
+                    .filter_map(|_| selector.pick(&mut self.rng))
+                    .map(|i| group[i])
+                    .collect(),
+            };
+            parents.extend(&merged);
+            return vec![Brain::Layered(self.merge_brains(&merged)); count];
+        }
+        (0..count)
+            .map(|_| {
+                let first = group[selector.pick(&mut self.rng).unwrap()];
+                let second = group[selector.pick(&mut self.rng).unwrap()];
+                parents.extend([first, second]);
+                self.cross(first, second, fitness, config)
+            })
+            .collect()
+    }
+
+    /// NEAT genomes inherit the disjoint and excess genes of the fitter parent
+    fn cross(
+        &mut self,
+        first: usize,
+        second: usize,
+        fitness: &[f64],
+        config: &EvolutionConfig,
+    ) -> Brain {
+        match (
+            &self.population[first].brain,
+            &self.population[second].brain,
+        ) {
+            (Brain::Layered(a), Brain::Layered(b)) => {
+                Brain::Layered(config.crossover.cross(a, b, &mut self.rng))
+            }
+            (Brain::Neat(a), Brain::Neat(b)) => {
+                let (fitter, other) = if fitness[second] > fitness[first] {
+                    (b, a)
+                } else {
+                    (a, b)
+                };
+                Brain::Neat(NeatGenome::cross(fitter, other, &mut self.rng))
+            }
+            // kinds only differ when a brain of the other kind was loaded
+            (brain, _) => brain.clone(),
         }
-        let models_merged = models_to_merge.len();
-        self.mutate_population(mutation_factor);
-        (best_score, average_score, models_merged as u32)
     }
 
     fn merge_brains(&self, to_keep: &[usize]) -> NeuralNetwork {
-        let mut brain = self.population[0].brain.clone();
-        for l in 0..brain.layers.len() {
-            for i in 0..brain.layers[l].output_dim {
-                for j in 0..brain.layers[l].input_dim {
-                    let mut sum = 0.;
-                    for &k in to_keep.iter() {
-                        sum += self.population[k].brain.layers[l].weights[j][i];
-                    }
-                    brain.layers[l].weights[j][i] = sum / to_keep.len() as f64;
-                }
+        let networks: Vec<&NeuralNetwork> = to_keep
+            .iter()
+            .filter_map(|&k| self.population[k].brain.layered())
+            .collect();
+        let mut brain = networks[0].clone();
+        let mut sums = vec![0.; brain.genes().count()];
+        for network in networks.iter() {
+            for (sum, gene) in sums.iter_mut().zip(network.genes()) {
+                *sum += gene;
             }
         }
+        for (gene, sum) in brain.genes_mut().zip(sums) {
+            *gene = sum / networks.len() as f64;
+        }
+        let sigmas: Vec<f64> = networks
+            .iter()
+            .filter_map(|network| network.sigma)
+            .collect();
+        brain.sigma =
+            (!sigmas.is_empty()).then(|| sigmas.iter().sum::<f64>() / sigmas.len() as f64);
         brain
     }
 }
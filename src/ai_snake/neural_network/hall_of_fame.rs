@@ -0,0 +1,138 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    brain::Brain,
+    model::Model,
+    persistence::{BrainError, BrainFormat},
+};
+
+/// Best brains seen over the whole run, fittest first
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct HallOfFame {
+    pub entries: Vec<Champion>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Champion {
+    pub generation: u64,
+    pub score: u32,
+    pub fitness: f64,
+    pub brain: Brain,
+}
+
+impl HallOfFame {
+    /// Offers the best models of a generation, keeping at most `capacity` champions
+    pub fn record(&mut self, generation: u64, population: &[Model], capacity: usize) {
+        let mut candidates: Vec<&Model> = population.iter().collect();
+        candidates.sort_by(|a, b| b.fitness.total_cmp(&a.fitness));
+
+        for model in candidates.into_iter().take(capacity) {
+            if self
+                .entries
+                .get(capacity.saturating_sub(1))
+                .is_some_and(|last| last.fitness >= model.fitness)
+            {
+                break;
+            }
+            self.insert(Champion {
+                generation,
+                score: model.score,
+                fitness: model.fitness,
+                brain: model.brain.clone(),
+            });
+        }
+        self.entries.truncate(capacity);
+    }
+
+    /// An elite carried over unchanged only keeps its best fitness
+    fn insert(&mut self, champion: Champion) {
+        if let Some(i) = self
+            .entries
+            .iter()
+            .position(|entry| entry.brain == champion.brain)
+        {
+            if self.entries[i].fitness >= champion.fitness {
+                return;
+            }
+            self.entries.remove(i);
+        }
+        let i = self
+            .entries
+            .partition_point(|entry| entry.fitness >= champion.fitness);
+        self.entries.insert(i, champion);
+    }
+
+    /// Writes every champion to `dir`, named after its rank, generation and score
+    pub fn export(&self, dir: &Path, format: BrainFormat) -> Result<Vec<PathBuf>, BrainError> {
+        fs::create_dir_all(dir).map_err(BrainError::Io)?;
+        (0..self.entries.len())
+            .map(|rank| self.export_one(rank, dir, format))
+            .collect()
+    }
+
+    pub fn export_one(
+        &self,
+        rank: usize,
+        dir: &Path,
+        format: BrainFormat,
+    ) -> Result<PathBuf, BrainError> {
+        let champion = &self.entries[rank];
+        let extension = match format {
+            BrainFormat::Json => "json",
+            BrainFormat::Binary => "bin",
+        };
+        fs::create_dir_all(dir).map_err(BrainError::Io)?;
+        let path = dir.join(format!(
+            "{:02}_gen{}_score{}.{extension}",
+            rank + 1,
+            champion.generation,
+            champion.score
+        ));
+        champion.brain.save(&path, format)?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai_snake::neural_network::testing::population;
+
+    fn scores(hall_of_fame: &HallOfFame) -> Vec<(u64, u32)> {
+        hall_of_fame
+            .entries
+            .iter()
+            .map(|champion| (champion.generation, champion.score))
+            .collect()
+    }
+
+    #[test]
+    fn keeps_the_best_scores_ever_seen() {
+        let mut hall_of_fame = HallOfFame::default();
+        hall_of_fame.record(0, &population(&[3, 9, 1, 5]), 3);
+        assert_eq!(scores(&hall_of_fame), vec![(0, 9), (0, 5), (0, 3)]);
+
+        hall_of_fame.record(1, &population(&[8, 4, 2, 0]), 3);
+        assert_eq!(scores(&hall_of_fame), vec![(0, 9), (1, 8), (0, 5)]);
+    }
+
+    #[test]
+    fn same_brain_is_listed_once() {
+        let mut hall_of_fame = HallOfFame::default();
+        let mut models = population(&[3, 9]);
+        hall_of_fame.record(0, &models, 5);
+
+        (models[1].score, models[1].fitness) = (4, 4.);
+        hall_of_fame.record(1, &models, 5);
+        assert_eq!(scores(&hall_of_fame), vec![(0, 9), (0, 3)]);
+
+        (models[1].score, models[1].fitness) = (12, 12.);
+        hall_of_fame.record(2, &models, 5);
+        assert_eq!(scores(&hall_of_fame), vec![(2, 12), (0, 3)]);
+    }
+}
@@ -0,0 +1,330 @@
+use std::{fmt, fs, io, path::Path};
+
+use rand::{seq::SliceRandom, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::snake_core::universe::Direction;
+
+use super::{
+    architecture::Architecture,
+    backprop::{Adam, Gradients, Trace},
+    model::ActionSpace,
+    sensor::SensorConfig,
+    ActivationFunction, NeuralNetwork,
+};
+
+/// Version written in every dataset file, bumped when the layout changes
+pub const DATASET_FORMAT_VERSION: u32 = 1;
+
+/// Moves of human players, with what a snake would have seen before each of them
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Dataset {
+    /// Observations are scaled by the vision range, it should match the one of the training
+    pub vision_range: i64,
+    /// Sensors the observations were made with, datasets without them used the rays
+    #[serde(default)]
+    pub sensors: SensorConfig,
+    pub demonstrations: Vec<Demonstration>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Demonstration {
+    /// Output of [`Sensors::observe`](super::sensor::Sensors::observe)
+    pub observation: Vec<f64>,
+    pub direction: Direction,
+    /// Direction of the snake before the move, datasets without it only train absolute actions
+    #[serde(default)]
+    pub heading: Option<Direction>,
+}
+
+#[derive(Debug)]
+pub enum DatasetError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    UnsupportedVersion(u32),
+    ObservationSize {
+        demonstration: usize,
+        expected: usize,
+        found: usize,
+    },
+}
+
+#[derive(Serialize)]
+struct DatasetFileRef<'a> {
+    version: u32,
+    dataset: &'a Dataset,
+}
+
+#[derive(Deserialize)]
+struct DatasetFile {
+    version: u32,
+    dataset: Dataset,
+}
+
+/// Settings of the supervised trainer cloning the behaviour of a dataset
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImitationConfig {
+    /// Passes over the whole dataset
+    pub epochs: usize,
+    /// Demonstrations per gradient step
+    pub batch_size: usize,
+    pub learning_rate: f64,
+}
+
+/// Measured over the demonstrations of one epoch
+#[derive(Clone, Debug)]
+pub struct ImitationReport {
+    pub epoch: usize,
+    /// Mean cross-entropy
+    pub loss: f64,
+    /// Fraction of the demonstrations whose action has the highest output
+    pub accuracy: f64,
+}
+
+impl Default for ImitationConfig {
+    fn default() -> Self {
+        ImitationConfig {
+            epochs: 50,
+            batch_size: 32,
+            learning_rate: 0.001,
+        }
+    }
+}
+
+impl Dataset {
+    pub fn new(vision_range: i64, sensors: SensorConfig) -> Self {
+        Dataset {
+            vision_range,
+            sensors,
+            demonstrations: vec![],
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), DatasetError> {
+        let json = serde_json::to_string(&DatasetFileRef {
+            version: DATASET_FORMAT_VERSION,
+            dataset: self,
+        })
+        .map_err(DatasetError::Json)?;
+        fs::write(path, json).map_err(DatasetError::Io)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, DatasetError> {
+        let json = fs::read_to_string(path).map_err(DatasetError::Io)?;
+        let file: DatasetFile = serde_json::from_str(&json).map_err(DatasetError::Json)?;
+        if file.version != DATASET_FORMAT_VERSION {
+            return Err(DatasetError::UnsupportedVersion(file.version));
+        }
+        file.dataset.validate()?;
+        Ok(file.dataset)
+    }
+
+    /// Every observation must fit the input of the brains
+    pub fn validate(&self) -> Result<(), DatasetError> {
+        let expected = self.sensors.input_size();
+        for (i, demonstration) in self.demonstrations.iter().enumerate() {
+            if demonstration.observation.len() != expected {
+                return Err(DatasetError::ObservationSize {
+                    demonstration: i,
+                    expected,
+                    found: demonstration.observation.len(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Demonstration {
+    /// Output of `actions` moving the snake like the player did, `None` when relative actions
+    /// cannot, without heading or when the player turned back
+    pub fn action(&self, actions: ActionSpace) -> Option<usize> {
+        match (actions, &self.heading) {
+            (_, Some(heading)) => actions.action(heading, &self.direction),
+            (ActionSpace::Absolute, None) => actions.action(&self.direction, &self.direction),
+            (ActionSpace::Relative, None) => None,
+        }
+    }
+}
+
+/// Fits a network to `dataset` by minimising the cross-entropy between the softmax of its
+/// outputs and the recorded actions. The output activation is replaced by the identity,
+/// which leaves the action picked by the snakes unchanged. Demonstrations `actions` cannot
+/// reproduce are left out
+pub fn clone_behaviour(
+    architecture: &Architecture,
+    dataset: &Dataset,
+    actions: ActionSpace,
+    config: &ImitationConfig,
+    seed: u64,
+    mut on_epoch: impl FnMut(&ImitationReport),
+) -> NeuralNetwork {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let architecture = Architecture {
+        output_activation: ActivationFunction::Identity,
+        ..architecture.clone()
+    };
+    let mut network =
+        architecture.build_from(dataset.sensors.input_shape(), actions.size(), &mut rng);
+    let mut adam = Adam::new(&network, config.learning_rate);
+    let mut gradients = Gradients::zeros(&network);
+    let mut trace = Trace::default();
+
+    // demonstration and action of each target
+    let targets: Vec<(usize, usize)> = dataset
+        .demonstrations
+        .iter()
+        .enumerate()
+        .filter_map(|(i, demonstration)| Some((i, demonstration.action(actions)?)))
+        .collect();
+    let mut order: Vec<usize> = (0..targets.len()).collect();
+    for epoch in 0..config.epochs {
+        order.shuffle(&mut rng);
+        let (mut loss, mut correct) = (0., 0);
+        for batch in order.chunks(config.batch_size.max(1)) {
+            gradients.clear();
+            for &k in batch {
+                let (i, target) = targets[k];
+                network.forward_trace(&dataset.demonstrations[i].observation, &mut trace);
+                let mut probabilities = trace.output().to_vec();
+                ActivationFunction::Softmax.apply(&mut probabilities);
+                let predicted = (0..probabilities.len())
+                    .max_by(|&a, &b| probabilities[a].total_cmp(&probabilities[b]))
+                    .unwrap_or(0);
+                correct += usize::from(predicted == target);
+                loss -= probabilities[target].max(f64::MIN_POSITIVE).ln();
+                // the gradient of the cross-entropy of a softmax is the probabilities minus the target
+                probabilities[target] -= 1.;
+                network.backward(&trace, &probabilities, &mut gradients);
+            }
+            gradients.scale(1. / batch.len() as f64);
+            adam.step(&mut network, &gradients);
+        }
+        let count = targets.len().max(1) as f64;
+        on_epoch(&ImitationReport {
+            epoch,
+            loss: loss / count,
+            accuracy: correct as f64 / count,
+        });
+    }
+    network
+}
+
+impl fmt::Display for DatasetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DatasetError::Io(e) => write!(f, "could not access dataset: {e}"),
+            DatasetError::Json(e) => write!(f, "invalid dataset: {e}"),
+            DatasetError::UnsupportedVersion(v) => write!(
+                f,
+                "unsupported dataset format version {v} (expected {DATASET_FORMAT_VERSION})"
+            ),
+            DatasetError::ObservationSize {
+                demonstration,
+                expected,
+                found,
+            } => write!(
+                f,
+                "demonstration {demonstration}: {found} inputs instead of {expected}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DatasetError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Heads for the closest food, the odd inputs of each pair
+    fn dataset() -> Dataset {
+        let directions = [
+            Direction::Up,
+            Direction::Down,
+            Direction::Left,
+            Direction::Right,
+        ];
+        let mut dataset = Dataset::new(8, SensorConfig::default());
+        for (i, direction) in directions.into_iter().cycle().take(40).enumerate() {
+            let mut observation = vec![0.; dataset.sensors.input_size()];
+            let action = ActionSpace::Absolute
+                .action(&direction, &direction)
+                .unwrap();
+            observation[2 * action + 1] = 0.5 + (i % 5) as f64 / 10.;
+            dataset.demonstrations.push(Demonstration {
+                observation,
+                direction,
+                heading: None,
+            });
+        }
+        dataset
+    }
+
+    #[test]
+    fn clones_a_separable_behaviour() {
+        let config = ImitationConfig {
+            epochs: 100,
+            batch_size: 8,
+            learning_rate: 0.01,
+        };
+        let mut last = None;
+        let actions = ActionSpace::Absolute;
+        let network = clone_behaviour(
+            &Architecture::default(),
+            &dataset(),
+            actions,
+            &config,
+            0,
+            |r| last = Some(r.clone()),
+        );
+        let report = last.unwrap();
+        assert_eq!(report.accuracy, 1.);
+        assert!(report.loss < 0.1);
+
+        let mut trace = Trace::default();
+        let demonstration = &dataset().demonstrations[1];
+        network.forward_trace(&demonstration.observation, &mut trace);
+        let output = trace.output();
+        let best = (0..output.len())
+            .max_by(|&a, &b| output[a].total_cmp(&output[b]))
+            .unwrap();
+        assert_eq!(Some(best), demonstration.action(actions));
+    }
+
+    #[test]
+    fn relative_actions_need_the_heading() {
+        let mut demonstration = dataset().demonstrations.remove(2);
+        assert_eq!(demonstration.action(ActionSpace::Absolute), Some(2));
+        assert_eq!(demonstration.action(ActionSpace::Relative), None);
+        // turning left from up
+        demonstration.heading = Some(Direction::Up);
+        assert_eq!(demonstration.action(ActionSpace::Relative), Some(0));
+        demonstration.heading = Some(Direction::Right);
+        assert_eq!(demonstration.action(ActionSpace::Relative), None);
+    }
+
+    #[test]
+    fn dataset_round_trips() {
+        let path = std::env::temp_dir().join(format!("dataset_{}.json", std::process::id()));
+        let dataset = dataset();
+        dataset.save(&path).unwrap();
+        let loaded = Dataset::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), dataset);
+    }
+
+    #[test]
+    fn rejects_observations_of_another_size() {
+        let mut dataset = dataset();
+        dataset.demonstrations[3].observation.pop();
+        assert!(matches!(
+            dataset.validate(),
+            Err(DatasetError::ObservationSize {
+                demonstration: 3,
+                ..
+            })
+        ));
+    }
+}
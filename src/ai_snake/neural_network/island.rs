@@ -0,0 +1,154 @@
+use std::{fmt, ops::Range, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use super::{model::Model, mutation::MutationConfig, selection::SelectionStrategy};
+
+/// Islands receiving the best models of each island when migrating
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum MigrationTopology {
+    /// Island `i` sends to island `i + 1`, the last one to the first
+    #[default]
+    Ring,
+    /// Every island sends to every other island
+    FullyConnected,
+}
+
+/// Settings of one island overriding the ones of the whole population
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IslandSettings {
+    pub mutation_factor: Option<f64>,
+    pub selection: Option<SelectionStrategy>,
+}
+
+impl MigrationTopology {
+    pub const ALL: [MigrationTopology; 2] =
+        [MigrationTopology::Ring, MigrationTopology::FullyConnected];
+
+    pub fn destinations(&self, island: usize, islands: usize) -> Vec<usize> {
+        match self {
+            MigrationTopology::Ring if islands > 1 => vec![(island + 1) % islands],
+            MigrationTopology::Ring => vec![],
+            MigrationTopology::FullyConnected => (0..islands).filter(|&i| i != island).collect(),
+        }
+    }
+}
+
+impl IslandSettings {
+    /// Selection of the whole population as seen by this island
+    pub fn selection(&self, selection: SelectionStrategy) -> SelectionStrategy {
+        self.selection.unwrap_or(selection)
+    }
+
+    /// Mutation of the whole population as seen by this island
+    pub fn mutation(&self, mutation: MutationConfig) -> MutationConfig {
+        MutationConfig {
+            factor: self.mutation_factor.unwrap_or(mutation.factor),
+            ..mutation
+        }
+    }
+}
+
+/// Splits `population` models into at most `islands` contiguous ranges whose sizes differ by one at most
+pub fn ranges(population: usize, islands: usize) -> Vec<Range<usize>> {
+    let islands = islands.clamp(1, population.max(1));
+    (0..islands)
+        .map(|i| i * population / islands..(i + 1) * population / islands)
+        .collect()
+}
+
+/// Copies the `count` fittest models of every island over the least fit models of its
+/// destinations, fitness included so migrants compete in the selection.
+/// At most half of an island is replaced
+pub fn migrate(
+    population: &mut [Model],
+    ranges: &[Range<usize>],
+    topology: MigrationTopology,
+    count: usize,
+) {
+    let ranked = |population: &[Model], range: &Range<usize>| {
+        let mut ranked: Vec<usize> = range.clone().collect();
+        ranked.sort_by(|&a, &b| population[b].fitness.total_cmp(&population[a].fitness));
+        ranked
+    };
+    let mut arrivals = vec![vec![]; ranges.len()];
+    for (island, range) in ranges.iter().enumerate() {
+        for &i in ranked(population, range).iter().take(count) {
+            for destination in topology.destinations(island, ranges.len()) {
+                let model = &population[i];
+                arrivals[destination].push((model.brain.clone(), model.score, model.fitness));
+            }
+        }
+    }
+    for (range, arrivals) in ranges.iter().zip(arrivals) {
+        let worst = ranked(population, range).into_iter().rev();
+        for (i, (brain, score, fitness)) in worst.zip(arrivals).take(range.len() / 2) {
+            population[i].brain = brain;
+            population[i].score = score;
+            population[i].fitness = fitness;
+        }
+    }
+}
+
+impl fmt::Display for MigrationTopology {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MigrationTopology::Ring => write!(f, "ring"),
+            MigrationTopology::FullyConnected => write!(f, "fully connected"),
+        }
+    }
+}
+
+/// Parses the displayed name, with dashes instead of spaces
+impl FromStr for MigrationTopology {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        MigrationTopology::ALL
+            .into_iter()
+            .find(|topology| topology.to_string().replace(' ', "-") == s)
+            .ok_or(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai_snake::neural_network::testing::population;
+
+    #[test]
+    fn ranges_cover_the_population() {
+        assert_eq!(ranges(10, 3), vec![0..3, 3..6, 6..10]);
+        assert_eq!(ranges(2, 5), vec![0..1, 1..2]);
+        assert_eq!(ranges(7, 0), vec![0..7]);
+    }
+
+    #[test]
+    fn ring_sends_the_best_over_the_worst() {
+        let mut models = population(&[1, 9, 5, 2, 0, 4]);
+        let ranges = ranges(6, 2);
+        let best_of_first = models[1].brain.clone();
+        let best_of_second = models[5].brain.clone();
+        migrate(&mut models, &ranges, MigrationTopology::Ring, 1);
+
+        let scores: Vec<u32> = models.iter().map(|model| model.score).collect();
+        assert_eq!(scores, vec![4, 9, 5, 2, 9, 4]);
+        assert!(models[4].brain == best_of_first);
+        assert!(models[0].brain == best_of_second);
+    }
+
+    #[test]
+    fn fully_connected_reaches_every_island() {
+        let mut models = population(&[1, 7, 0, 8, 2, 6]);
+        migrate(
+            &mut models,
+            &ranges(6, 3),
+            MigrationTopology::FullyConnected,
+            1,
+        );
+        // islands of two only give up one model
+        let scores: Vec<u32> = models.iter().map(|model| model.score).collect();
+        assert_eq!(scores, vec![8, 7, 7, 8, 7, 6]);
+    }
+}
@@ -1,32 +1,258 @@
+pub mod architecture;
+pub mod backprop;
+pub mod batch;
+pub mod brain;
+pub mod crossover;
+pub mod diversity;
+pub mod dqn;
+pub mod evolution_strategy;
+pub mod fitness;
 pub mod genetic;
+pub mod hall_of_fame;
+pub mod imitation;
+pub mod island;
 pub mod model;
+pub mod mutation;
+pub mod neat;
+pub mod persistence;
+pub mod selection;
+pub mod sensor;
+pub mod speciation;
+#[cfg(test)]
+mod testing;
 use std::fmt::{self};
 
-#[derive(Clone)]
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use batch::Scalar;
+use mutation::{gaussian, Mutation};
+
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct NeuralNetwork {
     pub layers: Vec<Layer>,
+    /// Mutation step size evolved along the weights by the self-adaptive schedule
+    #[serde(default)]
+    pub sigma: Option<f64>,
 }
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 
 pub struct Layer {
     pub input_dim: usize,
     pub output_dim: usize,
-    pub weights: Vec<Vec<f64>>,
+    /// One row per output of dense layers, per filter of convolutions: the weights feeding
+    /// output `o` of a dense layer are contiguous
+    pub weights: Vec<f64>,
     biases: Vec<f64>,
     activation: ActivationFunction,
+    /// Disabled biases are neither added in `forward` nor mutated
+    pub use_bias: bool,
+    #[serde(default)]
+    pub kind: LayerKind,
+}
+
+/// `channels` planes of `height x width` values, stored plane after plane and row after row
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Shape {
+    pub channels: usize,
+    pub height: usize,
+    pub width: usize,
+}
+
+/// How a layer maps its input to its output, every kind reads and writes flat vectors
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum LayerKind {
+    /// Every output is a weighted sum of every input
+    #[default]
+    Dense,
+    /// `filters` kernels of `kernel x kernel` sliding over the planes of `input`, zero padded
+    /// so that odd kernels keep the size of the planes
+    Conv2d {
+        input: Shape,
+        filters: usize,
+        kernel: usize,
+    },
+    /// Maximum of each `size x size` block of each plane of `input`
+    MaxPool { input: Shape, size: usize },
+    /// Hands planes over to dense layers, the values are already stored flat
+    Flatten,
 }
-#[derive(Clone)]
-#[allow(dead_code)]
+
+/// Buffers reused by [`NeuralNetwork::forward_with`] so a forward pass does not allocate
+#[derive(Clone, Default)]
+pub struct Scratch {
+    current: Vec<f64>,
+    next: Vec<f64>,
+}
+/// New variants go last, binary brain files store the variant index
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum ActivationFunction {
     Relu,
     Sigmoid,
     Softmax,
     Identity,
+    Tanh,
+    LeakyRelu,
+    Elu,
+    Swish,
+    Step,
+}
+
+const LEAKY_RELU_SLOPE: f64 = 0.01;
+const ELU_ALPHA: f64 = 1.0;
+
+impl ActivationFunction {
+    pub const ALL: [ActivationFunction; 9] = [
+        ActivationFunction::Relu,
+        ActivationFunction::Sigmoid,
+        ActivationFunction::Softmax,
+        ActivationFunction::Identity,
+        ActivationFunction::Tanh,
+        ActivationFunction::LeakyRelu,
+        ActivationFunction::Elu,
+        ActivationFunction::Swish,
+        ActivationFunction::Step,
+    ];
+
+    pub fn apply<T: Scalar>(&self, output: &mut [T]) {
+        match self {
+            ActivationFunction::Relu => output.iter_mut().for_each(|x| *x = x.max(T::ZERO)),
+            ActivationFunction::Sigmoid => output.iter_mut().for_each(|x| *x = sigmoid(*x)),
+            ActivationFunction::Softmax => {
+                // shifting by the max keeps exp from overflowing without changing the result
+                let max = output.iter().copied().fold(T::NEG_INFINITY, T::max);
+                let mut sum = T::ZERO;
+                output.iter_mut().for_each(|x| {
+                    *x = (*x - max).exp();
+                    sum += *x;
+                });
+                output.iter_mut().for_each(|x| *x /= sum);
+            }
+            ActivationFunction::Identity => (),
+            ActivationFunction::Tanh => output.iter_mut().for_each(|x| *x = x.tanh()),
+            ActivationFunction::LeakyRelu => output.iter_mut().for_each(|x| {
+                if *x < T::ZERO {
+                    *x *= T::from_f64(LEAKY_RELU_SLOPE)
+                }
+            }),
+            ActivationFunction::Elu => output.iter_mut().for_each(|x| {
+                if *x < T::ZERO {
+                    *x = T::from_f64(ELU_ALPHA) * x.exp_m1()
+                }
+            }),
+            ActivationFunction::Swish => output.iter_mut().for_each(|x| *x *= sigmoid(*x)),
+            ActivationFunction::Step => output
+                .iter_mut()
+                .for_each(|x| *x = if *x > T::ZERO { T::ONE } else { T::ZERO }),
+        }
+    }
+}
+
+impl Shape {
+    pub fn len(&self) -> usize {
+        self.channels * self.height * self.width
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A flat vector, as channels of a single value
+impl From<usize> for Shape {
+    fn from(len: usize) -> Self {
+        Shape {
+            channels: len,
+            height: 1,
+            width: 1,
+        }
+    }
+}
+
+impl LayerKind {
+    /// Shape of the output of a layer of this kind reading `input_dim` values
+    pub fn output_shape(&self, input_dim: usize) -> Shape {
+        match *self {
+            LayerKind::Dense | LayerKind::Flatten => input_dim.into(),
+            LayerKind::Conv2d {
+                input,
+                filters,
+                kernel,
+            } => {
+                let pad = kernel / 2;
+                Shape {
+                    channels: filters,
+                    height: (input.height + 2 * pad + 1).saturating_sub(kernel),
+                    width: (input.width + 2 * pad + 1).saturating_sub(kernel),
+                }
+            }
+            LayerKind::MaxPool { input, size } => Shape {
+                channels: input.channels,
+                height: input.height / size.max(1),
+                width: input.width / size.max(1),
+            },
+        }
+    }
+}
+
+/// Index in a filter row and in the input of every kernel tap of output `(y, x)`
+/// of a convolution, taps falling in the padding are skipped
+fn conv_taps(
+    input: Shape,
+    kernel: usize,
+    y: usize,
+    x: usize,
+) -> impl Iterator<Item = (usize, usize)> {
+    let pad = kernel / 2;
+    (0..input.channels).flat_map(move |c| {
+        (0..kernel).flat_map(move |ky| {
+            (0..kernel).filter_map(move |kx| {
+                let iy = (y + ky).checked_sub(pad).filter(|&iy| iy < input.height)?;
+                let ix = (x + kx).checked_sub(pad).filter(|&ix| ix < input.width)?;
+                Some((
+                    (c * kernel + ky) * kernel + kx,
+                    (c * input.height + iy) * input.width + ix,
+                ))
+            })
+        })
+    })
+}
+
+/// Index in the input of the largest value of the block pooled into output `(c, y, x)`
+fn pool_argmax(
+    input: Shape,
+    size: usize,
+    values: &[f64],
+    (c, y, x): (usize, usize, usize),
+) -> usize {
+    (0..size)
+        .flat_map(|dy| (0..size).map(move |dx| (dy, dx)))
+        .map(|(dy, dx)| (c * input.height + y * size + dy) * input.width + x * size + dx)
+        .max_by(|&a, &b| values[a].total_cmp(&values[b]))
+        .unwrap_or(0)
+}
+
+fn sigmoid<T: Scalar>(x: T) -> T {
+    T::ONE / (T::ONE + (-x).exp())
+}
+
+/// Scales the output so its absolute values sum to 1
+fn normalize<T: Scalar>(output: &mut [T]) {
+    let mut sum = T::ZERO;
+    for x in output.iter() {
+        sum += x.abs();
+    }
+    if sum > T::ZERO {
+        output.iter_mut().for_each(|x| *x /= sum);
+    }
 }
 
 impl NeuralNetwork {
     pub fn new() -> Self {
-        NeuralNetwork { layers: vec![] }
+        NeuralNetwork {
+            layers: vec![],
+            sigma: None,
+        }
     }
 
     pub fn add_layer(&mut self, layer: Layer) -> &mut Self {
@@ -34,36 +260,67 @@ impl NeuralNetwork {
         self
     }
 
-    pub fn forward(&self, mut input: Vec<f64>) -> Vec<f64> {
-        for layer in &self.layers {
-            input = layer.forward(input);
-        }
-        self.normalize(input)
+    pub fn input_dim(&self) -> usize {
+        self.layers.first().map_or(0, |layer| layer.input_dim)
     }
 
-    fn normalize(&self, v: Vec<f64>) -> Vec<f64> {
-        let mut sum = 0.;
-        for x in v.iter() {
-            sum += x.abs();
-        }
-        if sum > 0.0 {
-            v.into_iter().map(|x| x / sum).collect()
-        } else {
-            v
+    pub fn output_dim(&self) -> usize {
+        self.layers.last().map_or(0, |layer| layer.output_dim)
+    }
+
+    /// Weights then biases of each layer, the genome recombined by crossovers
+    pub fn genes(&self) -> impl Iterator<Item = &f64> {
+        self.layers
+            .iter()
+            .flat_map(|layer| layer.weights.iter().chain(&layer.biases))
+    }
+
+    pub fn genes_mut(&mut self) -> impl Iterator<Item = &mut f64> {
+        self.layers
+            .iter_mut()
+            .flat_map(|layer| layer.weights.iter_mut().chain(&mut layer.biases))
+    }
+
+    pub fn forward(&self, input: Vec<f64>) -> Vec<f64> {
+        self.forward_with(&input, &mut Scratch::default()).to_vec()
+    }
+
+    /// Same as [`NeuralNetwork::forward`], reusing the buffers of `scratch`
+    pub fn forward_with<'a>(&self, input: &[f64], scratch: &'a mut Scratch) -> &'a mut [f64] {
+        scratch.current.clear();
+        scratch.current.extend_from_slice(input);
+        for layer in &self.layers {
+            layer.forward_into(&scratch.current, &mut scratch.next);
+            std::mem::swap(&mut scratch.current, &mut scratch.next);
         }
+        normalize(&mut scratch.current);
+        &mut scratch.current
     }
 
-    pub fn mutate(&mut self, mutation_factor: f64) {
+    pub fn mutate(&mut self, mutation: &Mutation, rng: &mut impl Rng) {
+        let sigma = if mutation.self_adaptive {
+            // learning rate of sigma, 1 / sqrt(n) as usual in evolution strategies
+            let tau = 1. / (self.genes().count().max(1) as f64).sqrt();
+            let sigma = self.sigma.unwrap_or(mutation.sigma) * (tau * gaussian(rng)).exp();
+            self.sigma = Some(sigma);
+            sigma
+        } else {
+            mutation.sigma
+        };
         for layer in &mut self.layers {
-            for i in 0..layer.output_dim {
-                for j in 0..layer.input_dim {
-                    let rand = rand::random::<f64>();
-                    if rand < mutation_factor {
-                        layer.weights[j][i] = rand::random::<f64>() * 2. - 1.;
+            let row_len = layer.row_len();
+            for i in 0..layer.rows() {
+                for j in 0..row_len {
+                    let rand = rng.gen::<f64>();
+                    if rand < mutation.rate {
+                        let w = &mut layer.weights[i * row_len + j];
+                        *w = mutation.operator.apply(*w, sigma, rng);
                     }
-                    let rand = rand::random::<f64>();
-                    if rand < mutation_factor {
-                        layer.biases[i] = rand::random::<f64>() * 2. - 1.;
+                }
+                if layer.use_bias {
+                    let rand = rng.gen::<f64>();
+                    if rand < mutation.rate {
+                        layer.biases[i] = mutation.operator.apply(layer.biases[i], sigma, rng);
                     }
                 }
             }
@@ -75,7 +332,7 @@ impl Layer {
     pub fn new(
         input_dim: usize,
         output_dim: usize,
-        weights: Vec<Vec<f64>>,
+        weights: Vec<f64>,
         biases: Vec<f64>,
         activation: ActivationFunction,
     ) -> Self {
@@ -85,61 +342,189 @@ impl Layer {
             weights,
             biases,
             activation,
+            use_bias: true,
+            kind: LayerKind::Dense,
         }
     }
-    pub fn forward(&self, input: Vec<f64>) -> Vec<f64> {
-        if input.is_empty() {
-            return vec![];
+
+    /// `weights` holds `channels x kernel x kernel` values per filter, `biases` one per filter
+    pub fn conv2d(
+        input: Shape,
+        filters: usize,
+        kernel: usize,
+        weights: Vec<f64>,
+        biases: Vec<f64>,
+        activation: ActivationFunction,
+    ) -> Self {
+        let kind = LayerKind::Conv2d {
+            input,
+            filters,
+            kernel,
+        };
+        Layer {
+            input_dim: input.len(),
+            output_dim: kind.output_shape(input.len()).len(),
+            weights,
+            biases,
+            activation,
+            use_bias: true,
+            kind,
         }
-        let mut output = vec![0.0; self.output_dim];
+    }
 
-        (0..self.output_dim).for_each(|j| {
-            (0..self.input_dim).for_each(|k| {
-                output[j] += input[k] * self.weights[k][j]; // + self.biases[j];
-            });
-        });
+    pub fn max_pool(input: Shape, size: usize) -> Self {
+        let kind = LayerKind::MaxPool { input, size };
+        Layer {
+            input_dim: input.len(),
+            output_dim: kind.output_shape(input.len()).len(),
+            weights: vec![],
+            biases: vec![],
+            activation: ActivationFunction::Identity,
+            use_bias: false,
+            kind,
+        }
+    }
 
-        match self.activation {
-            ActivationFunction::Relu => {
-                (0..self.output_dim).for_each(|i| {
-                    if output[i] < 0.0 {
-                        output[i] = 0.0;
-                    }
-                });
+    pub fn flatten(len: usize) -> Self {
+        Layer {
+            input_dim: len,
+            output_dim: len,
+            weights: vec![],
+            biases: vec![],
+            activation: ActivationFunction::Identity,
+            use_bias: false,
+            kind: LayerKind::Flatten,
+        }
+    }
+
+    /// Rows of weights, each with its own bias: outputs of dense layers, filters of convolutions
+    pub fn rows(&self) -> usize {
+        match self.kind {
+            LayerKind::Dense => self.output_dim,
+            LayerKind::Conv2d { filters, .. } => filters,
+            LayerKind::MaxPool { .. } | LayerKind::Flatten => 0,
+        }
+    }
+
+    pub fn row_len(&self) -> usize {
+        match self.kind {
+            LayerKind::Dense => self.input_dim,
+            LayerKind::Conv2d { input, kernel, .. } => input.channels * kernel * kernel,
+            LayerKind::MaxPool { .. } | LayerKind::Flatten => 0,
+        }
+    }
+
+    pub fn output_shape(&self) -> Shape {
+        match self.kind {
+            LayerKind::Dense | LayerKind::Flatten => self.output_dim.into(),
+            kind => kind.output_shape(self.input_dim),
+        }
+    }
+
+    pub fn with_bias(mut self, use_bias: bool) -> Self {
+        self.use_bias = use_bias;
+        self
+    }
+
+    pub fn weight(&self, input: usize, output: usize) -> f64 {
+        self.weights[output * self.input_dim + input]
+    }
+
+    pub fn biases(&self) -> &[f64] {
+        &self.biases
+    }
+
+    pub fn activation(&self) -> ActivationFunction {
+        self.activation
+    }
+
+    pub fn forward(&self, input: Vec<f64>) -> Vec<f64> {
+        let mut output = vec![];
+        self.forward_into(&input, &mut output);
+        output
+    }
+
+    /// Writes the output of the layer into `output`, reusing its allocation
+    pub fn forward_into(&self, input: &[f64], output: &mut Vec<f64>) {
+        self.weighted_sum(input, output);
+        self.activation.apply(output);
+    }
+
+    /// Output of the layer before its activation
+    fn weighted_sum(&self, input: &[f64], output: &mut Vec<f64>) {
+        output.clear();
+        if input.is_empty() {
+            return;
+        }
+        match self.kind {
+            LayerKind::Dense => {
+                for (o, row) in self.weights.chunks_exact(self.input_dim).enumerate() {
+                    let bias = if self.use_bias { self.biases[o] } else { 0.0 };
+                    output.push(
+                        row.iter()
+                            .zip(input)
+                            .fold(bias, |acc, (&w, &x)| acc + x * w),
+                    );
+                }
             }
-            ActivationFunction::Sigmoid => {
-                (0..self.output_dim).for_each(|i| {
-                    output[i] = 1.0 / (1.0 + (-output[i]).exp());
-                });
+            LayerKind::Conv2d {
+                input: shape,
+                kernel,
+                ..
+            } => {
+                let out = self.output_shape();
+                for (f, row) in self.weights.chunks_exact(self.row_len()).enumerate() {
+                    let bias = if self.use_bias { self.biases[f] } else { 0.0 };
+                    for y in 0..out.height {
+                        for x in 0..out.width {
+                            output.push(
+                                conv_taps(shape, kernel, y, x)
+                                    .fold(bias, |acc, (w, i)| acc + input[i] * row[w]),
+                            );
+                        }
+                    }
+                }
             }
-            ActivationFunction::Softmax => {
-                let mut sum = 0.0;
-                (0..self.output_dim).for_each(|i| {
-                    output[i] = (-output[i]).exp();
-                    sum += output[i];
-                });
-                (0..self.output_dim).for_each(|i| {
-                    output[i] /= sum;
-                })
+            LayerKind::MaxPool { input: shape, size } => {
+                let out = self.output_shape();
+                for c in 0..out.channels {
+                    for y in 0..out.height {
+                        for x in 0..out.width {
+                            output.push(input[pool_argmax(shape, size, input, (c, y, x))]);
+                        }
+                    }
+                }
             }
-            ActivationFunction::Identity => (),
+            LayerKind::Flatten => output.extend_from_slice(input),
         }
-        output
     }
 }
 impl fmt::Display for Layer {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         writeln!(
             f,
-            "Layer : input_dim={}, output_dim={}",
-            self.input_dim, self.output_dim
+            "Layer : {}, input_dim={}, output_dim={}, activation={}",
+            self.kind, self.input_dim, self.output_dim, self.activation
         )?;
 
-        for i in 0..self.weights.len() {
-            for j in 0..self.weights[i].len() {
-                write!(f, "{:.2}, ", self.weights[i][j])?;
+        for row in self.weights.chunks(self.row_len().max(1)) {
+            for w in row {
+                write!(f, "{:.2}, ", w)?;
+            }
+            writeln!(f)?;
+        }
+        // pooling and flattening have nothing to learn
+        if self.rows() == 0 {
+            return Ok(());
+        }
+        if self.use_bias {
+            write!(f, "Biases: ")?;
+            for b in self.biases.iter() {
+                write!(f, "{:.2}, ", b)?;
             }
             writeln!(f)?;
+        } else {
+            writeln!(f, "Biases: disabled")?;
         }
         Ok(())
     }
@@ -151,10 +536,40 @@ impl fmt::Display for ActivationFunction {
             ActivationFunction::Relu => write!(f, "relu"),
             ActivationFunction::Sigmoid => write!(f, "sigmoid"),
             ActivationFunction::Softmax => write!(f, "softmax"),
-            ActivationFunction::Identity => Ok(()),
+            ActivationFunction::Identity => write!(f, "identity"),
+            ActivationFunction::Tanh => write!(f, "tanh"),
+            ActivationFunction::LeakyRelu => write!(f, "leaky relu"),
+            ActivationFunction::Elu => write!(f, "elu"),
+            ActivationFunction::Swish => write!(f, "swish"),
+            ActivationFunction::Step => write!(f, "step"),
         }
     }
 }
+
+impl fmt::Display for Shape {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{}x{}", self.channels, self.height, self.width)
+    }
+}
+
+impl fmt::Display for LayerKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LayerKind::Dense => write!(f, "dense"),
+            LayerKind::Conv2d {
+                input,
+                filters,
+                kernel,
+            } => write!(
+                f,
+                "conv2d of {filters} {kernel}x{kernel} filters over {input}"
+            ),
+            LayerKind::MaxPool { input, size } => write!(f, "max pool {size}x{size} over {input}"),
+            LayerKind::Flatten => write!(f, "flatten"),
+        }
+    }
+}
+
 impl fmt::Display for NeuralNetwork {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         for layer in self.layers.iter() {
@@ -163,3 +578,232 @@ impl fmt::Display for NeuralNetwork {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer(activation: ActivationFunction) -> Layer {
+        // weights[output][input]
+        Layer::new(2, 2, vec![1., 3., 2., 4.], vec![0.5, -1.], activation)
+    }
+
+    #[test]
+    fn forward_adds_biases() {
+        let output = layer(ActivationFunction::Identity).forward(vec![1., 1.]);
+        assert_eq!(output, vec![4.5, 5.]);
+    }
+
+    #[test]
+    fn forward_ignores_disabled_biases() {
+        let output = layer(ActivationFunction::Identity)
+            .with_bias(false)
+            .forward(vec![1., 1.]);
+        assert_eq!(output, vec![4., 6.]);
+    }
+
+    #[test]
+    fn biases_go_through_activation() {
+        let output = layer(ActivationFunction::Relu).forward(vec![-1., 0.]);
+        assert_eq!(output, vec![0., 0.]);
+
+        let output = layer(ActivationFunction::Relu).forward(vec![0., 0.]);
+        assert_eq!(output, vec![0.5, 0.]);
+    }
+
+    #[test]
+    fn network_forward_normalizes_output() {
+        let mut network = NeuralNetwork::new();
+        network
+            .add_layer(layer(ActivationFunction::Identity))
+            .add_layer(Layer::new(
+                2,
+                1,
+                vec![1., -1.],
+                vec![2.],
+                ActivationFunction::Identity,
+            ));
+        // 4.5 - 5 + 2
+        assert_eq!(network.layers[1].forward(vec![4.5, 5.]), vec![1.5]);
+        assert_eq!(network.forward(vec![1., 1.]), vec![1.]);
+    }
+
+    #[test]
+    fn forward_with_reuses_scratch() {
+        let mut network = NeuralNetwork::new();
+        network
+            .add_layer(layer(ActivationFunction::Identity))
+            .add_layer(layer(ActivationFunction::Sigmoid));
+        let mut scratch = Scratch::default();
+        for input in [[1., 1.], [-1., 0.5]] {
+            let expected = network.forward(input.to_vec());
+            assert_eq!(network.forward_with(&input, &mut scratch), expected);
+        }
+        assert_eq!(network.layers[0].weight(1, 0), 3.);
+    }
+
+    #[test]
+    fn disabled_biases_are_not_mutated() {
+        let mut network = NeuralNetwork::new();
+        network.add_layer(layer(ActivationFunction::Identity).with_bias(false));
+        let mutation = Mutation {
+            operator: mutation::MutationOperator::Gaussian,
+            rate: 1.,
+            sigma: 1.,
+            self_adaptive: false,
+        };
+        network.mutate(&mutation, &mut rand::thread_rng());
+        assert_eq!(network.layers[0].biases, vec![0.5, -1.]);
+    }
+
+    #[test]
+    fn convolutions_keep_the_size_of_the_planes() {
+        let input = Shape {
+            channels: 1,
+            height: 3,
+            width: 3,
+        };
+        let conv = Layer::conv2d(
+            input,
+            1,
+            3,
+            vec![1.; 9],
+            vec![0.],
+            ActivationFunction::Identity,
+        );
+        assert_eq!(conv.output_shape(), input);
+        let output = conv.forward((1..=9).map(f64::from).collect());
+        // the corners only see 4 cells, the centre sees them all
+        assert_eq!(output[0], 1. + 2. + 4. + 5.);
+        assert_eq!(output[4], 45.);
+    }
+
+    #[test]
+    fn max_pool_keeps_the_largest_of_each_block() {
+        let pool = Layer::max_pool(
+            Shape {
+                channels: 1,
+                height: 4,
+                width: 4,
+            },
+            2,
+        );
+        assert_eq!(pool.output_dim, 4);
+        let output = pool.forward((0..16).map(f64::from).collect());
+        assert_eq!(output, vec![5., 7., 13., 15.]);
+    }
+
+    fn activate(activation: ActivationFunction, input: &[f64]) -> Vec<f64> {
+        let mut output = input.to_vec();
+        activation.apply(&mut output);
+        output
+    }
+
+    fn assert_close(output: &[f64], expected: &[f64]) {
+        assert_eq!(output.len(), expected.len());
+        for (o, e) in output.iter().zip(expected) {
+            assert!((o - e).abs() < 1e-8, "{output:?} != {expected:?}");
+        }
+    }
+
+    const INPUT: [f64; 5] = [-2., -0.5, 0., 0.5, 2.];
+
+    #[test]
+    fn relu() {
+        assert_close(
+            &activate(ActivationFunction::Relu, &INPUT),
+            &[0., 0., 0., 0.5, 2.],
+        );
+    }
+
+    #[test]
+    fn sigmoid() {
+        assert_close(
+            &activate(ActivationFunction::Sigmoid, &INPUT),
+            &[
+                0.11920292202,
+                0.37754066880,
+                0.5,
+                0.62245933120,
+                0.88079707798,
+            ],
+        );
+    }
+
+    #[test]
+    fn softmax() {
+        assert_close(
+            &activate(ActivationFunction::Softmax, &[1., 2., 3.]),
+            &[0.09003057317, 0.24472847105, 0.66524095578],
+        );
+    }
+
+    #[test]
+    fn softmax_is_stable_for_large_inputs() {
+        assert_close(
+            &activate(ActivationFunction::Softmax, &[1000., 1001.]),
+            &[0.26894142137, 0.73105857863],
+        );
+        assert_close(
+            &activate(ActivationFunction::Softmax, &[-1000., -1000.]),
+            &[0.5, 0.5],
+        );
+    }
+
+    #[test]
+    fn identity() {
+        assert_close(&activate(ActivationFunction::Identity, &INPUT), &INPUT);
+    }
+
+    #[test]
+    fn tanh() {
+        assert_close(
+            &activate(ActivationFunction::Tanh, &INPUT),
+            &[
+                -0.96402758008,
+                -0.46211715726,
+                0.,
+                0.46211715726,
+                0.96402758008,
+            ],
+        );
+    }
+
+    #[test]
+    fn leaky_relu() {
+        assert_close(
+            &activate(ActivationFunction::LeakyRelu, &INPUT),
+            &[-0.02, -0.005, 0., 0.5, 2.],
+        );
+    }
+
+    #[test]
+    fn elu() {
+        assert_close(
+            &activate(ActivationFunction::Elu, &INPUT),
+            &[-0.86466471676, -0.39346934029, 0., 0.5, 2.],
+        );
+    }
+
+    #[test]
+    fn swish() {
+        assert_close(
+            &activate(ActivationFunction::Swish, &INPUT),
+            &[
+                -0.23840584404,
+                -0.18877033440,
+                0.,
+                0.31122966560,
+                1.76159415596,
+            ],
+        );
+    }
+
+    #[test]
+    fn step() {
+        assert_close(
+            &activate(ActivationFunction::Step, &INPUT),
+            &[0., 0., 0., 1., 1.],
+        );
+    }
+}
@@ -1,98 +1,153 @@
-use std::fmt;
+use std::{collections::HashSet, fmt, str::FromStr};
+
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
 
 use crate::snake_core::{
     snake::{Snake, SnakeException},
-    universe::{Food, Universe},
+    universe::{Direction, Universe},
 };
 
-use super::NeuralNetwork;
+use super::{brain::Brain, fitness::EpisodeStats, sensor::Sensors, Scratch};
+
+/// Moves picked by the brain with absolute actions, in the order of its outputs
+const DIRECTIONS: [Direction; 4] = [
+    Direction::Up,
+    Direction::Down,
+    Direction::Left,
+    Direction::Right,
+];
 
+/// How the outputs of the brain are turned into moves
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum ActionSpace {
+    /// One output per direction of the grid, the one turning the snake back is never taken
+    #[default]
+    Absolute,
+    /// Turn left, go straight or turn right from the heading of the snake, every output
+    /// is a legal move
+    Relative,
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct Model {
     pub universe: Universe,
-    pub brain: NeuralNetwork,
+    pub brain: Brain,
+    /// Food eaten in the current game, the mean over the generation once it ends
     pub score: u32,
+    /// Measures of the current game the fitness is computed from
+    pub stats: EpisodeStats,
+    /// Measures of the games already played this generation
+    pub past_episodes: Vec<EpisodeStats>,
+    /// Set from the episodes when the generation ends, the models are selected on it
+    pub fitness: f64,
     pub allowed_moves_number: u64,
     pub moves_left: u64,
+    /// How the output of the brain is read, the same for the whole run
+    pub actions: ActionSpace,
 
     pub id: usize,
+    pub rng: ChaCha8Rng,
+    /// Places the food instead of `rng` when episodes share their random numbers
+    pub food_rng: Option<ChaCha8Rng>,
+    #[serde(skip)]
+    scratch: Scratch,
+    /// Cells the head went through since the last food, saved so that a resumed run
+    /// counts the same revisits as one that was not interrupted
+    visited: HashSet<(u64, u64)>,
 }
 
 impl Model {
-    pub fn new(width: u64, height: u64, moves_left: u64, id: usize, brain: NeuralNetwork) -> Self {
+    pub fn new(
+        width: u64,
+        height: u64,
+        moves_left: u64,
+        id: usize,
+        brain: Brain,
+        rng: ChaCha8Rng,
+    ) -> Self {
         let universe = Universe::new_empty(width, height);
         let score = 0;
         Model {
             universe,
             brain,
             score,
+            stats: EpisodeStats::default(),
+            past_episodes: vec![],
+            fitness: 0.,
             allowed_moves_number: moves_left,
             moves_left,
+            actions: ActionSpace::default(),
             id,
+            rng,
+            food_rng: None,
+            scratch: Scratch::default(),
+            visited: HashSet::new(),
         }
     }
+
+    /// Stream `id + 1` of `seed`, stream 0 is left to the population
+    pub fn rng(seed: u64, id: usize) -> ChaCha8Rng {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        rng.set_stream(id as u64 + 1);
+        rng
+    }
+
+    /// Food of episode `episode` of `generation` when the whole population shares it
+    pub fn episode_rng(seed: u64, generation: u64, episode: usize) -> ChaCha8Rng {
+        // mixed with the golden ratio so the streams differ from the ones of `rng`
+        let mut rng =
+            ChaCha8Rng::seed_from_u64(seed ^ 0x9E37_79B9_7F4A_7C15u64.wrapping_mul(generation + 1));
+        rng.set_stream(episode as u64);
+        rng
+    }
+
+    /// Starts a new generation, forgetting the episodes played
     pub fn reset(&mut self, moves_left: u64, food_ammount: u64) {
+        self.past_episodes.clear();
+        self.start_episode(moves_left, food_ammount);
+    }
+
+    /// Keeps the measures of the finished game and starts another one
+    pub fn next_episode(&mut self, moves_left: u64, food_ammount: u64) {
+        self.past_episodes.push(self.stats);
+        self.start_episode(moves_left, food_ammount);
+    }
+
+    /// Episodes played this generation, the current one included
+    pub fn episodes(&self) -> impl Iterator<Item = &EpisodeStats> {
+        self.past_episodes.iter().chain([&self.stats])
+    }
+
+    fn start_episode(&mut self, moves_left: u64, food_ammount: u64) {
+        self.universe.snakes.clear();
         self.add_snake(Snake::new(self.universe.width, self.universe.height, 0));
         self.score = 0;
+        self.stats = EpisodeStats::default();
+        self.visited.clear();
         self.allowed_moves_number = moves_left;
         self.moves_left = self.allowed_moves_number;
         self.universe.food = vec![];
         for _ in 0..food_ammount {
-            self.universe.spawn_food();
+            self.spawn_food();
         }
     }
-    pub fn compute_input(&self, width: u64, height: u64, vision_range: i64) -> Option<Vec<f64>> {
-        let mut input = vec![];
-        if let Some(snake) = self.universe.get_snake(0) {
-            let mut counter = 0;
-            for u in -1..=1 {
-                for v in -1..=1 {
-                    if u == 0 && v == 0 {
-                        continue;
-                    }
-                    input.push(0.);
-                    input.push(0.);
-
-                    for i in 1..=vision_range {
-                        let pos = (
-                            (snake.positions[0].0 as i64 + (i * u as i64)),
-                            (snake.positions[0].1 as i64 + (i * v as i64)),
-                        );
-                        if pos.0 > width as i64
-                            || pos.0 < 0
-                            || pos.1 > height as i64
-                            || pos.1 < 0
-                            || snake.is_in_pos((pos.0 as u64, pos.1 as u64))
-                        {
-                            input[counter] = 1. - i as f64 / vision_range as f64;
-                            break;
-                        }
-                    }
 
-                    for i in 1..vision_range {
-                        let pos = (
-                            (snake.positions[0].0 as i64 + (i * u as i64)),
-                            (snake.positions[0].1 as i64 + (i * v as i64)),
-                        );
-                        if pos.0 < width as i64
-                            && pos.0 >= 0
-                            && pos.1 < height as i64
-                            && pos.1 >= 0
-                            && self
-                                .universe
-                                .food
-                                .contains(&Food(pos.0 as u64, pos.1 as u64))
-                        {
-                            input[counter + 1] = 1. - i as f64 / vision_range as f64;
-                            break;
-                        }
-                    }
-                    counter += 2;
-                }
-            }
+    fn spawn_food(&mut self) {
+        let rng = self.food_rng.as_mut().unwrap_or(&mut self.rng);
+        self.universe.spawn_food(rng);
+    }
+    pub fn compute_input(&self, sensors: &Sensors) -> Option<Vec<f64>> {
+        sensors.observe(&self.universe)
+    }
 
-            Some(input)
-        } else {
-            None
+    /// Feeds the surroundings of the snake to the brain and moves it accordingly
+    pub fn step(&mut self, sensors: &Sensors) {
+        if let Some(input) = self.compute_input(sensors) {
+            let mut scratch = std::mem::take(&mut self.scratch);
+            self.update_position(self.brain.forward_with(&input, &mut scratch));
+            self.scratch = scratch;
         }
     }
 
@@ -100,18 +155,36 @@ impl Model {
         self.brain.forward(input)
     }
 
+    /// Output whose direction would turn the snake back onto itself, it never gets picked.
+    /// Relative actions have none
+    pub fn reverse_action(&self) -> Option<usize> {
+        let heading = &self.universe.get_snake(0)?.direction;
+        let reverse = turn_right(&turn_right(heading));
+        self.actions.action(heading, &reverse)
+    }
+
+    /// Moves the snake towards output `action` instead of asking the brain
+    pub fn act(&mut self, action: usize) {
+        let mut output = vec![0.; self.actions.size()];
+        output[action] = 1.;
+        self.update_position(&mut output);
+    }
+
+    /// Manhattan distance from the head to the closest food
+    fn food_distance(&self) -> Option<u64> {
+        let head = *self.universe.get_snake(0)?.positions.first()?;
+        self.universe
+            .food
+            .iter()
+            .map(|food| head.0.abs_diff(food.0) + head.1.abs_diff(food.1))
+            .min()
+    }
+
     pub fn add_snake(&mut self, snake: Snake) {
         self.universe.add_snake(snake);
     }
 
-    pub fn update_position(&mut self, mut output: Vec<f64>) {
-        let direction = [
-            crate::snake_core::universe::Direction::Up,
-            crate::snake_core::universe::Direction::Down,
-            crate::snake_core::universe::Direction::Left,
-            crate::snake_core::universe::Direction::Right,
-        ];
-
+    pub fn update_position(&mut self, output: &mut [f64]) {
         let index_max = output
             .iter()
             .enumerate()
@@ -119,19 +192,44 @@ impl Model {
             .unwrap()
             .0;
 
-        let direction = direction[index_max].clone();
+        let Some(heading) = self.universe.get_snake(0).map(|snake| &snake.direction) else {
+            return;
+        };
+        let direction = self.actions.direction(heading, index_max);
 
         if self.moves_left == 0 {
             self.universe.kill_snake(0);
         } else {
+            let distance = self.food_distance();
             match self.universe.move_snake(0, direction) {
                 Ok(true) => {
                     self.score += 1;
-                    self.universe.spawn_food();
+                    self.stats.food += 1;
+                    self.stats.steps += 1;
+                    self.stats.approach += 1;
+                    self.visited.clear();
+                    self.spawn_food();
+                }
+                Ok(false) => {
+                    self.moves_left -= 1;
+                    self.stats.steps += 1;
+                    self.stats.timed_out = self.moves_left == 0;
+                    if let Some(&head) = self
+                        .universe
+                        .get_snake(0)
+                        .and_then(|snake| snake.positions.first())
+                    {
+                        if !self.visited.insert(head) {
+                            self.stats.revisits += 1;
+                        }
+                    }
+                    if let (Some(before), Some(after)) = (distance, self.food_distance()) {
+                        self.stats.approach += (before as i64 - after as i64).signum();
+                    }
                 }
-                Ok(false) => self.moves_left -= 1,
                 Err(SnakeException::InvalidMove) => {
-                    output[index_max] = 0.0;
+                    // outputs are not always positive, e.g. with an identity output layer
+                    output[index_max] = f64::NEG_INFINITY;
                     self.update_position(output);
                 }
                 Err(SnakeException::DeadSnake) => {
@@ -142,6 +240,72 @@ impl Model {
     }
 }
 
+impl ActionSpace {
+    pub const ALL: [ActionSpace; 2] = [ActionSpace::Absolute, ActionSpace::Relative];
+
+    /// Size of the brain output
+    pub fn size(self) -> usize {
+        match self {
+            ActionSpace::Absolute => DIRECTIONS.len(),
+            ActionSpace::Relative => 3,
+        }
+    }
+
+    /// Direction a snake heading towards `heading` moves in when taking output `action`
+    pub fn direction(self, heading: &Direction, action: usize) -> Direction {
+        match (self, action) {
+            (ActionSpace::Absolute, action) => DIRECTIONS[action].clone(),
+            (ActionSpace::Relative, 0) => turn_left(heading),
+            (ActionSpace::Relative, 1) => heading.clone(),
+            (ActionSpace::Relative, _) => turn_right(heading),
+        }
+    }
+
+    /// Output moving a snake heading towards `heading` in `direction`, `None` when relative
+    /// actions cannot turn it back
+    pub fn action(self, heading: &Direction, direction: &Direction) -> Option<usize> {
+        (0..self.size()).find(|&action| self.direction(heading, action) == *direction)
+    }
+}
+
+fn turn_left(heading: &Direction) -> Direction {
+    match heading {
+        Direction::Up => Direction::Left,
+        Direction::Left => Direction::Down,
+        Direction::Down => Direction::Right,
+        Direction::Right => Direction::Up,
+    }
+}
+
+fn turn_right(heading: &Direction) -> Direction {
+    match heading {
+        Direction::Up => Direction::Right,
+        Direction::Right => Direction::Down,
+        Direction::Down => Direction::Left,
+        Direction::Left => Direction::Up,
+    }
+}
+
+impl fmt::Display for ActionSpace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ActionSpace::Absolute => write!(f, "absolute"),
+            ActionSpace::Relative => write!(f, "relative"),
+        }
+    }
+}
+
+impl FromStr for ActionSpace {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        ActionSpace::ALL
+            .into_iter()
+            .find(|actions| actions.to_string() == s)
+            .ok_or(())
+    }
+}
+
 impl fmt::Display for Model {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         writeln!(f, "Model {}, NN:\n", self.id)?;
@@ -149,3 +313,86 @@ impl fmt::Display for Model {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai_snake::neural_network::{architecture::Architecture, sensor::SensorConfig};
+
+    fn model(id: usize) -> Model {
+        let mut rng = Model::rng(0, id);
+        let brain = Architecture::default()
+            .build(
+                SensorConfig::default().input_size(),
+                ActionSpace::default().size(),
+                &mut rng,
+            )
+            .into();
+        Model::new(16, 16, 20, id, brain, rng)
+    }
+
+    #[test]
+    fn episodes_are_kept_until_the_generation_ends() {
+        let mut model = model(0);
+        model.reset(20, 3);
+        model.stats.food = 2;
+        model.next_episode(20, 3);
+        model.stats.food = 5;
+        let food: Vec<u32> = model.episodes().map(|stats| stats.food).collect();
+        assert_eq!(food, vec![2, 5]);
+        assert_eq!(model.universe.snakes.len(), 1);
+        model.reset(20, 3);
+        assert_eq!(model.episodes().count(), 1);
+    }
+
+    #[test]
+    fn common_seeds_share_the_food() {
+        let (mut first, mut second) = (model(0), model(1));
+        for model in [&mut first, &mut second] {
+            model.food_rng = Some(Model::episode_rng(7, 2, 1));
+            model.reset(20, 5);
+        }
+        assert_eq!(first.universe.food, second.universe.food);
+        first.food_rng = None;
+        first.reset(20, 5);
+        assert_ne!(first.universe.food, second.universe.food);
+    }
+
+    #[test]
+    fn checkpoints_keep_the_cells_visited() {
+        let mut model = model(0);
+        model.reset(20, 0);
+        model.act(3);
+        model.act(0);
+        let resumed: Model = bincode::deserialize(&bincode::serialize(&model).unwrap()).unwrap();
+        assert_eq!(resumed.visited, model.visited);
+        assert_eq!(resumed.visited.len(), 2);
+    }
+
+    #[test]
+    fn relative_actions_turn_with_the_heading() {
+        let actions = ActionSpace::Relative;
+        assert_eq!(actions.direction(&Direction::Up, 0), Direction::Left);
+        assert_eq!(actions.direction(&Direction::Left, 1), Direction::Left);
+        assert_eq!(actions.direction(&Direction::Down, 2), Direction::Left);
+        assert_eq!(actions.action(&Direction::Right, &Direction::Up), Some(0));
+        assert_eq!(actions.action(&Direction::Right, &Direction::Left), None);
+        assert_eq!(
+            ActionSpace::Absolute.action(&Direction::Right, &Direction::Left),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn relative_models_never_reverse() {
+        let mut model = model(0);
+        model.actions = ActionSpace::Relative;
+        model.reset(20, 0);
+        assert_eq!(model.reverse_action(), None);
+        // turning right from up
+        model.act(2);
+        let snake = model.universe.get_snake(0).unwrap();
+        assert_eq!(snake.direction, Direction::Right);
+        assert_eq!(snake.positions[0], (9, 8));
+    }
+}
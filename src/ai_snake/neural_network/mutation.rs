@@ -0,0 +1,248 @@
+use std::{fmt, str::FromStr};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// What happens to a weight or bias picked for mutation
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum MutationOperator {
+    /// Replaced by a uniform value in [-1, 1]
+    #[default]
+    Reset,
+    /// Gaussian noise of standard deviation sigma is added
+    Gaussian,
+    /// Multiplied by `1 + N(0, sigma)`, small weights stay small
+    Scaled,
+    /// Sign is flipped
+    SignFlip,
+}
+
+/// How the mutation rate and sigma evolve over a run
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum MutationSchedule {
+    #[default]
+    Constant,
+    /// Multiplied by `mutation_decay` every generation
+    Decay,
+    /// Rechenberg's rule: grows when more than a fifth of the recent generations
+    /// improved the best score, shrinks when fewer did
+    OneFifth,
+    /// Every genome carries its own sigma, mutated log-normally before its weights
+    SelfAdaptive,
+}
+
+/// Mutation settings of a run, see [`Mutation::from_config`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MutationConfig {
+    /// Divided by the size of the island to get the per-weight mutation rate
+    pub factor: f64,
+    pub operator: MutationOperator,
+    /// Standard deviation of the gaussian and scaled operators
+    pub sigma: f64,
+    pub schedule: MutationSchedule,
+    /// Applied every generation by the decay schedule
+    pub decay: f64,
+    /// Current scale of the rate and sigma, driven by the schedule
+    pub scale: f64,
+}
+
+/// Mutation applied to the brains of one generation
+#[derive(Clone, Copy, Debug)]
+pub struct Mutation {
+    pub operator: MutationOperator,
+    /// Probability for each weight and bias to mutate
+    pub rate: f64,
+    pub sigma: f64,
+    /// Use and evolve the sigma of each genome instead of `sigma`
+    pub self_adaptive: bool,
+}
+
+/// Generations looked at by the 1/5th rule
+const ONE_FIFTH_WINDOW: usize = 10;
+/// Factor applied by the 1/5th rule, the usual 0.817
+const ONE_FIFTH_FACTOR: f64 = 0.817;
+const SCALE_RANGE: (f64, f64) = (0.01, 100.);
+
+impl MutationOperator {
+    pub const ALL: [MutationOperator; 4] = [
+        MutationOperator::Reset,
+        MutationOperator::Gaussian,
+        MutationOperator::Scaled,
+        MutationOperator::SignFlip,
+    ];
+
+    pub fn apply(&self, value: f64, sigma: f64, rng: &mut impl Rng) -> f64 {
+        match self {
+            MutationOperator::Reset => rng.gen::<f64>() * 2. - 1.,
+            MutationOperator::Gaussian => value + sigma * gaussian(rng),
+            MutationOperator::Scaled => value * (1. + sigma * gaussian(rng)),
+            MutationOperator::SignFlip => -value,
+        }
+    }
+
+    /// Whether sigma has any effect
+    pub fn uses_sigma(&self) -> bool {
+        matches!(self, MutationOperator::Gaussian | MutationOperator::Scaled)
+    }
+}
+
+impl MutationSchedule {
+    pub const ALL: [MutationSchedule; 4] = [
+        MutationSchedule::Constant,
+        MutationSchedule::Decay,
+        MutationSchedule::OneFifth,
+        MutationSchedule::SelfAdaptive,
+    ];
+}
+
+impl MutationConfig {
+    /// Scale of the rate and sigma once a generation has been added to the (best, average)
+    /// score `history`
+    pub fn next_scale(&self, history: &[(u64, u64)]) -> f64 {
+        let scale = match self.schedule {
+            MutationSchedule::Constant | MutationSchedule::SelfAdaptive => return 1.,
+            MutationSchedule::Decay => self.scale * self.decay,
+            MutationSchedule::OneFifth => {
+                let window = &history[history.len().saturating_sub(ONE_FIFTH_WINDOW + 1)..];
+                if window.len() <= ONE_FIFTH_WINDOW {
+                    return self.scale;
+                }
+                let successes = window.windows(2).filter(|w| w[1].0 > w[0].0).count();
+                match (successes * 5).cmp(&ONE_FIFTH_WINDOW) {
+                    std::cmp::Ordering::Greater => self.scale / ONE_FIFTH_FACTOR,
+                    std::cmp::Ordering::Less => self.scale * ONE_FIFTH_FACTOR,
+                    std::cmp::Ordering::Equal => self.scale,
+                }
+            }
+        };
+        scale.clamp(SCALE_RANGE.0, SCALE_RANGE.1)
+    }
+}
+
+impl Mutation {
+    /// The mutation factor is divided by the number of models mutated together, the size
+    /// of their island, then scaled by the schedule
+    pub fn from_config(config: &MutationConfig, population_size: usize) -> Self {
+        let scale = match config.schedule {
+            MutationSchedule::SelfAdaptive => 1.,
+            _ => config.scale,
+        };
+        Mutation {
+            operator: config.operator,
+            rate: (config.factor / population_size.max(1) as f64 * scale).min(1.),
+            sigma: config.sigma * scale,
+            self_adaptive: config.schedule == MutationSchedule::SelfAdaptive,
+        }
+    }
+}
+
+/// Standard normal sample, through the Box-Muller transform
+pub fn gaussian(rng: &mut impl Rng) -> f64 {
+    let u = 1. - rng.gen::<f64>();
+    let v = rng.gen::<f64>();
+    (-2. * u.ln()).sqrt() * (std::f64::consts::TAU * v).cos()
+}
+
+impl fmt::Display for MutationOperator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MutationOperator::Reset => write!(f, "reset"),
+            MutationOperator::Gaussian => write!(f, "gaussian"),
+            MutationOperator::Scaled => write!(f, "scaled"),
+            MutationOperator::SignFlip => write!(f, "sign flip"),
+        }
+    }
+}
+
+impl fmt::Display for MutationSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MutationSchedule::Constant => write!(f, "constant"),
+            MutationSchedule::Decay => write!(f, "decay"),
+            MutationSchedule::OneFifth => write!(f, "1/5th rule"),
+            MutationSchedule::SelfAdaptive => write!(f, "self-adaptive"),
+        }
+    }
+}
+
+/// Parses the displayed name, with dashes instead of spaces
+impl FromStr for MutationOperator {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        MutationOperator::ALL
+            .into_iter()
+            .find(|operator| operator.to_string().replace(' ', "-") == s)
+            .ok_or(())
+    }
+}
+
+impl FromStr for MutationSchedule {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        MutationSchedule::ALL
+            .into_iter()
+            .find(|schedule| schedule.to_string().replace(' ', "-") == s)
+            .ok_or(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+
+    #[test]
+    fn gaussian_has_unit_variance() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let samples: Vec<f64> = (0..100_000).map(|_| gaussian(&mut rng)).collect();
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        let variance =
+            samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / samples.len() as f64;
+        assert!(mean.abs() < 0.01, "{mean}");
+        assert!((variance - 1.).abs() < 0.02, "{variance}");
+    }
+
+    fn config(schedule: MutationSchedule, scale: f64) -> MutationConfig {
+        MutationConfig {
+            factor: 0.4,
+            operator: MutationOperator::Gaussian,
+            sigma: 0.1,
+            schedule,
+            decay: 0.5,
+            scale,
+        }
+    }
+
+    #[test]
+    fn one_fifth_rule_follows_the_success_rate() {
+        let config = config(MutationSchedule::OneFifth, 1.);
+        // improves every generation
+        let mut history: Vec<(u64, u64)> = (0..11).map(|i| (i, 0)).collect();
+        assert!(config.next_scale(&history) > 1.);
+        // stagnates
+        history = vec![(5, 0); 11];
+        assert!(config.next_scale(&history) < 1.);
+        // not enough history yet
+        history.truncate(3);
+        assert_eq!(config.next_scale(&history), 1.);
+    }
+
+    #[test]
+    fn decay_is_bounded() {
+        assert_eq!(config(MutationSchedule::Decay, 0.01).next_scale(&[]), 0.01);
+    }
+
+    #[test]
+    fn rate_is_shared_by_the_population() {
+        let mutation = Mutation::from_config(&config(MutationSchedule::Decay, 0.5), 10);
+        assert!((mutation.rate - 0.02).abs() < 1e-12);
+        assert!((mutation.sigma - 0.05).abs() < 1e-12);
+        let mutation = Mutation::from_config(&config(MutationSchedule::SelfAdaptive, 0.5), 10);
+        assert!(mutation.self_adaptive);
+        assert!((mutation.rate - 0.04).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,593 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    sync::OnceLock,
+};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use super::{mutation::Mutation, normalize, ActivationFunction, Scratch};
+
+/// Settings of NeuroEvolution of Augmenting Topologies
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NeatConfig {
+    pub hidden_activation: ActivationFunction,
+    /// Probability for a genome to gain a connection each generation
+    pub add_connection: f64,
+    /// Probability for a genome to split a connection with a new node each generation
+    pub add_node: f64,
+    /// Genomes closer than this to the representative of a species belong to it
+    pub compatibility_threshold: f64,
+    pub excess_coefficient: f64,
+    pub disjoint_coefficient: f64,
+    pub weight_coefficient: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum NodeKind {
+    Input,
+    Bias,
+    Output,
+    Hidden,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NodeGene {
+    pub id: usize,
+    pub kind: NodeKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ConnectionGene {
+    pub innovation: usize,
+    pub from: usize,
+    pub to: usize,
+    pub weight: f64,
+    pub enabled: bool,
+}
+
+/// Historical markings shared by a population, so genes with the same origin line up in crossovers
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Innovations {
+    next_innovation: usize,
+    next_node: usize,
+    connections: BTreeMap<(usize, usize), usize>,
+    /// Node created by splitting each connection
+    splits: BTreeMap<usize, usize>,
+}
+
+/// Network whose structure evolves, starting from inputs fully connected to outputs
+#[derive(Clone, Serialize, Deserialize)]
+pub struct NeatGenome {
+    pub inputs: usize,
+    pub outputs: usize,
+    /// Inputs, the bias node, outputs, then hidden nodes in order of creation
+    pub nodes: Vec<NodeGene>,
+    /// Sorted by innovation number
+    pub connections: Vec<ConnectionGene>,
+    pub hidden_activation: ActivationFunction,
+    pub output_activation: ActivationFunction,
+    /// Evaluation order, rebuilt after the genes change
+    #[serde(skip)]
+    compiled: OnceLock<Compiled>,
+}
+
+#[derive(Clone)]
+struct Compiled {
+    /// Node index and range of its incoming links, in topological order
+    order: Vec<(usize, usize, usize)>,
+    /// Source node index and weight
+    links: Vec<(usize, f64)>,
+}
+
+/// Tries to find two unconnected nodes before giving up on adding a connection
+const ADD_CONNECTION_ATTEMPTS: usize = 20;
+/// Below this many genes, distances are not divided by the genome size
+const SMALL_GENOME: usize = 20;
+
+impl Default for NeatConfig {
+    fn default() -> Self {
+        NeatConfig {
+            hidden_activation: ActivationFunction::Tanh,
+            add_connection: 0.05,
+            add_node: 0.03,
+            compatibility_threshold: 3.0,
+            excess_coefficient: 1.0,
+            disjoint_coefficient: 1.0,
+            weight_coefficient: 0.4,
+        }
+    }
+}
+
+impl Innovations {
+    /// Makes sure the genes of `genome` are known, e.g. for the genomes of a new population
+    pub fn observe(&mut self, genome: &NeatGenome) {
+        for connection in &genome.connections {
+            self.connections
+                .entry((connection.from, connection.to))
+                .or_insert(connection.innovation);
+            self.next_innovation = self.next_innovation.max(connection.innovation + 1);
+        }
+        for node in &genome.nodes {
+            self.next_node = self.next_node.max(node.id + 1);
+        }
+    }
+
+    fn connection(&mut self, from: usize, to: usize) -> usize {
+        *self.connections.entry((from, to)).or_insert_with(|| {
+            self.next_innovation += 1;
+            self.next_innovation - 1
+        })
+    }
+
+    fn split(&mut self, innovation: usize) -> usize {
+        *self.splits.entry(innovation).or_insert_with(|| {
+            self.next_node += 1;
+            self.next_node - 1
+        })
+    }
+}
+
+impl NeatGenome {
+    /// Every input and the bias connected to every output, with uniform weights in [-1, 1].
+    /// Innovation numbers only depend on the sizes so every minimal genome lines up
+    pub fn minimal(
+        inputs: usize,
+        outputs: usize,
+        hidden_activation: ActivationFunction,
+        output_activation: ActivationFunction,
+        rng: &mut impl Rng,
+    ) -> Self {
+        let nodes = (0..inputs)
+            .map(|id| NodeGene {
+                id,
+                kind: NodeKind::Input,
+            })
+            .chain([NodeGene {
+                id: inputs,
+                kind: NodeKind::Bias,
+            }])
+            .chain((0..outputs).map(|o| NodeGene {
+                id: inputs + 1 + o,
+                kind: NodeKind::Output,
+            }))
+            .collect();
+        let mut connections = vec![];
+        for from in 0..=inputs {
+            for o in 0..outputs {
+                connections.push(ConnectionGene {
+                    innovation: from * outputs + o,
+                    from,
+                    to: inputs + 1 + o,
+                    weight: rng.gen::<f64>() * 2. - 1.,
+                    enabled: true,
+                });
+            }
+        }
+        NeatGenome {
+            inputs,
+            outputs,
+            nodes,
+            connections,
+            hidden_activation,
+            output_activation,
+            compiled: OnceLock::new(),
+        }
+    }
+
+    pub fn hidden_nodes(&self) -> usize {
+        self.nodes.len() - self.inputs - 1 - self.outputs
+    }
+
+    /// Same as `NeuralNetwork::forward_with`, the output is normalized the same way
+    pub fn forward_with<'a>(&self, input: &[f64], scratch: &'a mut Scratch) -> &'a mut [f64] {
+        let compiled = self.compiled.get_or_init(|| self.compile());
+        let values = &mut scratch.current;
+        values.clear();
+        values.resize(self.nodes.len(), 0.);
+        for (value, &x) in values.iter_mut().zip(input).take(self.inputs) {
+            *value = x;
+        }
+        values[self.inputs] = 1.;
+
+        for &(node, start, end) in &compiled.order {
+            let sum = compiled.links[start..end]
+                .iter()
+                .fold(0., |acc, &(from, w)| acc + values[from] * w);
+            values[node] = if self.nodes[node].kind == NodeKind::Hidden {
+                let mut value = [sum];
+                self.hidden_activation.apply(&mut value);
+                value[0]
+            } else {
+                sum
+            };
+        }
+
+        let output = &mut scratch.next;
+        output.clear();
+        output.extend_from_slice(&values[self.inputs + 1..self.inputs + 1 + self.outputs]);
+        self.output_activation.apply(output);
+        normalize(output);
+        output
+    }
+
+    fn compile(&self) -> Compiled {
+        let index: HashMap<usize, usize> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (node.id, i))
+            .collect();
+        let mut incoming = vec![vec![]; self.nodes.len()];
+        let mut pending = vec![0; self.nodes.len()];
+        let mut outgoing = vec![vec![]; self.nodes.len()];
+        for connection in self.connections.iter().filter(|c| c.enabled) {
+            if let (Some(&from), Some(&to)) =
+                (index.get(&connection.from), index.get(&connection.to))
+            {
+                incoming[to].push((from, connection.weight));
+                outgoing[from].push(to);
+                pending[to] += 1;
+            }
+        }
+
+        // Kahn's algorithm, nodes left in a cycle are never evaluated
+        let mut ready: Vec<usize> = (0..self.nodes.len()).filter(|&i| pending[i] == 0).collect();
+        let mut compiled = Compiled {
+            order: vec![],
+            links: vec![],
+        };
+        while let Some(node) = ready.pop() {
+            if matches!(self.nodes[node].kind, NodeKind::Hidden | NodeKind::Output) {
+                let start = compiled.links.len();
+                compiled.links.extend(&incoming[node]);
+                compiled.order.push((node, start, compiled.links.len()));
+            }
+            for &next in &outgoing[node] {
+                pending[next] -= 1;
+                if pending[next] == 0 {
+                    ready.push(next);
+                }
+            }
+        }
+        compiled
+    }
+
+    /// Perturbs the weights like a layered network, then maybe adds a connection or a node.
+    /// The self-adaptive schedule does not apply, genomes use the sigma of `mutation`
+    pub fn mutate(
+        &mut self,
+        mutation: &Mutation,
+        config: &NeatConfig,
+        innovations: &mut Innovations,
+        rng: &mut impl Rng,
+    ) {
+        for connection in &mut self.connections {
+            if rng.gen::<f64>() < mutation.rate {
+                connection.weight = mutation
+                    .operator
+                    .apply(connection.weight, mutation.sigma, rng);
+            }
+        }
+        if rng.gen::<f64>() < config.add_connection {
+            self.add_connection(innovations, rng);
+        }
+        if rng.gen::<f64>() < config.add_node {
+            self.add_node(innovations, rng);
+        }
+        self.compiled = OnceLock::new();
+    }
+
+    fn add_connection(&mut self, innovations: &mut Innovations, rng: &mut impl Rng) {
+        for _ in 0..ADD_CONNECTION_ATTEMPTS {
+            let from = &self.nodes[rng.gen_range(0..self.nodes.len())];
+            let to = &self.nodes[rng.gen_range(0..self.nodes.len())];
+            if from.kind == NodeKind::Output
+                || matches!(to.kind, NodeKind::Input | NodeKind::Bias)
+                || from.id == to.id
+                || self
+                    .connections
+                    .iter()
+                    .any(|c| c.from == from.id && c.to == to.id)
+                || self.reaches(to.id, from.id)
+            {
+                continue;
+            }
+            let (from, to) = (from.id, to.id);
+            self.insert(ConnectionGene {
+                innovation: innovations.connection(from, to),
+                from,
+                to,
+                weight: rng.gen::<f64>() * 2. - 1.,
+                enabled: true,
+            });
+            return;
+        }
+    }
+
+    fn add_node(&mut self, innovations: &mut Innovations, rng: &mut impl Rng) {
+        let enabled: Vec<usize> = (0..self.connections.len())
+            .filter(|&i| self.connections[i].enabled)
+            .collect();
+        if enabled.is_empty() {
+            return;
+        }
+        let split = enabled[rng.gen_range(0..enabled.len())];
+        let ConnectionGene {
+            innovation,
+            from,
+            to,
+            weight,
+            ..
+        } = self.connections[split];
+        let node = innovations.split(innovation);
+        if self.nodes.iter().any(|n| n.id == node) {
+            // this connection was already split, then enabled again by a crossover
+            return;
+        }
+
+        self.connections[split].enabled = false;
+        self.nodes.push(NodeGene {
+            id: node,
+            kind: NodeKind::Hidden,
+        });
+        self.insert(ConnectionGene {
+            innovation: innovations.connection(from, node),
+            from,
+            to: node,
+            weight: 1.,
+            enabled: true,
+        });
+        self.insert(ConnectionGene {
+            innovation: innovations.connection(node, to),
+            from: node,
+            to,
+            weight,
+            enabled: true,
+        });
+    }
+
+    fn insert(&mut self, connection: ConnectionGene) {
+        let i = self
+            .connections
+            .partition_point(|c| c.innovation < connection.innovation);
+        self.connections.insert(i, connection);
+        self.compiled = OnceLock::new();
+    }
+
+    /// Whether `to` can be reached from `from`, disabled connections included
+    /// since a crossover may enable them again
+    fn reaches(&self, from: usize, to: usize) -> bool {
+        let mut stack = vec![from];
+        let mut seen = vec![from];
+        while let Some(node) = stack.pop() {
+            if node == to {
+                return true;
+            }
+            for connection in self.connections.iter().filter(|c| c.from == node) {
+                if !seen.contains(&connection.to) {
+                    seen.push(connection.to);
+                    stack.push(connection.to);
+                }
+            }
+        }
+        false
+    }
+
+    /// Child with the structure of `fitter`, matching genes take either parent's weight
+    /// and stay disabled with a 75% chance when one parent disabled them
+    pub fn cross(fitter: &NeatGenome, other: &NeatGenome, rng: &mut impl Rng) -> NeatGenome {
+        let mut child = fitter.clone();
+        for gene in &mut child.connections {
+            if let Ok(i) = other
+                .connections
+                .binary_search_by_key(&gene.innovation, |c| c.innovation)
+            {
+                let matching = &other.connections[i];
+                if rng.gen_bool(0.5) {
+                    gene.weight = matching.weight;
+                }
+                if !gene.enabled || !matching.enabled {
+                    gene.enabled = !rng.gen_bool(0.75);
+                }
+            }
+        }
+        child.compiled = OnceLock::new();
+        child
+    }
+
+    /// NEAT compatibility distance, from the excess and disjoint genes and the weight
+    /// difference of the matching ones
+    pub fn compatibility(&self, other: &NeatGenome, config: &NeatConfig) -> f64 {
+        let (mut i, mut j) = (0, 0);
+        let (mut disjoint, mut matching, mut weight_difference) = (0, 0, 0.);
+        let (a, b) = (&self.connections, &other.connections);
+        while i < a.len() && j < b.len() {
+            match a[i].innovation.cmp(&b[j].innovation) {
+                std::cmp::Ordering::Equal => {
+                    matching += 1;
+                    weight_difference += (a[i].weight - b[j].weight).abs();
+                    i += 1;
+                    j += 1;
+                }
+                std::cmp::Ordering::Less => {
+                    disjoint += 1;
+                    i += 1;
+                }
+                std::cmp::Ordering::Greater => {
+                    disjoint += 1;
+                    j += 1;
+                }
+            }
+        }
+        let excess = (a.len() - i) + (b.len() - j);
+        let size = a.len().max(b.len());
+        let n = if size < SMALL_GENOME { 1. } else { size as f64 };
+        let mean_weight_difference = if matching > 0 {
+            weight_difference / matching as f64
+        } else {
+            0.
+        };
+        config.excess_coefficient * excess as f64 / n
+            + config.disjoint_coefficient * disjoint as f64 / n
+            + config.weight_coefficient * mean_weight_difference
+    }
+}
+
+impl PartialEq for NeatGenome {
+    fn eq(&self, other: &Self) -> bool {
+        self.inputs == other.inputs
+            && self.outputs == other.outputs
+            && self.nodes == other.nodes
+            && self.connections == other.connections
+            && self.hidden_activation == other.hidden_activation
+            && self.output_activation == other.output_activation
+    }
+}
+
+impl fmt::Display for NeatGenome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "NEAT genome: {} inputs, {} outputs, {} hidden nodes, hidden={}, output={}",
+            self.inputs,
+            self.outputs,
+            self.hidden_nodes(),
+            self.hidden_activation,
+            self.output_activation
+        )?;
+        for connection in &self.connections {
+            write!(
+                f,
+                "[{}] {} -> {}: {:.2}",
+                connection.innovation, connection.from, connection.to, connection.weight
+            )?;
+            if !connection.enabled {
+                write!(f, " (disabled)")?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+    use crate::ai_snake::neural_network::mutation::MutationOperator;
+
+    fn genome(rng: &mut ChaCha8Rng) -> NeatGenome {
+        NeatGenome::minimal(
+            3,
+            2,
+            ActivationFunction::Tanh,
+            ActivationFunction::Identity,
+            rng,
+        )
+    }
+
+    fn grow(genome: &mut NeatGenome, innovations: &mut Innovations, rng: &mut ChaCha8Rng) {
+        let config = NeatConfig {
+            add_connection: 1.,
+            add_node: 1.,
+            ..Default::default()
+        };
+        let mutation = Mutation {
+            operator: MutationOperator::Gaussian,
+            rate: 0.5,
+            sigma: 0.5,
+            self_adaptive: false,
+        };
+        genome.mutate(&mutation, &config, innovations, rng);
+    }
+
+    #[test]
+    fn minimal_genome_is_linear() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let genome = genome(&mut rng);
+        let input = [0.5, -1., 2.];
+        let mut expected = vec![0.; 2];
+        for c in &genome.connections {
+            let x = if c.from == 3 { 1. } else { input[c.from] };
+            expected[c.to - 4] += x * c.weight;
+        }
+        let sum: f64 = expected.iter().map(|x| x.abs()).sum();
+        expected.iter_mut().for_each(|x| *x /= sum);
+
+        let output = genome
+            .forward_with(&input, &mut Scratch::default())
+            .to_vec();
+        for (o, e) in output.iter().zip(&expected) {
+            assert!((o - e).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn split_connection_keeps_its_path() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let mut genome = genome(&mut rng);
+        let mut innovations = Innovations::default();
+        innovations.observe(&genome);
+        genome.add_node(&mut innovations, &mut rng);
+
+        assert_eq!(genome.hidden_nodes(), 1);
+        assert_eq!(genome.connections.iter().filter(|c| !c.enabled).count(), 1);
+        assert_eq!(genome.connections.len(), 10);
+        assert!(genome
+            .connections
+            .windows(2)
+            .all(|w| w[0].innovation < w[1].innovation));
+        genome.forward_with(&[1., 1., 1.], &mut Scratch::default());
+    }
+
+    #[test]
+    fn same_mutation_gets_same_innovation() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let mut innovations = Innovations::default();
+        let mut first = genome(&mut rng);
+        innovations.observe(&first);
+        let mut second = first.clone();
+        first.add_node(&mut innovations, &mut ChaCha8Rng::seed_from_u64(1));
+        second.add_node(&mut innovations, &mut ChaCha8Rng::seed_from_u64(1));
+        assert!(first == second);
+        assert_eq!(first.compatibility(&second, &NeatConfig::default()), 0.);
+    }
+
+    #[test]
+    fn growth_stays_acyclic() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let mut innovations = Innovations::default();
+        let mut genome = genome(&mut rng);
+        innovations.observe(&genome);
+        for _ in 0..50 {
+            grow(&mut genome, &mut innovations, &mut rng);
+        }
+        assert!(genome.hidden_nodes() > 10);
+        let evaluated = genome.compile().order.len();
+        assert_eq!(evaluated, genome.hidden_nodes() + genome.outputs);
+    }
+
+    #[test]
+    fn crossover_keeps_the_fitter_structure() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let mut innovations = Innovations::default();
+        let mut fitter = genome(&mut rng);
+        innovations.observe(&fitter);
+        let mut other = fitter.clone();
+        for _ in 0..5 {
+            grow(&mut fitter, &mut innovations, &mut rng);
+            grow(&mut other, &mut innovations, &mut rng);
+        }
+        let child = NeatGenome::cross(&fitter, &other, &mut rng);
+        assert_eq!(child.nodes, fitter.nodes);
+        let innovations =
+            |g: &NeatGenome| -> Vec<usize> { g.connections.iter().map(|c| c.innovation).collect() };
+        assert_eq!(innovations(&child), innovations(&fitter));
+        assert!(fitter.compatibility(&other, &NeatConfig::default()) > 0.);
+    }
+}
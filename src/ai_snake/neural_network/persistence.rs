@@ -0,0 +1,559 @@
+use std::{
+    fmt, fs,
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    brain::Brain,
+    neat::{NeatGenome, NodeKind},
+    LayerKind, NeuralNetwork,
+};
+
+/// Version written in every brain file, bumped when the layout changes
+pub const BRAIN_FORMAT_VERSION: u32 = 1;
+
+/// First bytes of a binary brain file, JSON files start with `{`
+const BINARY_MAGIC: &[u8; 4] = b"SNKB";
+
+/// Version written in every NEAT genome file, bumped when the layout changes
+pub const NEAT_FORMAT_VERSION: u32 = 1;
+
+const NEAT_MAGIC: &[u8; 4] = b"SNKN";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BrainFormat {
+    Json,
+    Binary,
+}
+
+#[derive(Debug)]
+pub enum BrainError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Binary(bincode::Error),
+    UnsupportedVersion(u32),
+    NoLayers,
+    /// The sizes of a convolution or pooling layer do not match its planes
+    PlanesShape {
+        layer: usize,
+    },
+    /// A convolution has no kernel, or a layer has outputs computed from no weights
+    EmptyRows {
+        layer: usize,
+    },
+    WeightsShape {
+        layer: usize,
+        input_dim: usize,
+        output_dim: usize,
+    },
+    BiasesShape {
+        layer: usize,
+        expected: usize,
+        found: usize,
+    },
+    LayerMismatch {
+        layer: usize,
+        output_dim: usize,
+        next_input_dim: usize,
+    },
+    /// Genome nodes do not start with the inputs, the bias and the outputs
+    GenomeLayout,
+    MissingNode {
+        innovation: usize,
+        node: usize,
+    },
+    InputTarget {
+        innovation: usize,
+        node: usize,
+    },
+}
+
+#[derive(Serialize)]
+struct BrainFileRef<'a> {
+    version: u32,
+    network: &'a NeuralNetwork,
+}
+
+/// The version is checked beforehand through [`VersionOnly`]
+#[derive(Deserialize)]
+struct BrainFile {
+    network: NeuralNetwork,
+}
+
+#[derive(Serialize)]
+struct GenomeFileRef<'a> {
+    version: u32,
+    genome: &'a NeatGenome,
+}
+
+#[derive(Deserialize)]
+struct GenomeFile {
+    genome: NeatGenome,
+}
+
+#[derive(Deserialize)]
+struct VersionOnly {
+    version: u32,
+}
+
+impl BrainFormat {
+    /// `.json` files are human-readable, anything else is binary
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => BrainFormat::Json,
+            _ => BrainFormat::Binary,
+        }
+    }
+}
+
+impl NeuralNetwork {
+    pub fn to_json(&self) -> Result<String, BrainError> {
+        serde_json::to_string_pretty(&BrainFileRef {
+            version: BRAIN_FORMAT_VERSION,
+            network: self,
+        })
+        .map_err(BrainError::Json)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, BrainError> {
+        let value: serde_json::Value = serde_json::from_str(json).map_err(BrainError::Json)?;
+        let VersionOnly { version } =
+            serde_json::from_value(value.clone()).map_err(BrainError::Json)?;
+        if version != BRAIN_FORMAT_VERSION {
+            return Err(BrainError::UnsupportedVersion(version));
+        }
+        let network = serde_json::from_value::<BrainFile>(value)
+            .map_err(BrainError::Json)?
+            .network;
+        network.validate()?;
+        Ok(network)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, BrainError> {
+        let mut bytes = BINARY_MAGIC.to_vec();
+        bytes.extend_from_slice(&BRAIN_FORMAT_VERSION.to_le_bytes());
+        bincode::serialize_into(&mut bytes, self).map_err(BrainError::Binary)?;
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BrainError> {
+        let mut reader = bytes;
+        let mut magic = [0; 4];
+        let mut version = [0; 4];
+        reader.read_exact(&mut magic).map_err(BrainError::Io)?;
+        if &magic != BINARY_MAGIC {
+            return Err(BrainError::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a binary brain file",
+            )));
+        }
+        reader.read_exact(&mut version).map_err(BrainError::Io)?;
+        let version = u32::from_le_bytes(version);
+        if version != BRAIN_FORMAT_VERSION {
+            return Err(BrainError::UnsupportedVersion(version));
+        }
+        let network: NeuralNetwork =
+            bincode::deserialize_from(reader).map_err(BrainError::Binary)?;
+        network.validate()?;
+        Ok(network)
+    }
+
+    pub fn save(&self, path: &Path, format: BrainFormat) -> Result<(), BrainError> {
+        let bytes = match format {
+            BrainFormat::Json => self.to_json()?.into_bytes(),
+            BrainFormat::Binary => self.to_bytes()?,
+        };
+        write_file(path, &bytes)
+    }
+
+    /// Loads a brain written by [`NeuralNetwork::save`], whatever its format
+    pub fn load(path: &Path) -> Result<Self, BrainError> {
+        let bytes = fs::read(path).map_err(BrainError::Io)?;
+        if bytes.starts_with(BINARY_MAGIC) {
+            Self::from_bytes(&bytes)
+        } else {
+            let json = String::from_utf8(bytes)
+                .map_err(|e| BrainError::Io(io::Error::new(io::ErrorKind::InvalidData, e)))?;
+            Self::from_json(&json)
+        }
+    }
+
+    /// Checks that every layer has the shape it claims and that layers chain correctly
+    pub fn validate(&self) -> Result<(), BrainError> {
+        if self.layers.is_empty() {
+            return Err(BrainError::NoLayers);
+        }
+        for (l, layer) in self.layers.iter().enumerate() {
+            let planes = match layer.kind {
+                LayerKind::Dense => true,
+                LayerKind::Flatten => layer.input_dim == layer.output_dim,
+                LayerKind::Conv2d { input, .. } | LayerKind::MaxPool { input, .. } => {
+                    input.len() == layer.input_dim && layer.output_shape().len() == layer.output_dim
+                }
+            };
+            if !planes {
+                return Err(BrainError::PlanesShape { layer: l });
+            }
+            // forward splits the weights into rows, which cannot be empty
+            let no_kernel = matches!(layer.kind, LayerKind::Conv2d { kernel: 0, .. });
+            if no_kernel || (layer.rows() > 0 && layer.row_len() == 0) {
+                return Err(BrainError::EmptyRows { layer: l });
+            }
+            if layer.weights.len() != layer.rows() * layer.row_len() {
+                return Err(BrainError::WeightsShape {
+                    layer: l,
+                    input_dim: layer.input_dim,
+                    output_dim: layer.output_dim,
+                });
+            }
+            if layer.biases.len() != layer.rows() {
+                return Err(BrainError::BiasesShape {
+                    layer: l,
+                    expected: layer.rows(),
+                    found: layer.biases.len(),
+                });
+            }
+            if let Some(next) = self.layers.get(l + 1) {
+                if next.input_dim != layer.output_dim {
+                    return Err(BrainError::LayerMismatch {
+                        layer: l,
+                        output_dim: layer.output_dim,
+                        next_input_dim: next.input_dim,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl NeatGenome {
+    pub fn to_json(&self) -> Result<String, BrainError> {
+        serde_json::to_string_pretty(&GenomeFileRef {
+            version: NEAT_FORMAT_VERSION,
+            genome: self,
+        })
+        .map_err(BrainError::Json)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, BrainError> {
+        let value: serde_json::Value = serde_json::from_str(json).map_err(BrainError::Json)?;
+        let VersionOnly { version } =
+            serde_json::from_value(value.clone()).map_err(BrainError::Json)?;
+        if version != NEAT_FORMAT_VERSION {
+            return Err(BrainError::UnsupportedVersion(version));
+        }
+        let genome = serde_json::from_value::<GenomeFile>(value)
+            .map_err(BrainError::Json)?
+            .genome;
+        genome.validate()?;
+        Ok(genome)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, BrainError> {
+        let mut bytes = NEAT_MAGIC.to_vec();
+        bytes.extend_from_slice(&NEAT_FORMAT_VERSION.to_le_bytes());
+        bincode::serialize_into(&mut bytes, self).map_err(BrainError::Binary)?;
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BrainError> {
+        let mut reader = bytes;
+        let mut magic = [0; 4];
+        let mut version = [0; 4];
+        reader.read_exact(&mut magic).map_err(BrainError::Io)?;
+        if &magic != NEAT_MAGIC {
+            return Err(BrainError::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a binary NEAT genome file",
+            )));
+        }
+        reader.read_exact(&mut version).map_err(BrainError::Io)?;
+        let version = u32::from_le_bytes(version);
+        if version != NEAT_FORMAT_VERSION {
+            return Err(BrainError::UnsupportedVersion(version));
+        }
+        let genome: NeatGenome = bincode::deserialize_from(reader).map_err(BrainError::Binary)?;
+        genome.validate()?;
+        Ok(genome)
+    }
+
+    /// Checks the node layout and that every connection links existing nodes
+    pub fn validate(&self) -> Result<(), BrainError> {
+        let expected = (0..self.inputs)
+            .map(|_| NodeKind::Input)
+            .chain([NodeKind::Bias])
+            .chain((0..self.outputs).map(|_| NodeKind::Output));
+        let mut count = 0;
+        for (node, kind) in self.nodes.iter().zip(expected) {
+            if node.kind != kind || node.id != count {
+                return Err(BrainError::GenomeLayout);
+            }
+            count += 1;
+        }
+        if count != self.inputs + 1 + self.outputs
+            || self.nodes[count..]
+                .iter()
+                .any(|node| node.kind != NodeKind::Hidden)
+        {
+            return Err(BrainError::GenomeLayout);
+        }
+        for connection in &self.connections {
+            for node in [connection.from, connection.to] {
+                if !self.nodes.iter().any(|n| n.id == node) {
+                    return Err(BrainError::MissingNode {
+                        innovation: connection.innovation,
+                        node,
+                    });
+                }
+            }
+            if connection.to <= self.inputs {
+                return Err(BrainError::InputTarget {
+                    innovation: connection.innovation,
+                    node: connection.to,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Brain {
+    /// Layered networks and NEAT genomes each keep their own file format
+    pub fn save(&self, path: &Path, format: BrainFormat) -> Result<(), BrainError> {
+        match self {
+            Brain::Layered(network) => network.save(path, format),
+            Brain::Neat(genome) => {
+                let bytes = match format {
+                    BrainFormat::Json => genome.to_json()?.into_bytes(),
+                    BrainFormat::Binary => genome.to_bytes()?,
+                };
+                write_file(path, &bytes)
+            }
+        }
+    }
+
+    /// Loads a brain written by [`Brain::save`], whatever its kind and format
+    pub fn load(path: &Path) -> Result<Self, BrainError> {
+        let bytes = fs::read(path).map_err(BrainError::Io)?;
+        if bytes.starts_with(BINARY_MAGIC) {
+            return NeuralNetwork::from_bytes(&bytes).map(Brain::Layered);
+        }
+        if bytes.starts_with(NEAT_MAGIC) {
+            return NeatGenome::from_bytes(&bytes).map(Brain::Neat);
+        }
+        let json = String::from_utf8(bytes)
+            .map_err(|e| BrainError::Io(io::Error::new(io::ErrorKind::InvalidData, e)))?;
+        let value: serde_json::Value = serde_json::from_str(&json).map_err(BrainError::Json)?;
+        if value.get("genome").is_some() {
+            NeatGenome::from_json(&json).map(Brain::Neat)
+        } else {
+            NeuralNetwork::from_json(&json).map(Brain::Layered)
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), BrainError> {
+        match self {
+            Brain::Layered(network) => network.validate(),
+            Brain::Neat(genome) => genome.validate(),
+        }
+    }
+}
+
+fn write_file(path: &Path, bytes: &[u8]) -> Result<(), BrainError> {
+    fs::File::create(path)
+        .and_then(|mut file| file.write_all(bytes))
+        .map_err(BrainError::Io)
+}
+
+impl fmt::Display for BrainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BrainError::Io(e) => write!(f, "could not access brain file: {e}"),
+            BrainError::Json(e) => write!(f, "invalid JSON brain: {e}"),
+            BrainError::Binary(e) => write!(f, "invalid binary brain: {e}"),
+            BrainError::UnsupportedVersion(v) => {
+                write!(f, "unsupported brain format version {v}")
+            }
+            BrainError::NoLayers => write!(f, "brain has no layers"),
+            BrainError::PlanesShape { layer } => {
+                write!(f, "layer {layer}: sizes do not match the planes it reads")
+            }
+            BrainError::EmptyRows { layer } => {
+                write!(f, "layer {layer}: outputs are computed from no weights")
+            }
+            BrainError::WeightsShape {
+                layer,
+                input_dim,
+                output_dim,
+            } => write!(
+                f,
+                "layer {layer}: weights do not fit {input_dim} inputs and {output_dim} outputs"
+            ),
+            BrainError::BiasesShape {
+                layer,
+                expected,
+                found,
+            } => write!(
+                f,
+                "layer {layer}: expected {expected} biases, found {found}"
+            ),
+            BrainError::LayerMismatch {
+                layer,
+                output_dim,
+                next_input_dim,
+            } => write!(
+                f,
+                "layer {layer} outputs {output_dim} values but layer {} expects {next_input_dim}",
+                layer + 1
+            ),
+            BrainError::GenomeLayout => write!(
+                f,
+                "genome nodes do not start with the inputs, the bias and the outputs"
+            ),
+            BrainError::MissingNode { innovation, node } => write!(
+                f,
+                "connection {innovation} refers to node {node} which does not exist"
+            ),
+            BrainError::InputTarget { innovation, node } => write!(
+                f,
+                "connection {innovation} feeds node {node} which is an input or the bias"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BrainError {}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+    use crate::ai_snake::neural_network::{
+        architecture::Architecture, ActivationFunction, Layer, Shape,
+    };
+
+    fn network() -> NeuralNetwork {
+        Architecture::default().build(16, 4, &mut ChaCha8Rng::seed_from_u64(0))
+    }
+
+    #[test]
+    fn both_formats_round_trip() {
+        let network = network();
+        let json = NeuralNetwork::from_json(&network.to_json().unwrap()).unwrap();
+        let binary = NeuralNetwork::from_bytes(&network.to_bytes().unwrap()).unwrap();
+        assert!(json == network);
+        assert!(binary == network);
+    }
+
+    #[test]
+    fn files_are_read_in_the_format_they_were_written() {
+        let network = network();
+        for extension in ["json", "bin"] {
+            let path =
+                std::env::temp_dir().join(format!("brain_{}.{extension}", std::process::id()));
+            let format = BrainFormat::from_path(&path);
+            network.save(&path, format).unwrap();
+            let bytes = fs::read(&path).unwrap();
+            let loaded = NeuralNetwork::load(&path);
+            fs::remove_file(&path).unwrap();
+            assert_eq!(
+                bytes.starts_with(BINARY_MAGIC),
+                format == BrainFormat::Binary
+            );
+            assert!(loaded.unwrap() == network);
+        }
+    }
+
+    #[test]
+    fn layers_must_chain() {
+        let layer = |input_dim, output_dim| {
+            Layer::new(
+                input_dim,
+                output_dim,
+                vec![0.; input_dim * output_dim],
+                vec![0.; output_dim],
+                ActivationFunction::Identity,
+            )
+        };
+        let mut network = NeuralNetwork::new();
+        network.add_layer(layer(2, 3)).add_layer(layer(4, 1));
+        assert!(matches!(
+            NeuralNetwork::from_json(&network.to_json().unwrap()),
+            Err(BrainError::LayerMismatch {
+                layer: 0,
+                output_dim: 3,
+                next_input_dim: 4
+            })
+        ));
+
+        network.layers[1] = layer(3, 1);
+        network.layers[1].weights.pop();
+        assert!(matches!(
+            NeuralNetwork::from_bytes(&network.to_bytes().unwrap()),
+            Err(BrainError::WeightsShape { layer: 1, .. })
+        ));
+        assert!(matches!(
+            NeuralNetwork::new().validate(),
+            Err(BrainError::NoLayers)
+        ));
+    }
+
+    #[test]
+    fn empty_rows_are_rejected() {
+        let planes = Shape {
+            channels: 1,
+            height: 3,
+            width: 3,
+        };
+        let mut network = NeuralNetwork::new();
+        network.add_layer(Layer::conv2d(
+            planes,
+            2,
+            0,
+            vec![],
+            vec![0.; 2],
+            ActivationFunction::Identity,
+        ));
+        assert!(matches!(
+            NeuralNetwork::from_bytes(&network.to_bytes().unwrap()),
+            Err(BrainError::EmptyRows { layer: 0 })
+        ));
+
+        network.layers[0] = Layer::new(0, 2, vec![], vec![0.; 2], ActivationFunction::Identity);
+        assert!(matches!(
+            NeuralNetwork::from_json(&network.to_json().unwrap()),
+            Err(BrainError::EmptyRows { layer: 0 })
+        ));
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let json = r#"{
+            "version": 3,
+            "network": { "layers": [{
+                "input_dim": 2, "output_dim": 3,
+                "weights": [[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]],
+                "biases": [0.0, 0.0, 0.0],
+                "activation": "Identity",
+                "use_bias": false
+            }] }
+        }"#;
+        assert!(matches!(
+            NeuralNetwork::from_json(json),
+            Err(BrainError::UnsupportedVersion(3))
+        ));
+
+        let mut bytes = NeuralNetwork::default().to_bytes().unwrap();
+        bytes[4..8].copy_from_slice(&(BRAIN_FORMAT_VERSION + 1).to_le_bytes());
+        assert!(matches!(
+            NeuralNetwork::from_bytes(&bytes),
+            Err(BrainError::UnsupportedVersion(_))
+        ));
+    }
+}
@@ -0,0 +1,250 @@
+use std::{fmt, str::FromStr};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// How the parents of the next generation are picked from the fitness of the current one
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum SelectionStrategy {
+    /// Models with a fitness of at least `(1 - keep_x_best) * best_fitness`, picked uniformly
+    Threshold,
+    /// Best of `tournament_size` models drawn at random
+    #[default]
+    Tournament,
+    /// Probability proportional to the fitness, shifted to be positive if some is negative
+    Roulette,
+    /// Probability proportional to the rank, the worst model has rank 1
+    Rank,
+    /// The best `keep_x_best` fraction of the population, picked uniformly
+    Truncation,
+}
+
+/// Selection strategy of a run along with its parameters
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SelectionConfig {
+    pub strategy: SelectionStrategy,
+    /// Used by the threshold and truncation selections
+    pub keep_x_best: f64,
+    /// Models drawn per tournament
+    pub tournament_size: usize,
+}
+
+/// Selection prepared for one generation
+pub enum Selector {
+    /// Parents are drawn uniformly from these models
+    Pool(Vec<usize>),
+    Tournament {
+        fitness: Vec<f64>,
+        size: usize,
+    },
+    /// Cumulative weights of the models
+    Weighted(Vec<f64>),
+}
+
+impl SelectionStrategy {
+    pub const ALL: [SelectionStrategy; 5] = [
+        SelectionStrategy::Threshold,
+        SelectionStrategy::Tournament,
+        SelectionStrategy::Roulette,
+        SelectionStrategy::Rank,
+        SelectionStrategy::Truncation,
+    ];
+}
+
+impl SelectionConfig {
+    pub fn selector(&self, fitness: &[f64]) -> Selector {
+        match self.strategy {
+            SelectionStrategy::Threshold => {
+                let best = fitness.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+                // relative to the best, so the threshold falls below it when fitness is negative
+                let threshold = best - self.keep_x_best * best.abs();
+                Selector::Pool(
+                    (0..fitness.len())
+                        .filter(|&i| fitness[i] >= threshold)
+                        .collect(),
+                )
+            }
+            SelectionStrategy::Tournament => Selector::Tournament {
+                fitness: fitness.to_vec(),
+                size: self.tournament_size.max(1),
+            },
+            SelectionStrategy::Roulette => {
+                let lowest = fitness.iter().copied().fold(0., f64::min);
+                if fitness.iter().all(|&f| f == lowest) {
+                    // nothing to tell the models apart
+                    Selector::Pool((0..fitness.len()).collect())
+                } else {
+                    Selector::weighted(fitness.iter().map(|&f| f - lowest))
+                }
+            }
+            SelectionStrategy::Rank => {
+                let mut ranks = vec![0.; fitness.len()];
+                for (rank, i) in by_fitness(fitness).into_iter().rev().enumerate() {
+                    ranks[i] = (rank + 1) as f64;
+                }
+                Selector::weighted(ranks)
+            }
+            SelectionStrategy::Truncation => {
+                let kept = ((self.keep_x_best * fitness.len() as f64).ceil() as usize)
+                    .clamp(1.min(fitness.len()), fitness.len());
+                let mut pool = by_fitness(fitness);
+                pool.truncate(kept);
+                Selector::Pool(pool)
+            }
+        }
+    }
+
+    /// Name and parameters, as printed in the generation log
+    pub fn describe(&self) -> String {
+        let strategy = self.strategy;
+        match strategy {
+            SelectionStrategy::Threshold | SelectionStrategy::Truncation => {
+                format!("{strategy} (x={})", self.keep_x_best)
+            }
+            SelectionStrategy::Tournament => format!("{strategy} (k={})", self.tournament_size),
+            SelectionStrategy::Roulette | SelectionStrategy::Rank => strategy.to_string(),
+        }
+    }
+}
+
+/// Indices of the models, fittest first
+fn by_fitness(fitness: &[f64]) -> Vec<usize> {
+    let mut indices: Vec<usize> = (0..fitness.len()).collect();
+    indices.sort_by(|&a, &b| fitness[b].total_cmp(&fitness[a]));
+    indices
+}
+
+impl Selector {
+    fn weighted(weights: impl IntoIterator<Item = f64>) -> Self {
+        let mut total = 0.;
+        Selector::Weighted(
+            weights
+                .into_iter()
+                .map(|w| {
+                    total += w;
+                    total
+                })
+                .collect(),
+        )
+    }
+
+    /// Models every parent is drawn from, if they are known beforehand
+    pub fn pool(&self) -> Option<&[usize]> {
+        match self {
+            Selector::Pool(pool) => Some(pool),
+            _ => None,
+        }
+    }
+
+    /// Index of one parent, `None` for an empty population
+    pub fn pick(&self, rng: &mut impl Rng) -> Option<usize> {
+        match self {
+            Selector::Pool(pool) if pool.is_empty() => None,
+            Selector::Pool(pool) => Some(pool[rng.gen_range(0..pool.len())]),
+            Selector::Tournament { fitness, .. } if fitness.is_empty() => None,
+            Selector::Tournament { fitness, size } => (0..*size)
+                .map(|_| rng.gen_range(0..fitness.len()))
+                .max_by(|&a, &b| fitness[a].total_cmp(&fitness[b])),
+            Selector::Weighted(cumulative) => {
+                let total = *cumulative.last()?;
+                let target = rng.gen::<f64>() * total;
+                Some(
+                    cumulative
+                        .partition_point(|&c| c <= target)
+                        .min(cumulative.len() - 1),
+                )
+            }
+        }
+    }
+}
+
+impl fmt::Display for SelectionStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SelectionStrategy::Threshold => write!(f, "threshold"),
+            SelectionStrategy::Tournament => write!(f, "tournament"),
+            SelectionStrategy::Roulette => write!(f, "roulette"),
+            SelectionStrategy::Rank => write!(f, "rank"),
+            SelectionStrategy::Truncation => write!(f, "truncation"),
+        }
+    }
+}
+
+impl FromStr for SelectionStrategy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        SelectionStrategy::ALL
+            .into_iter()
+            .find(|strategy| strategy.to_string() == s)
+            .ok_or(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+
+    fn config(strategy: SelectionStrategy) -> SelectionConfig {
+        SelectionConfig {
+            strategy,
+            keep_x_best: 0.5,
+            tournament_size: 3,
+        }
+    }
+
+    fn counts(strategy: SelectionStrategy, fitness: &[f64]) -> Vec<usize> {
+        let selector = config(strategy).selector(fitness);
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let mut counts = vec![0; fitness.len()];
+        for _ in 0..10000 {
+            counts[selector.pick(&mut rng).unwrap()] += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn threshold_keeps_models_close_to_the_best() {
+        let selector = config(SelectionStrategy::Threshold).selector(&[10., 4., 6., 5.]);
+        assert_eq!(selector.pool(), Some([0, 2, 3].as_slice()));
+    }
+
+    #[test]
+    fn truncation_keeps_the_best_fraction() {
+        let selector = config(SelectionStrategy::Truncation).selector(&[1., 4., 6., 5.]);
+        assert_eq!(selector.pool(), Some([2, 3].as_slice()));
+    }
+
+    #[test]
+    fn roulette_never_picks_zero_scores() {
+        let counts = counts(SelectionStrategy::Roulette, &[0., 1., 3.]);
+        assert_eq!(counts[0], 0);
+        assert!(counts[2] > 2 * counts[1]);
+    }
+
+    #[test]
+    fn roulette_shifts_negative_fitness() {
+        let counts = counts(SelectionStrategy::Roulette, &[-2., -1., 1.]);
+        assert_eq!(counts[0], 0);
+        assert!(counts[2] > counts[1]);
+    }
+
+    #[test]
+    fn rank_and_tournament_prefer_better_scores() {
+        for strategy in [SelectionStrategy::Rank, SelectionStrategy::Tournament] {
+            let counts = counts(strategy, &[0., 100., 1.]);
+            assert!(counts[1] > counts[2] && counts[2] > counts[0], "{strategy}");
+        }
+    }
+
+    #[test]
+    fn empty_population_has_no_parent() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        for strategy in SelectionStrategy::ALL {
+            assert_eq!(config(strategy).selector(&[]).pick(&mut rng), None);
+        }
+    }
+}
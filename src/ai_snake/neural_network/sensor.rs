@@ -0,0 +1,876 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use super::Shape;
+use crate::snake_core::{
+    snake::Snake,
+    universe::{Direction, Food, Universe},
+};
+
+/// Order of the one-hot encodings of directions
+const COMPASS: [Direction; 4] = [
+    Direction::Up,
+    Direction::Down,
+    Direction::Left,
+    Direction::Right,
+];
+
+/// Part of the brain input, computed from what surrounds the snake
+pub trait Sensor: Send + Sync {
+    /// Name of each value appended by `sense`, in order
+    fn labels(&self) -> Vec<String>;
+
+    /// Number of values appended by `sense`, the length of `labels`
+    fn size(&self) -> usize;
+
+    /// Appends what `snake` perceives of `universe` to `input`
+    fn sense(&self, universe: &Universe, snake: &Snake, input: &mut Vec<f64>);
+}
+
+/// Sensors that can be enabled, see [`SensorConfig`]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SensorKind {
+    /// Distance to an obstacle and to food in 8 directions
+    Rays,
+    /// One-hot direction of the head
+    HeadDirection,
+    /// One-hot direction the tail moves towards
+    TailDirection,
+    /// Cells taken by the snake, relative to the grid
+    Length,
+    /// Offset from the head to the closest food, relative to the grid
+    FoodVector,
+    /// Distance from the head to the closest wall, the same in every frame
+    WallDistance,
+    /// Obstacles in a square window centred on the head
+    Occupancy,
+    /// Body, head, food and wall planes of the board or of a window centred on the head
+    Planes,
+}
+
+/// Orientation of what the sensors perceive
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Frame {
+    /// Up is the top of the grid
+    #[default]
+    Absolute,
+    /// Up is ahead of the head, so what the snake perceives does not depend on its heading
+    Egocentric,
+}
+
+/// What the planes of [`SensorKind::Planes`] cover
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum PlaneView {
+    /// The whole board, the head can be anywhere
+    Board,
+    /// A window centred on the head
+    #[default]
+    Window,
+}
+
+/// Which sensors make up the brain input, the input size follows from it
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SensorConfig {
+    /// In the order their values appear in the input
+    pub enabled: Vec<SensorKind>,
+    /// Side of the occupancy window, in cells
+    pub window: usize,
+    /// Orientation of the rays, directions, food vector, occupancy window and planes
+    pub frame: Frame,
+    pub planes: PlaneView,
+    /// Side of the planes, in cells, the side of the grid plus one shows the whole board
+    pub plane_size: usize,
+}
+
+/// Sensors built from a [`SensorConfig`], ready to observe grids
+pub struct Sensors(Vec<Box<dyn Sensor>>);
+
+pub struct Rays {
+    pub vision_range: i64,
+    pub frame: Frame,
+}
+
+pub struct HeadDirection {
+    pub frame: Frame,
+}
+
+pub struct TailDirection {
+    pub frame: Frame,
+}
+
+pub struct Length;
+
+pub struct FoodVector {
+    pub frame: Frame,
+}
+
+pub struct WallDistance;
+
+pub struct Occupancy {
+    pub window: usize,
+    pub frame: Frame,
+}
+
+pub struct Planes {
+    pub view: PlaneView,
+    pub size: usize,
+    pub frame: Frame,
+}
+
+/// Order of the channels of [`Planes`]
+const PLANES: [&str; 4] = ["body", "head", "food", "wall"];
+
+impl SensorKind {
+    pub const ALL: [SensorKind; 8] = [
+        SensorKind::Rays,
+        SensorKind::HeadDirection,
+        SensorKind::TailDirection,
+        SensorKind::Length,
+        SensorKind::FoodVector,
+        SensorKind::WallDistance,
+        SensorKind::Occupancy,
+        SensorKind::Planes,
+    ];
+}
+
+impl Frame {
+    pub const ALL: [Frame; 2] = [Frame::Absolute, Frame::Egocentric];
+
+    /// Offset in the grid of `(x, y)`, given in this frame for a snake heading towards `heading`
+    fn to_grid(self, heading: &Direction, (x, y): (i64, i64)) -> (i64, i64) {
+        match (self, heading) {
+            (Frame::Absolute, _) | (Frame::Egocentric, Direction::Up) => (x, y),
+            (Frame::Egocentric, Direction::Down) => (-x, -y),
+            (Frame::Egocentric, Direction::Left) => (-y, x),
+            (Frame::Egocentric, Direction::Right) => (y, -x),
+        }
+    }
+
+    /// Offset in this frame of `(x, y)`, given in the grid
+    fn to_frame(self, heading: &Direction, (x, y): (i64, i64)) -> (i64, i64) {
+        match (self, heading) {
+            (Frame::Absolute, _) | (Frame::Egocentric, Direction::Up) => (x, y),
+            (Frame::Egocentric, Direction::Down) => (-x, -y),
+            (Frame::Egocentric, Direction::Left) => (y, -x),
+            (Frame::Egocentric, Direction::Right) => (-y, x),
+        }
+    }
+
+    /// Name of a direction of this frame, given by its absolute name
+    fn name(self, absolute: &str) -> String {
+        match self {
+            Frame::Absolute => absolute.to_string(),
+            Frame::Egocentric => absolute.replace("up", "forward").replace("down", "back"),
+        }
+    }
+}
+
+impl PlaneView {
+    pub const ALL: [PlaneView; 2] = [PlaneView::Board, PlaneView::Window];
+}
+
+/// The 8 rays, as snakes always saw
+impl Default for SensorConfig {
+    fn default() -> Self {
+        SensorConfig {
+            enabled: vec![SensorKind::Rays],
+            window: 5,
+            frame: Frame::default(),
+            planes: PlaneView::default(),
+            plane_size: 9,
+        }
+    }
+}
+
+impl SensorConfig {
+    /// Sizes the planes to the whole board of a grid of side `grid_size`, walls included
+    pub fn show_board(&mut self, grid_size: u64) {
+        self.plane_size = grid_size as usize + 1;
+    }
+
+    pub fn build(&self, vision_range: i64) -> Sensors {
+        let frame = self.frame;
+        Sensors(
+            self.enabled
+                .iter()
+                .map(|kind| -> Box<dyn Sensor> {
+                    match kind {
+                        SensorKind::Rays => Box::new(Rays {
+                            vision_range,
+                            frame,
+                        }),
+                        SensorKind::HeadDirection => Box::new(HeadDirection { frame }),
+                        SensorKind::TailDirection => Box::new(TailDirection { frame }),
+                        SensorKind::Length => Box::new(Length),
+                        SensorKind::FoodVector => Box::new(FoodVector { frame }),
+                        SensorKind::WallDistance => Box::new(WallDistance),
+                        SensorKind::Occupancy => Box::new(Occupancy {
+                            window: self.window,
+                            frame,
+                        }),
+                        SensorKind::Planes => Box::new(Planes {
+                            view: self.planes,
+                            size: self.plane_size,
+                            frame,
+                        }),
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    /// Size of the brain input, the vision range does not change it
+    pub fn input_size(&self) -> usize {
+        self.build(1).input_size()
+    }
+
+    /// Planes when they are the only sensor, so convolutions can slide over them,
+    /// a flat vector otherwise
+    pub fn input_shape(&self) -> Shape {
+        match self.enabled.as_slice() {
+            [SensorKind::Planes] => Shape {
+                channels: PLANES.len(),
+                height: self.plane_size,
+                width: self.plane_size,
+            },
+            _ => self.input_size().into(),
+        }
+    }
+
+    pub fn is_enabled(&self, kind: SensorKind) -> bool {
+        self.enabled.contains(&kind)
+    }
+
+    /// Enabled sensors are appended after the others
+    pub fn set_enabled(&mut self, kind: SensorKind, enabled: bool) {
+        self.enabled.retain(|&k| k != kind);
+        if enabled {
+            self.enabled.push(kind);
+        }
+    }
+}
+
+impl Sensors {
+    pub fn input_size(&self) -> usize {
+        self.0.iter().map(|sensor| sensor.size()).sum()
+    }
+
+    pub fn labels(&self) -> Vec<String> {
+        self.0.iter().flat_map(|sensor| sensor.labels()).collect()
+    }
+
+    /// What the first snake of `universe` perceives, `None` once it is dead
+    pub fn observe(&self, universe: &Universe) -> Option<Vec<f64>> {
+        let snake = universe.get_snake(0)?;
+        let mut input = Vec::with_capacity(self.input_size());
+        for sensor in &self.0 {
+            sensor.sense(universe, snake, &mut input);
+        }
+        Some(input)
+    }
+}
+
+/// Offsets of the rays, `v` goes up
+fn ray_name(u: i64, v: i64) -> &'static str {
+    match (u, v) {
+        (-1, -1) => "down-left",
+        (-1, 0) => "left",
+        (-1, 1) => "up-left",
+        (0, -1) => "down",
+        (0, 1) => "up",
+        (1, -1) => "down-right",
+        (1, 0) => "right",
+        _ => "up-right",
+    }
+}
+
+fn rays() -> impl Iterator<Item = (i64, i64)> {
+    (-1..=1)
+        .flat_map(|u| (-1..=1).map(move |v| (u, v)))
+        .filter(|&ray| ray != (0, 0))
+}
+
+impl Sensor for Rays {
+    fn labels(&self) -> Vec<String> {
+        rays()
+            .flat_map(|(u, v)| {
+                let name = self.frame.name(ray_name(u, v));
+                [format!("{name} obstacle"), format!("{name} food")]
+            })
+            .collect()
+    }
+
+    fn size(&self) -> usize {
+        2 * rays().count()
+    }
+
+    /// 1 next to the head down to 0 out of the vision range
+    fn sense(&self, universe: &Universe, snake: &Snake, input: &mut Vec<f64>) {
+        let (width, height) = (universe.width, universe.height);
+        let vision_range = self.vision_range;
+        for ray in rays() {
+            let (u, v) = self.frame.to_grid(&snake.direction, ray);
+            let counter = input.len();
+            input.push(0.);
+            input.push(0.);
+
+            for i in 1..=vision_range {
+                let pos = (
+                    (snake.positions[0].0 as i64 + (i * u)),
+                    (snake.positions[0].1 as i64 + (i * v)),
+                );
+                if pos.0 > width as i64
+                    || pos.0 < 0
+                    || pos.1 > height as i64
+                    || pos.1 < 0
+                    || snake.is_in_pos((pos.0 as u64, pos.1 as u64))
+                {
+                    input[counter] = 1. - i as f64 / vision_range as f64;
+                    break;
+                }
+            }
+
+            for i in 1..vision_range {
+                let pos = (
+                    (snake.positions[0].0 as i64 + (i * u)),
+                    (snake.positions[0].1 as i64 + (i * v)),
+                );
+                if pos.0 < width as i64
+                    && pos.0 >= 0
+                    && pos.1 < height as i64
+                    && pos.1 >= 0
+                    && universe.food.contains(&Food(pos.0 as u64, pos.1 as u64))
+                {
+                    input[counter + 1] = 1. - i as f64 / vision_range as f64;
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn compass_labels(prefix: &str, frame: Frame) -> Vec<String> {
+    COMPASS
+        .iter()
+        .map(|direction| {
+            let name = format!("{direction:?}").to_lowercase();
+            format!("{prefix} {}", frame.name(&name))
+        })
+        .collect()
+}
+
+/// Unit offset in the grid
+fn offset(direction: &Direction) -> (i64, i64) {
+    match direction {
+        Direction::Up => (0, 1),
+        Direction::Down => (0, -1),
+        Direction::Left => (-1, 0),
+        Direction::Right => (1, 0),
+    }
+}
+
+/// `direction` as seen in `frame` by a snake heading towards `heading`
+fn one_hot(frame: Frame, heading: &Direction, direction: Option<&Direction>, input: &mut Vec<f64>) {
+    let seen = direction.map(|direction| frame.to_frame(heading, offset(direction)));
+    input.extend(
+        COMPASS
+            .iter()
+            .map(|d| if Some(offset(d)) == seen { 1. } else { 0. }),
+    );
+}
+
+impl Sensor for HeadDirection {
+    fn labels(&self) -> Vec<String> {
+        compass_labels("head", self.frame)
+    }
+
+    fn size(&self) -> usize {
+        COMPASS.len()
+    }
+
+    /// Always forward in the egocentric frame
+    fn sense(&self, _: &Universe, snake: &Snake, input: &mut Vec<f64>) {
+        one_hot(self.frame, &snake.direction, Some(&snake.direction), input);
+    }
+}
+
+impl Sensor for TailDirection {
+    fn labels(&self) -> Vec<String> {
+        compass_labels("tail", self.frame)
+    }
+
+    fn size(&self) -> usize {
+        COMPASS.len()
+    }
+
+    /// The head direction while the snake is a single cell
+    fn sense(&self, _: &Universe, snake: &Snake, input: &mut Vec<f64>) {
+        let direction = match snake.positions.as_slice() {
+            [.., before, tail] => {
+                if before.0 > tail.0 {
+                    Some(Direction::Right)
+                } else if before.0 < tail.0 {
+                    Some(Direction::Left)
+                } else if before.1 > tail.1 {
+                    Some(Direction::Up)
+                } else if before.1 < tail.1 {
+                    Some(Direction::Down)
+                } else {
+                    None
+                }
+            }
+            _ => Some(snake.direction.clone()),
+        };
+        one_hot(self.frame, &snake.direction, direction.as_ref(), input);
+    }
+}
+
+impl Sensor for Length {
+    fn labels(&self) -> Vec<String> {
+        vec!["length".to_string()]
+    }
+
+    fn size(&self) -> usize {
+        1
+    }
+
+    fn sense(&self, universe: &Universe, snake: &Snake, input: &mut Vec<f64>) {
+        let cells = (universe.width * universe.height).max(1);
+        input.push(snake.positions.len() as f64 / cells as f64);
+    }
+}
+
+impl Sensor for FoodVector {
+    fn labels(&self) -> Vec<String> {
+        match self.frame {
+            Frame::Absolute => vec!["food x".to_string(), "food y".to_string()],
+            Frame::Egocentric => vec!["food right".to_string(), "food forward".to_string()],
+        }
+    }
+
+    fn size(&self) -> usize {
+        2
+    }
+
+    /// 0 without food
+    fn sense(&self, universe: &Universe, snake: &Snake, input: &mut Vec<f64>) {
+        let head = snake.positions[0];
+        let closest = universe
+            .food
+            .iter()
+            .min_by_key(|food| head.0.abs_diff(food.0) + head.1.abs_diff(food.1));
+        let (x, y) = closest.map_or((0., 0.), |food| {
+            let (x, y) = self.frame.to_frame(
+                &snake.direction,
+                (food.0 as i64 - head.0 as i64, food.1 as i64 - head.1 as i64),
+            );
+            (
+                x as f64 / universe.width.max(1) as f64,
+                y as f64 / universe.height.max(1) as f64,
+            )
+        });
+        input.push(x);
+        input.push(y);
+    }
+}
+
+impl Sensor for WallDistance {
+    fn labels(&self) -> Vec<String> {
+        vec!["nearest wall".to_string()]
+    }
+
+    fn size(&self) -> usize {
+        1
+    }
+
+    /// In cells, relative to the smallest side of the grid
+    fn sense(&self, universe: &Universe, snake: &Snake, input: &mut Vec<f64>) {
+        let (x, y) = snake.positions[0];
+        // the snake dies on the first and last rows and columns
+        let distance = x
+            .min(universe.width.saturating_sub(x))
+            .min(y)
+            .min(universe.height.saturating_sub(y));
+        let side = universe.width.min(universe.height).max(1);
+        input.push(distance as f64 / side as f64);
+    }
+}
+
+impl Occupancy {
+    /// Offsets from the head of the rows and columns of the window
+    fn offsets(&self) -> impl DoubleEndedIterator<Item = i64> + Clone {
+        let first = -(self.window.saturating_sub(1) as i64 / 2);
+        first..first + self.window as i64
+    }
+}
+
+impl Sensor for Occupancy {
+    fn labels(&self) -> Vec<String> {
+        self.offsets()
+            .rev()
+            .flat_map(|y| self.offsets().map(move |x| format!("window {x:+},{y:+}")))
+            .collect()
+    }
+
+    fn size(&self) -> usize {
+        self.window * self.window
+    }
+
+    /// 1 for walls and the snake, 0 for free cells, row by row from the top
+    fn sense(&self, universe: &Universe, snake: &Snake, input: &mut Vec<f64>) {
+        let (width, height) = (universe.width as i64, universe.height as i64);
+        let head = snake.positions[0];
+        for y in self.offsets().rev() {
+            for x in self.offsets() {
+                let (x, y) = self.frame.to_grid(&snake.direction, (x, y));
+                let cell = (head.0 as i64 + x, head.1 as i64 + y);
+                let blocked = cell.0 <= 0
+                    || cell.1 <= 0
+                    || cell.0 >= width
+                    || cell.1 >= height
+                    || snake.is_in_pos((cell.0 as u64, cell.1 as u64));
+                input.push(if blocked { 1. } else { 0. });
+            }
+        }
+    }
+}
+
+impl Planes {
+    /// Cell seen at `row` and `column` of the planes, rows start at the top
+    ///
+    /// In the egocentric frame the board turns about its centre and the window about the head.
+    fn cell(&self, snake: &Snake, row: usize, column: usize) -> (i64, i64) {
+        let last = self.size as i64 - 1;
+        let (x, y) = (column as i64 - last / 2, last / 2 - row as i64);
+        let (x, y) = self.frame.to_grid(&snake.direction, (x, y));
+        let centre = match self.view {
+            PlaneView::Board => (last / 2, last - last / 2),
+            PlaneView::Window => (snake.positions[0].0 as i64, snake.positions[0].1 as i64),
+        };
+        (centre.0 + x, centre.1 + y)
+    }
+}
+
+impl Sensor for Planes {
+    fn labels(&self) -> Vec<String> {
+        PLANES
+            .iter()
+            .flat_map(|plane| {
+                (0..self.size)
+                    .flat_map(move |r| (0..self.size).map(move |c| format!("{plane} {r},{c}")))
+            })
+            .collect()
+    }
+
+    fn size(&self) -> usize {
+        PLANES.len() * self.size * self.size
+    }
+
+    /// 1 where the plane holds, plane after plane and row after row from the top
+    fn sense(&self, universe: &Universe, snake: &Snake, input: &mut Vec<f64>) {
+        let (width, height) = (universe.width as i64, universe.height as i64);
+        let cells = || {
+            (0..self.size).flat_map(move |r| (0..self.size).map(move |c| self.cell(snake, r, c)))
+        };
+        let wall = |(x, y): (i64, i64)| x <= 0 || y <= 0 || x >= width || y >= height;
+        let at = |(x, y): (i64, i64), pos: (u64, u64)| (x, y) == (pos.0 as i64, pos.1 as i64);
+
+        let body = cells().map(|cell| snake.positions[1..].iter().any(|&pos| at(cell, pos)));
+        let head = cells().map(|cell| at(cell, snake.positions[0]));
+        let food = cells().map(|cell| universe.food.iter().any(|food| at(cell, (food.0, food.1))));
+        let walls = cells().map(wall);
+        input.extend(
+            body.chain(head)
+                .chain(food)
+                .chain(walls)
+                .map(|hit| if hit { 1. } else { 0. }),
+        );
+    }
+}
+
+impl fmt::Display for SensorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SensorKind::Rays => write!(f, "rays"),
+            SensorKind::HeadDirection => write!(f, "head-direction"),
+            SensorKind::TailDirection => write!(f, "tail-direction"),
+            SensorKind::Length => write!(f, "length"),
+            SensorKind::FoodVector => write!(f, "food-vector"),
+            SensorKind::WallDistance => write!(f, "wall-distance"),
+            SensorKind::Occupancy => write!(f, "occupancy"),
+            SensorKind::Planes => write!(f, "planes"),
+        }
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Frame::Absolute => write!(f, "absolute"),
+            Frame::Egocentric => write!(f, "egocentric"),
+        }
+    }
+}
+
+impl FromStr for Frame {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        Frame::ALL
+            .into_iter()
+            .find(|frame| frame.to_string() == s)
+            .ok_or(())
+    }
+}
+
+impl fmt::Display for PlaneView {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PlaneView::Board => write!(f, "board"),
+            PlaneView::Window => write!(f, "window"),
+        }
+    }
+}
+
+impl FromStr for PlaneView {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        PlaneView::ALL
+            .into_iter()
+            .find(|view| view.to_string() == s)
+            .ok_or(())
+    }
+}
+
+impl FromStr for SensorKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        SensorKind::ALL
+            .into_iter()
+            .find(|kind| kind.to_string() == s)
+            .ok_or(())
+    }
+}
+
+/// Written as `rays,head-direction,occupancy`
+impl fmt::Display for SensorConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kinds: Vec<String> = self.enabled.iter().map(|kind| kind.to_string()).collect();
+        write!(f, "{}", kinds.join(","))
+    }
+}
+
+/// Parses the displayed form, keeping the default window, frame and planes
+impl FromStr for SensorConfig {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let enabled = s
+            .split(',')
+            .filter(|kind| !kind.is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()?;
+        Ok(SensorConfig {
+            enabled,
+            ..SensorConfig::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Snake of 3 cells going up from (4, 3) to (4, 5), food at (6, 5)
+    fn universe() -> Universe {
+        let mut universe = Universe::new_empty(10, 10);
+        let mut snake = Snake::new(10, 10, 0);
+        snake.positions = vec![(4, 5), (4, 4), (4, 3)];
+        universe.add_snake(snake);
+        universe.food.push(Food(6, 5));
+        universe
+    }
+
+    fn observe(enabled: Vec<SensorKind>) -> Vec<f64> {
+        let config = SensorConfig {
+            enabled,
+            window: 3,
+            ..SensorConfig::default()
+        };
+        let sensors = config.build(10);
+        let input = sensors.observe(&universe()).unwrap();
+        assert_eq!(input.len(), config.input_size());
+        assert_eq!(sensors.labels().len(), input.len());
+        input
+    }
+
+    #[test]
+    fn default_is_the_rays() {
+        assert_eq!(SensorConfig::default().input_size(), 16);
+        let labels = SensorConfig::default().build(10).labels();
+        assert_eq!(labels[4], "up-left obstacle");
+        assert_eq!(labels[13], "right food");
+        // food 2 cells to the right
+        assert!((observe(vec![SensorKind::Rays])[13] - 0.8).abs() < 1e-12);
+    }
+
+    #[test]
+    fn directions_are_one_hot() {
+        let input = observe(vec![SensorKind::HeadDirection, SensorKind::TailDirection]);
+        assert_eq!(input, vec![1., 0., 0., 0., 1., 0., 0., 0.]);
+    }
+
+    #[test]
+    fn scalar_sensors() {
+        let input = observe(vec![
+            SensorKind::Length,
+            SensorKind::FoodVector,
+            SensorKind::WallDistance,
+        ]);
+        assert_eq!(input, vec![0.03, 0.2, 0., 0.4]);
+    }
+
+    #[test]
+    fn occupancy_window_rows_start_at_the_top() {
+        let input = observe(vec![SensorKind::Occupancy]);
+        assert_eq!(input, vec![0., 0., 0., 0., 1., 0., 0., 1., 0.]);
+    }
+
+    #[test]
+    fn egocentric_sensors_turn_with_the_head() {
+        let mut universe = universe();
+        universe.snakes[0].direction = Direction::Right;
+        let config = SensorConfig {
+            enabled: vec![
+                SensorKind::Rays,
+                SensorKind::HeadDirection,
+                SensorKind::TailDirection,
+                SensorKind::FoodVector,
+            ],
+            frame: Frame::Egocentric,
+            ..SensorConfig::default()
+        };
+        let sensors = config.build(10);
+        let labels = sensors.labels();
+        let input = sensors.observe(&universe).unwrap();
+        assert_eq!(labels[9], "forward food");
+        assert!((input[9] - 0.8).abs() < 1e-12);
+        // the head always faces forward, the tail moves up, to the left of the head
+        assert_eq!(input[16..24], [1., 0., 0., 0., 0., 0., 1., 0.]);
+        assert_eq!(labels[22], "tail left");
+        assert_eq!(input[24..], [0., 0.2]);
+    }
+
+    /// Indices of the cells set in each plane, seen by a snake heading towards `heading`
+    fn planes(view: PlaneView, frame: Frame, heading: Direction, size: usize) -> Vec<Vec<usize>> {
+        let mut universe = universe();
+        universe.snakes[0].direction = heading;
+        let config = SensorConfig {
+            enabled: vec![SensorKind::Planes],
+            frame,
+            planes: view,
+            plane_size: size,
+            ..SensorConfig::default()
+        };
+        let input = config.build(10).observe(&universe).unwrap();
+        assert_eq!(input.len(), config.input_shape().len());
+        input
+            .chunks(size * size)
+            .map(|plane| (0..plane.len()).filter(|&i| plane[i] == 1.).collect())
+            .collect()
+    }
+
+    #[test]
+    fn board_planes_start_at_the_top_left() {
+        let planes = planes(PlaneView::Board, Frame::Absolute, Direction::Up, 11);
+        // (x, y) is at row 10 - y and column x
+        assert_eq!(planes[0], vec![6 * 11 + 4, 7 * 11 + 4]);
+        assert_eq!(planes[1], vec![5 * 11 + 4]);
+        assert_eq!(planes[2], vec![5 * 11 + 6]);
+        // the first and last rows and columns
+        assert_eq!(planes[3].len(), 4 * 10);
+    }
+
+    #[test]
+    fn window_planes_are_centred_on_the_head() {
+        let planes = planes(PlaneView::Window, Frame::Absolute, Direction::Right, 5);
+        // the head is centred, the body below it and the food 2 cells to its right
+        assert_eq!(planes[0], vec![3 * 5 + 2, 4 * 5 + 2]);
+        assert_eq!(planes[1], vec![2 * 5 + 2]);
+        assert_eq!(planes[2], vec![2 * 5 + 4]);
+        assert!(planes[3].is_empty());
+    }
+
+    #[test]
+    fn egocentric_planes_turn_with_the_head() {
+        let window = planes(PlaneView::Window, Frame::Egocentric, Direction::Right, 5);
+        // facing right, the food is 2 cells ahead and the body, below, on the right
+        assert_eq!(window[0], vec![2 * 5 + 3, 2 * 5 + 4]);
+        assert_eq!(window[1], vec![2 * 5 + 2]);
+        assert_eq!(window[2], vec![2]);
+
+        let board = planes(PlaneView::Board, Frame::Egocentric, Direction::Down, 11);
+        // heading down, the board is upside down: (x, y) is at row y and column 10 - x
+        assert_eq!(board[1], vec![5 * 11 + 6]);
+        assert_eq!(board[2], vec![5 * 11 + 4]);
+        assert_eq!(board[3].len(), 4 * 10);
+    }
+
+    #[test]
+    fn wall_distance_does_not_depend_on_the_frame() {
+        let distances: Vec<Vec<f64>> = [Direction::Up, Direction::Right, Direction::Down]
+            .into_iter()
+            .flat_map(|heading| Frame::ALL.map(|frame| (heading.clone(), frame)))
+            .map(|(heading, frame)| {
+                let mut universe = universe();
+                universe.snakes[0].direction = heading;
+                let config = SensorConfig {
+                    enabled: vec![SensorKind::WallDistance],
+                    frame,
+                    ..SensorConfig::default()
+                };
+                config.build(10).observe(&universe).unwrap()
+            })
+            .collect();
+        assert!(distances.iter().all(|distance| *distance == distances[0]));
+    }
+
+    #[test]
+    fn only_planes_keep_their_shape() {
+        let mut config = SensorConfig::default();
+        config.set_enabled(SensorKind::Planes, true);
+        assert_eq!(config.input_shape(), Shape::from(16 + 4 * 81));
+        config.set_enabled(SensorKind::Rays, false);
+        assert_eq!(
+            config.input_shape(),
+            Shape {
+                channels: 4,
+                height: 9,
+                width: 9
+            }
+        );
+    }
+
+    #[test]
+    fn sizes_match_the_labels() {
+        let config = SensorConfig {
+            enabled: SensorKind::ALL.to_vec(),
+            ..SensorConfig::default()
+        };
+        for sensor in &config.build(10).0 {
+            assert_eq!(sensor.size(), sensor.labels().len());
+        }
+    }
+
+    #[test]
+    fn sensor_lists_round_trip() {
+        let config: SensorConfig = "rays,food-vector,occupancy".parse().unwrap();
+        assert_eq!(config.input_size(), 16 + 2 + 25);
+        assert_eq!(config.to_string(), "rays,food-vector,occupancy");
+        assert!("rays,smell".parse::<SensorConfig>().is_err());
+    }
+}
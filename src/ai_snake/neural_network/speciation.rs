@@ -0,0 +1,157 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use super::{brain::Brain, model::Model};
+
+/// Factor applied to the threshold of layered brains when the species count is off target
+const THRESHOLD_STEP: f64 = 1.1;
+const THRESHOLD_RANGE: (f64, f64) = (0.001, 100.);
+
+/// Group of similar brains, compared through a representative of the previous generation
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Species {
+    pub id: usize,
+    pub representative: Brain,
+    /// Indices in the population
+    pub members: Vec<usize>,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Speciation {
+    pub species: Vec<Species>,
+    next_id: usize,
+}
+
+impl Speciation {
+    /// Puts every model in the first species whose representative is closer than `threshold`,
+    /// founding new species as needed, then draws the representatives of the next generation
+    pub fn speciate(
+        &mut self,
+        population: &[Model],
+        threshold: f64,
+        distance: impl Fn(&Brain, &Brain) -> f64,
+        rng: &mut impl Rng,
+    ) {
+        for species in &mut self.species {
+            species.members.clear();
+        }
+        for (i, model) in population.iter().enumerate() {
+            match self
+                .species
+                .iter_mut()
+                .find(|species| distance(&species.representative, &model.brain) < threshold)
+            {
+                Some(species) => species.members.push(i),
+                None => {
+                    self.species.push(Species {
+                        id: self.next_id,
+                        representative: model.brain.clone(),
+                        members: vec![i],
+                    });
+                    self.next_id += 1;
+                }
+            }
+        }
+        self.species.retain(|species| !species.members.is_empty());
+        for species in &mut self.species {
+            let member = species.members[rng.gen_range(0..species.members.len())];
+            species.representative = population[member].brain.clone();
+        }
+    }
+
+    /// Offspring of each species with fitness sharing: a species earns the mean fitness of its
+    /// members, so a large species does not take over the population by its size alone.
+    /// Means are shifted so the least fit species earns nothing when some are negative
+    pub fn quotas(&self, fitness: &[f64], offspring: usize) -> Vec<usize> {
+        let mut shares: Vec<f64> = self
+            .species
+            .iter()
+            .map(|species| {
+                species.members.iter().map(|&i| fitness[i]).sum::<f64>()
+                    / species.members.len() as f64
+            })
+            .collect();
+        let lowest = shares.iter().copied().fold(0., f64::min);
+        shares.iter_mut().for_each(|share| *share -= lowest);
+        if shares.iter().all(|&share| share == 0.) {
+            shares = self
+                .species
+                .iter()
+                .map(|species| species.members.len() as f64)
+                .collect();
+        }
+        largest_remainder(&shares, offspring)
+    }
+}
+
+/// Moves the `threshold` of layered brains towards `target` species in the whole population,
+/// as weight distances shrink while the population converges
+pub fn next_threshold(threshold: f64, species: usize, target: usize) -> f64 {
+    let threshold = match species.cmp(&target) {
+        std::cmp::Ordering::Less => threshold / THRESHOLD_STEP,
+        std::cmp::Ordering::Greater => threshold * THRESHOLD_STEP,
+        std::cmp::Ordering::Equal => threshold,
+    };
+    threshold.clamp(THRESHOLD_RANGE.0, THRESHOLD_RANGE.1)
+}
+
+/// Splits `total` proportionally to `shares`, the rounding going to the largest remainders
+fn largest_remainder(shares: &[f64], total: usize) -> Vec<usize> {
+    let sum: f64 = shares.iter().sum();
+    if sum <= 0. {
+        return vec![0; shares.len()];
+    }
+    let exact: Vec<f64> = shares.iter().map(|s| s / sum * total as f64).collect();
+    let mut quotas: Vec<usize> = exact.iter().map(|x| x.floor() as usize).collect();
+    let mut by_remainder: Vec<usize> = (0..shares.len()).collect();
+    by_remainder
+        .sort_by(|&a, &b| (exact[b] - exact[b].floor()).total_cmp(&(exact[a] - exact[a].floor())));
+    let missing = total - quotas.iter().sum::<usize>();
+    for &i in by_remainder.iter().take(missing) {
+        quotas[i] += 1;
+    }
+    quotas
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotas_add_up() {
+        assert_eq!(largest_remainder(&[1., 1., 1.], 10), vec![4, 3, 3]);
+        assert_eq!(largest_remainder(&[3., 0., 1.], 8), vec![6, 0, 2]);
+        assert_eq!(largest_remainder(&[0., 0.], 5), vec![0, 0]);
+    }
+
+    #[test]
+    fn shared_fitness_ignores_species_size() {
+        let speciation = Speciation {
+            species: vec![
+                Species {
+                    id: 0,
+                    representative: Brain::Layered(Default::default()),
+                    members: vec![0, 1, 2, 3],
+                },
+                Species {
+                    id: 1,
+                    representative: Brain::Layered(Default::default()),
+                    members: vec![4],
+                },
+            ],
+            next_id: 2,
+        };
+        // both species score 2 on average
+        assert_eq!(speciation.quotas(&[2.; 5], 10), vec![5, 5]);
+        // nothing scored, quotas follow the sizes
+        assert_eq!(speciation.quotas(&[0.; 5], 10), vec![8, 2]);
+    }
+
+    #[test]
+    fn threshold_follows_the_target() {
+        assert!(next_threshold(1., 0, 4) < 1.);
+        assert!(next_threshold(1., 9, 4) > 1.);
+        assert_eq!(next_threshold(1., 8, 8), 1.);
+        assert_eq!(next_threshold(100., 9, 4), 100.);
+    }
+}
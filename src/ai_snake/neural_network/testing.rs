@@ -0,0 +1,30 @@
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+use super::{
+    architecture::Architecture,
+    model::{ActionSpace, Model},
+    sensor::SensorConfig,
+};
+
+/// Models on 8x8 grids with brains of the default architecture and sensors,
+/// each scored `scores[i]` with the same fitness
+pub fn population(scores: &[u32]) -> Vec<Model> {
+    let mut rng = ChaCha8Rng::seed_from_u64(0);
+    scores
+        .iter()
+        .enumerate()
+        .map(|(i, &score)| {
+            let brain = Architecture::default()
+                .build(
+                    SensorConfig::default().input_size(),
+                    ActionSpace::default().size(),
+                    &mut rng,
+                )
+                .into();
+            let mut model = Model::new(8, 8, 10, i, brain, Model::rng(0, i));
+            (model.score, model.fitness) = (score, score as f64);
+            model
+        })
+        .collect()
+}
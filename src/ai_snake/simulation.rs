@@ -1,21 +1,57 @@
-use bevy::prelude::*;
-
-use crate::ai_snake::neural_network::{ActivationFunction, Layer, NeuralNetwork};
-
-use super::neural_network::genetic::GeneticModel;
+use std::{path::PathBuf, time::Duration};
+
+use bevy::{
+    prelude::*,
+    tasks::{block_on, poll_once, AsyncComputeTaskPool, Task},
+    utils::Instant,
+};
+use rand_chacha::ChaCha8Rng;
+use rayon::{prelude::*, ThreadPool, ThreadPoolBuilder};
+
+use super::checkpoint::save_checkpoint;
+use super::neural_network::{
+    brain::BrainKind, fitness::FitnessFunction, genetic::GeneticModel, hall_of_fame::HallOfFame,
+    model::Model, sensor::Sensors, speciation::next_threshold,
+};
 use super::ui::{AppConfig, SimulationState};
 
 #[derive(Resource)]
 pub struct Configuration {
     pub simulation: GeneticModel,
     pub grid_config: GridConfiguration,
+    /// Built once per run, along with the vision range it was built for, which can change
+    /// while the run goes on
+    sensors: (i64, Sensors),
+    /// Built on first use, along with the thread count it was built for
+    pool: Option<(usize, Option<ThreadPool>)>,
 }
+
+/// Run loaded from a checkpoint, installed by `start_set_up` instead of a new one
+#[derive(Resource)]
+pub struct PendingCheckpoint(pub AppConfig, pub Configuration);
 pub struct GridConfiguration {
     pub width: u64,
     pub height: u64,
     pub cell_size: f32,
 }
 
+/// How long a worker simulates in turbo mode before handing the run back to be drawn
+const TURBO_BATCH: Duration = Duration::from_millis(50);
+
+/// Batch of a run simulated on a worker in turbo mode, the run is out of the world meanwhile
+#[derive(Resource)]
+struct TurboBatch(Task<(Configuration, AppConfig)>);
+
+/// What the UI shows of a run while a worker simulates it in turbo mode
+#[derive(Resource)]
+pub struct TurboSnapshot {
+    pub hall_of_fame: HallOfFame,
+}
+
+/// Checkpoint asked for while the run is on a worker, saved before its next batch starts
+#[derive(Resource)]
+pub struct PendingCheckpointSave(pub PathBuf);
+
 pub struct SimulationPlugin;
 
 impl Plugin for SimulationPlugin {
@@ -24,116 +60,304 @@ impl Plugin for SimulationPlugin {
             FixedUpdate,
             (
                 start_set_up.run_if(in_state(SimulationState::StartUp)),
-                one_step_simulation.run_if(in_state(SimulationState::Running)),
-                evolve.run_if(in_state(SimulationState::Evolving)),
+                one_step_simulation.run_if(
+                    in_state(SimulationState::Running).and_then(resource_exists::<Configuration>),
+                ),
+                evolve.run_if(
+                    in_state(SimulationState::Evolving).and_then(resource_exists::<Configuration>),
+                ),
             ),
+        )
+        // the run is back in the world from the start of a frame until it is drawn
+        .add_systems(PreUpdate, finish_turbo_batch)
+        .add_systems(
+            Last,
+            start_turbo_batch.run_if(in_state(SimulationState::Running)),
         );
     }
 }
 
-fn one_step_simulation(
+fn one_step_simulation(mut sim_config: ResMut<Configuration>, mut app_config: ResMut<AppConfig>) {
+    if app_config.turbo {
+        return;
+    }
+    for _ in 0..app_config.steps_per_frame {
+        if sim_config.step(&mut app_config) {
+            sim_config.next_generation(&mut app_config);
+        }
+    }
+}
+
+/// Hands the run to a worker in turbo mode, so the frames keep their pace
+fn start_turbo_batch(world: &mut World) {
+    if !world.resource::<AppConfig>().turbo || world.contains_resource::<TurboBatch>() {
+        return;
+    }
+    let Some(mut sim_config) = world.remove_resource::<Configuration>() else {
+        return;
+    };
+    let mut app_config = world.resource::<AppConfig>().clone();
+    world.insert_resource(TurboSnapshot {
+        hall_of_fame: sim_config.simulation.hall_of_fame.clone(),
+    });
+    let task = AsyncComputeTaskPool::get().spawn(async move {
+        let start = Instant::now();
+        while start.elapsed() < TURBO_BATCH {
+            if sim_config.step(&mut app_config) {
+                sim_config.next_generation(&mut app_config);
+            }
+        }
+        (sim_config, app_config)
+    });
+    world.insert_resource(TurboBatch(task));
+}
+
+/// Puts the run back once its worker is done, along with the progress it made
+fn finish_turbo_batch(world: &mut World) {
+    let Some(mut batch) = world.get_resource_mut::<TurboBatch>() else {
+        return;
+    };
+    let Some((sim_config, progress)) = block_on(poll_once(&mut batch.0)) else {
+        return;
+    };
+    world.remove_resource::<TurboBatch>();
+    world.remove_resource::<TurboSnapshot>();
+    world.resource_mut::<AppConfig>().take_progress(progress);
+    if let Some(PendingCheckpointSave(path)) = world.remove_resource::<PendingCheckpointSave>() {
+        let mut app_config = world.resource_mut::<AppConfig>();
+        app_config.file_status = match save_checkpoint(&path, &app_config, &sim_config) {
+            Ok(()) => format!("Saved to {}", path.display()),
+            Err(e) => e.to_string(),
+        };
+    }
+    world.insert_resource(sim_config);
+}
+
+fn evolve(
     mut sim_config: ResMut<Configuration>,
-    next_state: ResMut<NextState<SimulationState>>,
+    mut next_state: ResMut<NextState<SimulationState>>,
     mut app_config: ResMut<AppConfig>,
 ) {
-    let width = sim_config.grid_config.width;
-    let height = sim_config.grid_config.height;
+    sim_config.next_generation(&mut app_config);
+    next_state.set(SimulationState::Running);
+}
 
-    let sim = &mut sim_config.simulation;
+fn start_set_up(world: &mut World) {
+    // a batch still simulating belongs to the run being replaced
+    world.remove_resource::<TurboBatch>();
+    world.remove_resource::<TurboSnapshot>();
+    world.remove_resource::<PendingCheckpointSave>();
+    if let Some(PendingCheckpoint(loaded, sim_config)) =
+        world.remove_resource::<PendingCheckpoint>()
+    {
+        world.resource_mut::<AppConfig>().resume(loaded);
+
+        world.insert_resource(sim_config);
+        world
+            .resource_mut::<NextState<SimulationState>>()
+            .set(SimulationState::Paused);
+    } else {
+        let sim_config = Configuration::new(world.resource::<AppConfig>());
+        world.insert_resource(sim_config);
+        world
+            .resource_mut::<NextState<SimulationState>>()
+            .set(SimulationState::Running);
+    }
+}
 
-    let mut finished = true;
-    for i in 0..sim.population.len() {
-        if sim.population[i].moves_left > 0 {
-            finished = false;
-        }
+impl Configuration {
+    pub fn new(app_config: &AppConfig) -> Self {
+        setup_simulation(
+            app_config.grid_size,
+            app_config.grid_size,
+            app_config.allowed_moves,
+            app_config.population_size,
+            app_config.food_amount,
+            app_config.seed,
+            app_config,
+        )
+    }
 
-        // get input for each snake
-        if let Some(input) = sim.population[i].compute_input(width, height, app_config.vision_range)
-        {
-            // compute output for each snake
-            if (app_config.print_input) && i == 0 {
-                println!("Input For #{}, Score={}", i, sim.population[i].score);
-                println!("Bodies:");
+    /// Rebuilds the configuration of a run loaded from a checkpoint
+    pub fn resume(simulation: GeneticModel, app_config: &AppConfig) -> Self {
+        Configuration {
+            simulation,
+            grid_config: GridConfiguration {
+                width: app_config.grid_size,
+                height: app_config.grid_size,
+                cell_size: 1.0,
+            },
+            sensors: build_sensors(app_config),
+            pool: None,
+        }
+    }
 
-                println!("{:.2} {:.2} {:.2}", input[4], input[8], input[14]);
-                println!("{:.2} X {:.2}", input[2], input[12]);
-                println!("{:.2} {:.2} {:.2}", input[0], input[6], input[10]);
+    /// Advances every snake by one move, starting the next episode of the snakes whose game ended,
+    /// returns true once no snake had moves left
+    pub fn step(&mut self, app_config: &mut AppConfig) -> bool {
+        if self.sensors.0 != app_config.vision_range {
+            self.sensors = build_sensors(app_config);
+        }
+        let sensors = &self.sensors.1;
 
-                println!("\nFood:");
+        let population = &mut self.simulation.population;
+        let finished = population.iter().all(|model| model.moves_left == 0);
 
-                println!("{:.2} {:.2} {:.2}", input[5], input[9], input[15]);
-                println!("{:.2} X {:.2}", input[3], input[13]);
-                println!("{:.2} {:.2} {:.2}", input[1], input[7], input[11]);
+        if let Some((first, others)) = population.split_first_mut() {
+            // model #0 is stepped on its own so its I/O is printed in order
+            if let Some(input) = first.compute_input(sensors) {
+                if app_config.print_input {
+                    print_input(first.score, &sensors.labels(), &input);
+                }
+                let mut output = first.compute_output(input);
+                if app_config.print_input {
+                    print_output(&output);
+                }
+                first.update_position(&mut output);
             }
 
-            let output = sim.population[i].compute_output(input);
-
-            if (app_config.print_input) && i == 0 {
-                println!();
-                if i == 0 {
-                    println!("Output:");
-                    println!(
-                        "{:.2} {:.2} {:.2} {:.2}",
-                        output[0], output[1], output[2], output[3]
-                    );
-                    println!();
+            // models only share read-only state so the order they are stepped in does not matter
+            match thread_pool(&mut self.pool, app_config.threads) {
+                Some(pool) => {
+                    pool.install(|| others.par_iter_mut().for_each(|model| model.step(sensors)))
                 }
+                None => others.iter_mut().for_each(|model| model.step(sensors)),
             }
+        }
+        if app_config.episodes > 1 {
+            for model in population.iter_mut() {
+                let episode = model.past_episodes.len() + 1;
+                if model.moves_left == 0 && episode < app_config.episodes {
+                    model.food_rng =
+                        episode_food(app_config, app_config.generation_number, episode);
+                    model.next_episode(app_config.allowed_moves, app_config.food_amount);
+                }
+            }
+        }
+        app_config.current_moves += 1;
+        finished
+    }
 
-            sim.population[i].update_position(output);
+    /// Evolves the population, resets every model and records the generation's scores
+    pub fn next_generation(&mut self, app_config: &mut AppConfig) -> (u32, u32, u32) {
+        let sim = &mut self.simulation;
+
+        let (best_score, average_score, models_merged) = sim.evolve(&app_config.evolution_config());
+        let method = match &sim.strategy {
+            Some(strategy) => format!("Optimiser: {}", strategy.describe()),
+            None => format!("Selection: {}", app_config.selection_config().describe()),
         };
-    }
-    app_config.current_moves += 1;
+        println!(
+            "[{}] Best: {}, Average: {}, Merged: {}, {}, Species: {}, Distance: {:.3}, Behaviours: {}",
+            app_config.generation_number,
+            best_score,
+            average_score,
+            models_merged,
+            method,
+            sim.diversity.species,
+            sim.diversity.mean_distance,
+            sim.diversity.unique_behaviours
+        );
+        if app_config.fitness != FitnessFunction::default() {
+            println!(
+                "    Fitness {}: best {:.2}, average {:.2}",
+                app_config.fitness, sim.fitness.0, sim.fitness.1
+            );
+        }
+        if app_config.episodes > 1 {
+            println!(
+                "    Episodes: {}, {} fitness, variance {:.2}",
+                app_config.episodes, app_config.aggregation, sim.fitness_variance
+            );
+        }
+        if sim.island_scores.len() > 1 {
+            let islands: Vec<String> = sim
+                .island_scores
+                .iter()
+                .map(|(best, average)| format!("{best}/{average}"))
+                .collect();
+            println!("    Islands (best/average): {}", islands.join(", "));
+        }
 
-    if finished {
-        evolve(sim_config, next_state, app_config)
+        let food = episode_food(app_config, app_config.generation_number + 1, 0);
+        for i in 0..sim.population.len() {
+            sim.population[i].food_rng = food.clone();
+            sim.population[i].reset(app_config.allowed_moves, app_config.food_amount);
+        }
+
+        app_config.generation_number += 1;
+        app_config.current_moves = 0;
+        app_config.best_score = best_score as u64;
+        app_config.average_score = average_score as u64;
+        (app_config.best_fitness, app_config.average_fitness) = sim.fitness;
+        app_config.fitness_variance = sim.fitness_variance;
+        app_config.last_merged = models_merged as u64;
+        app_config.diversity = sim.diversity;
+        app_config.island_scores = sim
+            .island_scores
+            .iter()
+            .map(|&(best, average)| (best as u64, average as u64))
+            .collect();
+        app_config
+            .score_history
+            .push((best_score as u64, average_score as u64));
+        app_config.mutation_scale = app_config
+            .mutation_config()
+            .next_scale(&app_config.score_history);
+        if app_config.speciation && app_config.architecture.kind == BrainKind::Layered {
+            // the target is per island
+            let target = app_config.species_target * app_config.islands.max(1);
+            app_config.species_threshold =
+                next_threshold(app_config.species_threshold, sim.diversity.species, target);
+        }
+        (best_score, average_score, models_merged)
     }
 }
 
-fn evolve(
-    mut sim_config: ResMut<Configuration>,
-    mut next_state: ResMut<NextState<SimulationState>>,
-    mut app_config: ResMut<AppConfig>,
-) {
-    let pop_size = sim_config.simulation.population.len() as f64;
-    let sim = &mut sim_config.simulation;
-
-    let (best_score, average_score, models_merged) = sim.evolve(
-        app_config.keep_x_best,
-        app_config.mutation_factor / pop_size,
-    );
-    println!(
-        "[{}] Best: {}, Average: {}, Merged: {}",
-        app_config.generation_number, best_score, average_score, models_merged
-    );
-
-    for i in 0..sim.population.len() {
-        sim.population[i].reset(app_config.allowed_moves, app_config.food_amount);
-    }
-
-    app_config.generation_number += 1;
-    app_config.current_moves = 0;
-    app_config.best_score = best_score as u64;
-    app_config.average_score = average_score as u64;
-    app_config.last_merged = models_merged as u64;
-    next_state.set(SimulationState::Running);
+/// Food shared by the population in `episode` of `generation`, `None` when every model
+/// draws its own
+fn episode_food(app_config: &AppConfig, generation: u64, episode: usize) -> Option<ChaCha8Rng> {
+    app_config
+        .common_seeds
+        .then(|| Model::episode_rng(app_config.seed, generation, episode))
 }
 
-fn start_set_up(
-    mut commands: Commands,
-    app_config: ResMut<AppConfig>,
-    mut next_state: ResMut<NextState<SimulationState>>,
-) {
-    let config = setup_simulation(
-        app_config.grid_size,
-        app_config.grid_size,
-        app_config.allowed_moves,
-        app_config.population_size,
-        app_config.food_amount,
-    );
-    commands.insert_resource(config);
+fn build_sensors(app_config: &AppConfig) -> (i64, Sensors) {
+    (
+        app_config.vision_range,
+        app_config.sensors.build(app_config.vision_range),
+    )
+}
 
-    next_state.set(SimulationState::Running);
+/// `None` runs the models serially: when asked for one thread or when threads are unavailable
+fn thread_pool(
+    pool: &mut Option<(usize, Option<ThreadPool>)>,
+    threads: usize,
+) -> Option<&ThreadPool> {
+    if threads == 1 {
+        return None;
+    }
+    if pool.as_ref().map(|(t, _)| *t) != Some(threads) {
+        // 0 threads lets rayon use every core
+        let built = ThreadPoolBuilder::new().num_threads(threads).build().ok();
+        *pool = Some((threads, built));
+    }
+    pool.as_ref().and_then(|(_, pool)| pool.as_ref())
+}
+
+fn print_input(score: u32, labels: &[String], input: &[f64]) {
+    println!("Input For #0, Score={}", score);
+    for (label, value) in labels.iter().zip(input) {
+        println!("{label}: {value:.2}");
+    }
+}
+
+fn print_output(output: &[f64]) {
+    println!();
+    println!("Output:");
+    let values: Vec<String> = output.iter().map(|value| format!("{value:.2}")).collect();
+    println!("{}", values.join(" "));
+    println!();
 }
 
 fn setup_simulation(
@@ -142,6 +366,8 @@ fn setup_simulation(
     allowed_moves: u64,
     population_count: u64,
     food_ammount: u64,
+    seed: u64,
+    app_config: &AppConfig,
 ) -> Configuration {
     let grid_config = GridConfiguration {
         width,
@@ -149,89 +375,78 @@ fn setup_simulation(
         cell_size: 1.0,
     };
 
-    let mut brains: Vec<NeuralNetwork> = vec![];
-    for _ in 0..population_count {
-        let inner_input_size = 16;
-        let inner_output_size = 16;
-        let mut weights1 = vec![vec![0.0; inner_input_size]; inner_output_size];
-
-        (0..weights1.len()).for_each(|i| {
-            for j in 0..weights1[i].len() {
-                weights1[i][j] = rand::random::<f64>() * 2. - 1.;
-            }
-        });
-
-        let mut biases1 = vec![0.; inner_output_size];
-        for b in biases1.iter_mut() {
-            *b = rand::random::<f64>() * 2. - 1.;
-        }
-
-        let inner_input_size = 16;
-        let inner_output_size = 16;
-        let mut weights2 = vec![vec![0.0; inner_input_size]; inner_output_size];
-
-        (0..weights2.len()).for_each(|i| {
-            for j in 0..weights2[i].len() {
-                weights2[i][j] = rand::random::<f64>() * 2. - 1.;
-            }
-        });
-
-        let mut biases2 = vec![0.; inner_output_size];
-        for b in biases2.iter_mut() {
-            *b = rand::random::<f64>() * 2. - 1.;
-        }
-
-        let final_size = 4;
-        let mut weights3 = vec![vec![0.0; final_size]; inner_output_size];
-
-        (0..weights3.len()).for_each(|i| {
-            for j in 0..weights3[i].len() {
-                weights3[i][j] = rand::random::<f64>() * 2. - 1.;
-            }
-        });
-
-        let mut biases3 = vec![0.; final_size];
-        for b in biases3.iter_mut() {
-            *b = rand::random::<f64>() * 2. - 1.;
-        }
-
-        let mut brain = NeuralNetwork::new();
-        brain
-            .add_layer(Layer::new(
-                inner_input_size,
-                inner_output_size,
-                weights1,
-                biases1,
-                ActivationFunction::Identity,
-            ))
-            .add_layer(Layer::new(
-                inner_input_size,
-                inner_output_size,
-                weights2,
-                biases2,
-                ActivationFunction::Identity,
-            ))
-            .add_layer(Layer::new(
-                inner_output_size,
-                final_size,
-                weights3,
-                biases3,
-                ActivationFunction::Softmax,
-            ));
-        brains.push(brain);
-    }
     let mut genetic_model =
-        GeneticModel::new(&grid_config, allowed_moves, population_count, brains);
+        GeneticModel::new(&grid_config, allowed_moves, population_count, seed, |rng| {
+            app_config.architecture.new_brain(
+                app_config.sensors.input_shape(),
+                app_config.actions.size(),
+                &app_config.neat,
+                rng,
+            )
+        });
 
     // spawn first snakes
+    let food = episode_food(app_config, app_config.generation_number, 0);
     for i in 0..population_count as usize {
+        genetic_model.population[i].actions = app_config.actions;
+        genetic_model.population[i].food_rng = food.clone();
         genetic_model.population[i].reset(allowed_moves, food_ammount);
     }
 
     println!("{genetic_model}");
+    println!("Seed: {seed}");
 
     Configuration {
         simulation: genetic_model,
         grid_config,
+        sensors: build_sensors(app_config),
+        pool: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Outcome {
+        scores: Vec<(u64, u64)>,
+        fitness: (f64, f64),
+        brains: Vec<String>,
+    }
+
+    /// Outcome of a seeded run after 3 generations stepped on `threads`
+    fn run(threads: usize) -> Outcome {
+        let mut app_config = AppConfig {
+            grid_size: 10,
+            vision_range: 10,
+            population_size: 30,
+            allowed_moves: 40,
+            seed: 7,
+            threads,
+            ..AppConfig::default()
+        };
+        let mut sim_config = Configuration::new(&app_config);
+        while app_config.generation_number < 3 {
+            if sim_config.step(&mut app_config) {
+                sim_config.next_generation(&mut app_config);
+            }
+        }
+        let brains = sim_config
+            .simulation
+            .population
+            .iter()
+            .map(|model| serde_json::to_string(&model.brain).unwrap())
+            .collect();
+        Outcome {
+            scores: app_config.score_history,
+            fitness: (app_config.best_fitness, app_config.average_fitness),
+            brains,
+        }
+    }
+
+    #[test]
+    fn parallel_runs_match_serial_runs() {
+        assert_eq!(run(1), run(4));
     }
 }
@@ -1,4 +1,4 @@
 pub mod camera;
 mod grids;
 pub mod render_sim_plugin;
-mod sprites;
+pub mod sprites;
@@ -1,6 +1,6 @@
 use bevy::prelude::*;
 
-use crate::ai_snake::ui::{RenderingState, SimulationState};
+use crate::ai_snake::ui::{AppConfig, RenderingState, SimulationState};
 
 use super::{
     camera::{camera_controls, camera_update, spawn_camera},
@@ -17,8 +17,24 @@ impl Plugin for RenderSimulationPlugin {
             .add_systems(Update, camera_controls)
             //.add_systems(Update, display_grid)
             .add_systems(
-                FixedPostUpdate,
-                update_sprites.run_if(in_state(RenderingState::Enabled)),
+                PostUpdate,
+                update_sprites
+                    .run_if(in_state(RenderingState::Enabled).and_then(sprite_refresh_due)),
             );
     }
 }
+
+/// Limits sprite updates to `AppConfig::sprite_refresh_rate`, rebuilding the image is costly
+fn sprite_refresh_due(
+    time: Res<Time<Real>>,
+    app_config: Res<AppConfig>,
+    mut last_refresh: Local<f32>,
+) -> bool {
+    let now = time.elapsed_seconds();
+    if now - *last_refresh >= 1.0 / app_config.sprite_refresh_rate {
+        *last_refresh = now;
+        true
+    } else {
+        false
+    }
+}
@@ -1,12 +1,27 @@
-use crate::ai_snake::simulation::Configuration;
+use crate::ai_snake::{neural_network::island, simulation::Configuration, ui::AppConfig};
 use bevy::{
     prelude::*,
     render::{render_asset::RenderAssetUsages, render_resource::Extent3d},
 };
 
+/// Snake colors of the islands, food is drawn green
+pub const ISLAND_COLORS: [[u8; 3]; 8] = [
+    [255, 0, 0],
+    [64, 128, 255],
+    [255, 220, 0],
+    [255, 0, 255],
+    [255, 128, 0],
+    [255, 255, 255],
+    [0, 200, 200],
+    [150, 80, 255],
+];
+
 #[derive(Resource)]
 pub struct MainSpriteId(AssetId<Image>);
 
+#[derive(Component)]
+pub struct MainSprite;
+
 fn get_image_dimensions(config: &Res<Configuration>) -> (u32, u32) {
     let row_length = (1.0 + config.simulation.population.len() as f64).sqrt() as u32;
     let column_length = row_length
@@ -22,7 +37,13 @@ pub fn setup_sprites(
     mut commands: Commands,
     config: Option<Res<Configuration>>,
     mut images: ResMut<Assets<Image>>,
+    previous_sprites: Query<Entity, With<MainSprite>>,
 ) {
+    // a checkpoint can be loaded over a running simulation
+    for entity in previous_sprites.iter() {
+        commands.entity(entity).despawn();
+    }
+
     if let Some(config) = config {
         let (width, height) = get_image_dimensions(&config);
 
@@ -43,20 +64,24 @@ pub fn setup_sprites(
         let image_handle = images.add(img);
         commands.insert_resource(MainSpriteId(image_handle.id()));
 
-        commands.spawn((SpriteBundle {
-            texture: image_handle,
-            sprite: Sprite {
-                anchor: bevy::sprite::Anchor::Center,
+        commands.spawn((
+            SpriteBundle {
+                texture: image_handle,
+                sprite: Sprite {
+                    anchor: bevy::sprite::Anchor::Center,
+                    ..Default::default()
+                },
+                transform: Transform::from_xyz(0., 0., 0.),
                 ..Default::default()
             },
-            transform: Transform::from_xyz(0., 0., 0.),
-            ..Default::default()
-        },));
+            MainSprite,
+        ));
     }
 }
 
 pub fn update_sprites(
     config: Option<Res<Configuration>>,
+    app_config: Res<AppConfig>,
 
     sprite_id: Option<Res<MainSpriteId>>,
     mut images: ResMut<Assets<Image>>,
@@ -69,8 +94,11 @@ pub fn update_sprites(
             let line_length = (1.0 + population.len() as f64).sqrt() as usize;
             let cell_size = config.grid_config.cell_size as usize;
 
+            let islands = island::ranges(population.len(), app_config.islands);
             img.data = vec![0; (width * height * 4) as usize];
             (0..population.len()).for_each(|index| {
+                let island = islands.iter().position(|island| island.contains(&index));
+                let [r, g, b] = ISLAND_COLORS[island.unwrap_or(0) % ISLAND_COLORS.len()];
                 let x_offset =
                     ((index % line_length) * config.grid_config.width as usize) * cell_size;
                 let y_offset =
@@ -86,7 +114,9 @@ pub fn update_sprites(
                                     + l;
 
                                 let pixel_index = 4 * (y as u32 * width + x as u32) as usize;
-                                img.data[pixel_index] = 255;
+                                img.data[pixel_index] = r;
+                                img.data[pixel_index + 1] = g;
+                                img.data[pixel_index + 2] = b;
                                 img.data[pixel_index + 3] = 255;
                             }
                         }
@@ -1,8 +1,35 @@
+use std::{fs, io, path::Path};
+
 use bevy::prelude::*;
 use bevy_egui::{
     egui::{self, Ui},
     EguiContexts, EguiPlugin,
 };
+use serde::{Deserialize, Serialize};
+
+use super::{
+    checkpoint::{load_checkpoint, save_checkpoint},
+    neural_network::{
+        architecture::{Architecture, InitScheme, LayerSpec, LayerType},
+        brain::BrainKind,
+        crossover::Crossover,
+        diversity::Diversity,
+        evolution_strategy::{Optimiser, StrategyConfig, FULL_COVARIANCE_LIMIT},
+        fitness::{Aggregation, FitnessFunction, FitnessTerm},
+        genetic::EvolutionConfig,
+        hall_of_fame::HallOfFame,
+        island::{IslandSettings, MigrationTopology},
+        model::ActionSpace,
+        mutation::{Mutation, MutationConfig, MutationOperator, MutationSchedule},
+        neat::NeatConfig,
+        persistence::BrainFormat,
+        selection::{SelectionConfig, SelectionStrategy},
+        sensor::{Frame, PlaneView, SensorConfig, SensorKind},
+        ActivationFunction, Shape,
+    },
+    simulation::{Configuration, PendingCheckpoint, PendingCheckpointSave, TurboSnapshot},
+    simulation_rendering::sprites::ISLAND_COLORS,
+};
 
 #[derive(Default, States, Debug, Hash, Eq, Clone, Copy, PartialEq)]
 
@@ -22,22 +49,210 @@ pub enum RenderingState {
     Enabled,
     Disabled,
 }
-#[derive(Default, Resource)]
+/// Missing fields of a config file take their default value
+#[derive(Resource, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct AppConfig {
     pub generation_number: u64,
     pub best_score: u64,
     pub average_score: u64,
+    /// Best and average fitness of the last generation, the score is the food eaten
+    pub best_fitness: f64,
+    pub average_fitness: f64,
+    /// Mean over the models of the variance of the fitness of their episodes
+    pub fitness_variance: f64,
     pub grid_size: u64,
     pub population_size: u64,
     pub current_moves: u64,
     pub allowed_moves: u64,
     pub last_merged: u64,
+    /// Measured on the last generation before it bred
+    pub diversity: Diversity,
+    /// Best and average score of each island in the last generation
+    pub island_scores: Vec<(u64, u64)>,
+    /// Divided by the size of an island to get the per-weight mutation rate
     pub mutation_factor: f64,
+    pub mutation_operator: MutationOperator,
+    /// Standard deviation of the gaussian and scaled operators
+    pub mutation_sigma: f64,
+    pub mutation_schedule: MutationSchedule,
+    /// Applied every generation by the decay schedule
+    pub mutation_decay: f64,
+    /// Current scale of the rate and sigma, driven by the schedule
+    pub mutation_scale: f64,
+    /// Used by the threshold and truncation selections
     pub keep_x_best: f64,
+    pub selection: SelectionStrategy,
+    /// Models drawn per tournament
+    pub tournament_size: usize,
+    pub crossover: Crossover,
+    /// Best models copied unchanged into the next generation
+    pub elitism: usize,
+    /// Champions kept in the hall of fame
+    pub hall_of_fame_size: usize,
+    /// Split layered networks into species breeding among themselves, NEAT genomes always are
+    pub speciation: bool,
+    /// Species count the threshold of layered networks is adjusted towards
+    pub species_target: usize,
+    /// Weight-space distance under which layered networks share a species
+    pub species_threshold: f64,
+    /// What the models are selected on
+    pub fitness: FitnessFunction,
+    /// Games played by every model each generation
+    pub episodes: usize,
+    /// Give the whole population the same food in each episode
+    pub common_seeds: bool,
+    /// How the fitness of the episodes of a model are combined
+    pub aggregation: Aggregation,
+    pub optimiser: Optimiser,
+    /// Standard deviation of the perturbations of the evolution strategies
+    pub es_sigma: f64,
+    /// Step of the OpenAI-ES centre along its estimated gradient
+    pub es_learning_rate: f64,
+    /// Sub-populations evolving on their own, 1 evolves the population as a whole
+    pub islands: usize,
+    /// Generations between two migrations, 0 never migrates
+    pub migration_interval: u64,
+    /// Best models each island sends to each of its destinations
+    pub migration_size: usize,
+    pub migration_topology: MigrationTopology,
+    /// Overrides of each island, missing islands use the settings above
+    pub island_settings: Vec<IslandSettings>,
     pub vision_range: i64,
+    /// What the snakes perceive, the brains take one input per value
+    pub sensors: SensorConfig,
+    /// How the snakes read the output of their brain, one output per action
+    pub actions: ActionSpace,
     pub food_amount: u64,
+    pub architecture: Architecture,
+    pub neat: NeatConfig,
+    /// Threads stepping the population, 0 uses every core and 1 runs serially
+    #[serde(skip)]
+    pub threads: usize,
+    /// Simulation steps run each tick of the fixed clock, ignored in turbo mode
+    #[serde(skip)]
+    pub steps_per_frame: u32,
+    /// Simulate on a worker as fast as it goes, handing the run back to be drawn now and then
+    #[serde(skip)]
+    pub turbo: bool,
+    /// Cap on how often the sprite image is refreshed
+    #[serde(skip)]
+    pub sprite_refresh_rate: f32,
+    /// Drives every random choice of a run, see `GeneticModel::new`
+    pub seed: u64,
+    /// (best, average) score of every past generation
+    pub score_history: Vec<(u64, u64)>,
 
     pub print_input: bool,
+    #[serde(skip)]
+    pub checkpoint_path: String,
+    #[serde(skip)]
+    pub config_path: String,
+    /// Directory the hall of fame is exported to
+    #[serde(skip)]
+    pub hall_of_fame_path: String,
+    /// Champion whose brain is shown in the hall of fame
+    #[serde(skip)]
+    pub selected_champion: Option<usize>,
+    /// Outcome of the last file operation, shown in the UI
+    #[serde(skip)]
+    pub file_status: String,
+}
+
+impl AppConfig {
+    /// Takes over a run loaded from a checkpoint, keeping the settings local to this machine
+    pub fn resume(&mut self, loaded: AppConfig) {
+        self.replace(loaded);
+        self.file_status = format!("Resumed at generation {}", self.generation_number);
+    }
+
+    /// Takes the progress of a run simulated on a worker in turbo mode, keeping the settings
+    /// changed meanwhile
+    pub fn take_progress(&mut self, worker: AppConfig) {
+        self.generation_number = worker.generation_number;
+        self.best_score = worker.best_score;
+        self.average_score = worker.average_score;
+        self.best_fitness = worker.best_fitness;
+        self.average_fitness = worker.average_fitness;
+        self.fitness_variance = worker.fitness_variance;
+        self.current_moves = worker.current_moves;
+        self.last_merged = worker.last_merged;
+        self.diversity = worker.diversity;
+        self.island_scores = worker.island_scores;
+        self.mutation_scale = worker.mutation_scale;
+        self.species_threshold = worker.species_threshold;
+        self.score_history = worker.score_history;
+    }
+
+    fn replace(&mut self, mut loaded: AppConfig) {
+        loaded.threads = self.threads;
+        loaded.steps_per_frame = self.steps_per_frame;
+        loaded.turbo = self.turbo;
+        loaded.sprite_refresh_rate = self.sprite_refresh_rate;
+        loaded.checkpoint_path = std::mem::take(&mut self.checkpoint_path);
+        loaded.config_path = std::mem::take(&mut self.config_path);
+        loaded.hall_of_fame_path = std::mem::take(&mut self.hall_of_fame_path);
+        *self = loaded;
+    }
+
+    /// Reads a JSON config file, e.g. one written by [`AppConfig::save`]
+    pub fn load(path: &Path) -> io::Result<AppConfig> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+
+    /// What the population is bred with at the end of the current generation
+    pub fn evolution_config(&self) -> EvolutionConfig {
+        EvolutionConfig {
+            generation: self.generation_number,
+            fitness: self.fitness.clone(),
+            aggregation: self.aggregation,
+            brain: self.architecture.kind,
+            neat: self.neat.clone(),
+            hall_of_fame_size: self.hall_of_fame_size,
+            elitism: self.elitism,
+            species_threshold: self.speciation.then_some(self.species_threshold),
+            selection: self.selection_config(),
+            crossover: self.crossover,
+            mutation: self.mutation_config(),
+            optimiser: self.optimiser,
+            strategy: self.strategy_config(),
+            islands: self.islands,
+            migration_interval: self.migration_interval,
+            migration_size: self.migration_size,
+            migration_topology: self.migration_topology,
+            island_settings: self.island_settings.clone(),
+        }
+    }
+
+    pub fn selection_config(&self) -> SelectionConfig {
+        SelectionConfig {
+            strategy: self.selection,
+            keep_x_best: self.keep_x_best,
+            tournament_size: self.tournament_size,
+        }
+    }
+
+    pub fn mutation_config(&self) -> MutationConfig {
+        MutationConfig {
+            factor: self.mutation_factor,
+            operator: self.mutation_operator,
+            sigma: self.mutation_sigma,
+            schedule: self.mutation_schedule,
+            decay: self.mutation_decay,
+            scale: self.mutation_scale,
+        }
+    }
+
+    pub fn strategy_config(&self) -> StrategyConfig {
+        StrategyConfig {
+            sigma: self.es_sigma,
+            learning_rate: self.es_learning_rate,
+        }
+    }
 }
 
 pub struct UIPlugin;
@@ -47,29 +262,82 @@ impl Plugin for UIPlugin {
             .init_state::<SimulationState>()
             .init_state::<RenderingState>()
             .add_plugins(EguiPlugin)
-            .add_systems(Startup, configure_app_state)
             .add_systems(Update, (build_ui, ui_controls));
     }
 }
-fn configure_app_state(mut app_state: ResMut<AppConfig>) {
-    app_state.generation_number = 0;
-    app_state.best_score = 0;
-    app_state.average_score = 0;
-    app_state.grid_size = 32;
-    app_state.population_size = 3000;
-    app_state.current_moves = 0;
-    app_state.allowed_moves = 800;
-    app_state.last_merged = 0;
-    app_state.mutation_factor = 0.4;
-    app_state.keep_x_best = 0.02;
-    app_state.vision_range = app_state.grid_size as i64;
-    app_state.food_amount = 10;
-    app_state.print_input = false;
+impl Default for AppConfig {
+    fn default() -> Self {
+        let grid_size = 32;
+        AppConfig {
+            generation_number: 0,
+            best_score: 0,
+            best_fitness: 0.,
+            average_fitness: 0.,
+            fitness_variance: 0.,
+            average_score: 0,
+            grid_size,
+            population_size: 3000,
+            current_moves: 0,
+            allowed_moves: 800,
+            last_merged: 0,
+            diversity: Diversity::default(),
+            island_scores: vec![],
+            mutation_factor: 0.4,
+            mutation_operator: MutationOperator::default(),
+            mutation_sigma: 0.1,
+            mutation_schedule: MutationSchedule::default(),
+            mutation_decay: 0.99,
+            mutation_scale: 1.,
+            keep_x_best: 0.02,
+            selection: SelectionStrategy::default(),
+            tournament_size: 3,
+            crossover: Crossover::default(),
+            elitism: 1,
+            hall_of_fame_size: 10,
+            speciation: false,
+            species_target: 8,
+            species_threshold: 1.,
+            fitness: FitnessFunction::default(),
+            episodes: 1,
+            common_seeds: false,
+            aggregation: Aggregation::default(),
+            optimiser: Optimiser::default(),
+            es_sigma: 0.1,
+            es_learning_rate: 0.05,
+            islands: 1,
+            migration_interval: 10,
+            migration_size: 2,
+            migration_topology: MigrationTopology::default(),
+            island_settings: vec![],
+            vision_range: grid_size as i64,
+            sensors: SensorConfig::default(),
+            actions: ActionSpace::default(),
+            food_amount: 10,
+            architecture: Architecture::default(),
+            neat: NeatConfig::default(),
+            threads: 0,
+            steps_per_frame: 1,
+            turbo: false,
+            sprite_refresh_rate: 30.0,
+            seed: rand::random(),
+            score_history: vec![],
+            print_input: false,
+            checkpoint_path: "checkpoint.bin".to_string(),
+            config_path: "config.json".to_string(),
+            hall_of_fame_path: "hall_of_fame".to_string(),
+            selected_champion: None,
+            file_status: String::new(),
+        }
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn build_ui(
+    mut commands: Commands,
     mut contexts: EguiContexts,
     mut app_config: ResMut<AppConfig>,
+    sim_config: Option<Res<Configuration>>,
+    turbo_snapshot: Option<Res<TurboSnapshot>>,
     sim_state: Res<State<SimulationState>>,
     mut next_sim_state: ResMut<NextState<SimulationState>>,
     rendering_state: Res<State<RenderingState>>,
@@ -82,14 +350,65 @@ fn build_ui(
                 SimulationState::StartUp => {}
                 SimulationState::Evolving => {}
                 SimulationState::Running => {
-                    running_ui(ui, &mut next_sim_state, &app_config);
+                    running_ui(ui, &mut next_sim_state, &mut app_config);
                 }
 
                 SimulationState::Paused | SimulationState::Stopped => {
-                    stopped_ui(ui, &mut app_config, sim_state, &mut next_sim_state);
+                    stopped_ui(ui, &mut app_config, &sim_state, &mut next_sim_state);
                 }
             }
 
+            ui.collapsing("Checkpoint", |ui| {
+                ui.text_edit_singleline(&mut app_config.checkpoint_path);
+                let transient = matches!(
+                    sim_state.get(),
+                    SimulationState::StartUp | SimulationState::Evolving
+                );
+                // in turbo mode the run is mostly on a worker, it is saved once back
+                let run_exists = sim_config.is_some() || turbo_snapshot.is_some();
+                if ui
+                    .add_enabled(
+                        run_exists && !transient,
+                        egui::Button::new("Save checkpoint"),
+                    )
+                    .clicked()
+                {
+                    let path = Path::new(&app_config.checkpoint_path);
+                    app_config.file_status = match &sim_config {
+                        Some(sim_config) => match save_checkpoint(path, &app_config, sim_config) {
+                            Ok(()) => format!("Saved to {}", path.display()),
+                            Err(e) => e.to_string(),
+                        },
+                        None => {
+                            commands.insert_resource(PendingCheckpointSave(path.to_owned()));
+                            format!("Saving to {} after the current batch", path.display())
+                        }
+                    };
+                }
+                if ui
+                    .add_enabled(!transient, egui::Button::new("Load checkpoint"))
+                    .clicked()
+                {
+                    match load_checkpoint(Path::new(&app_config.checkpoint_path)) {
+                        Ok((loaded_config, loaded_sim)) => {
+                            commands.insert_resource(PendingCheckpoint(loaded_config, loaded_sim));
+                            next_sim_state.set(SimulationState::StartUp);
+                        }
+                        Err(e) => app_config.file_status = e.to_string(),
+                    }
+                }
+                ui.label(&app_config.file_status);
+            });
+
+            let hall_of_fame = match (&sim_config, &turbo_snapshot) {
+                (Some(sim_config), _) => Some(&sim_config.simulation.hall_of_fame),
+                (None, Some(snapshot)) => Some(&snapshot.hall_of_fame),
+                (None, None) => None,
+            };
+            if let Some(hall_of_fame) = hall_of_fame {
+                hall_of_fame_ui(ui, &mut app_config, hall_of_fame);
+            }
+
             ui.collapsing("Controls", |ui| {
                 ui.label("Camera controls: WASD/ZQSD/Arrows");
                 ui.label("Zoom: Q,E/PageUp,PageDown");
@@ -119,7 +438,7 @@ fn build_ui(
 fn stopped_ui(
     ui: &mut Ui,
     app_config: &mut ResMut<AppConfig>,
-    sim_state: Res<State<SimulationState>>,
+    sim_state: &State<SimulationState>,
     next_state: &mut NextState<SimulationState>,
 ) {
     match *sim_state.get() {
@@ -128,11 +447,33 @@ fn stopped_ui(
             if ui.button("Start").clicked() {
                 next_state.set(SimulationState::StartUp)
             }
-            ui.add(egui::Slider::new(&mut app_config.grid_size, 0..=128).text("grid size"));
+            let resized = ui
+                .add(egui::Slider::new(&mut app_config.grid_size, 0..=128).text("grid size"))
+                .changed();
+            if resized && app_config.sensors.planes == PlaneView::Board {
+                let grid_size = app_config.grid_size;
+                app_config.sensors.show_board(grid_size);
+            }
             ui.add(
                 egui::Slider::new(&mut app_config.population_size, 0..=10000)
                     .text("population size"),
             );
+            ui.horizontal(|ui| {
+                // a drag value goes through f64 and would round large seeds
+                let mut seed = app_config.seed.to_string();
+                if ui.text_edit_singleline(&mut seed).changed() {
+                    if seed.is_empty() {
+                        app_config.seed = 0;
+                    } else if let Ok(seed) = seed.parse() {
+                        app_config.seed = seed;
+                    }
+                }
+                ui.label("seed");
+                if ui.button("Randomize").clicked() {
+                    app_config.seed = rand::random();
+                }
+            });
+            config_file_ui(ui, app_config);
         }
         SimulationState::Paused => {
             ui.heading("Paused");
@@ -149,6 +490,7 @@ fn stopped_ui(
                 egui::Slider::new(&mut app_config.population_size, 0..=10000)
                     .text("population size"),
             );
+            ui.label(format!("seed: {}", app_config.seed));
         }
         _ => (),
     }
@@ -158,20 +500,508 @@ fn stopped_ui(
             .text("allowed moves before evolution"),
     );
 
+    egui::ComboBox::from_label("Optimiser")
+        .selected_text(app_config.optimiser.to_string())
+        .show_ui(ui, |ui| {
+            for optimiser in Optimiser::ALL {
+                ui.selectable_value(&mut app_config.optimiser, optimiser, optimiser.to_string());
+            }
+        });
+    if app_config.optimiser == Optimiser::Genetic || app_config.architecture.kind == BrainKind::Neat
+    {
+        genetic_ui(ui, app_config);
+    } else {
+        strategy_ui(ui, app_config);
+    }
+    fitness_ui(ui, &mut app_config.fitness);
+    episodes_ui(ui, app_config);
+    ui.add(egui::Slider::new(&mut app_config.hall_of_fame_size, 0..=100).text("Hall of fame size"));
+    ui.add(egui::Slider::new(&mut app_config.vision_range, 0..=256).text("Vision range"));
+    ui.add(egui::Slider::new(&mut app_config.food_amount, 0..=256).text("Food ammount"));
+    ui.add(
+        egui::Slider::new(
+            &mut app_config.threads,
+            0..=std::thread::available_parallelism().map_or(1, usize::from),
+        )
+        .text("Threads (0: all cores)"),
+    );
+    speed_ui(ui, app_config);
+
+    let editable = *sim_state.get() == SimulationState::Stopped;
+    let app_config = &mut **app_config;
+    sensors_ui(ui, &mut app_config.sensors, app_config.grid_size, editable);
+    architecture_ui(
+        ui,
+        &mut app_config.architecture,
+        &mut app_config.neat,
+        app_config.sensors.input_shape(),
+        &mut app_config.actions,
+        editable,
+    );
+}
+
+/// Settings of selection, crossover and mutation
+fn genetic_ui(ui: &mut Ui, app_config: &mut AppConfig) {
+    mutation_ui(ui, app_config);
+
+    egui::ComboBox::from_label("Selection")
+        .selected_text(app_config.selection.to_string())
+        .show_ui(ui, |ui| {
+            for strategy in SelectionStrategy::ALL {
+                ui.selectable_value(&mut app_config.selection, strategy, strategy.to_string());
+            }
+        });
+    match app_config.selection {
+        SelectionStrategy::Threshold => {
+            ui.add(
+                egui::Slider::new(&mut app_config.keep_x_best, 0.0..=0.2)
+                    .text("Selection factor (merged if score > x*best_score)"),
+            );
+        }
+        SelectionStrategy::Truncation => {
+            ui.add(
+                egui::Slider::new(&mut app_config.keep_x_best, 0.0..=1.0)
+                    .text("Fraction of the population kept"),
+            );
+        }
+        SelectionStrategy::Tournament => {
+            ui.add(
+                egui::Slider::new(&mut app_config.tournament_size, 1..=32).text("Tournament size"),
+            );
+        }
+        SelectionStrategy::Roulette | SelectionStrategy::Rank => (),
+    }
+    ui.add(egui::Slider::new(&mut app_config.elitism, 0..=100).text("Elitism (copied unchanged)"));
+    if app_config.architecture.kind == BrainKind::Layered {
+        ui.checkbox(&mut app_config.speciation, "Speciation");
+        if app_config.speciation {
+            ui.add(
+                egui::Slider::new(&mut app_config.species_target, 1..=50).text("Target species"),
+            );
+            ui.label(format!(
+                "Species threshold: {:.4}",
+                app_config.species_threshold
+            ));
+        }
+    }
+    islands_ui(ui, app_config);
+    egui::ComboBox::from_label("Crossover")
+        .selected_text(app_config.crossover.to_string())
+        .show_ui(ui, |ui| {
+            for crossover in Crossover::ALL {
+                ui.selectable_value(&mut app_config.crossover, crossover, crossover.to_string());
+            }
+        });
+}
+
+fn strategy_ui(ui: &mut Ui, app_config: &mut AppConfig) {
+    let sigma_label = match app_config.optimiser {
+        Optimiser::CmaEs => "Initial step size (sigma)",
+        _ => "Perturbation standard deviation (sigma)",
+    };
+    ui.add(egui::Slider::new(&mut app_config.es_sigma, 0.001..=1.0).text(sigma_label));
+    if app_config.optimiser == Optimiser::OpenAiEs {
+        ui.add(
+            egui::Slider::new(&mut app_config.es_learning_rate, 0.001..=1.0)
+                .logarithmic(true)
+                .text("Learning rate"),
+        );
+    }
+    if app_config.optimiser == Optimiser::CmaEs {
+        ui.label(format!(
+            "Above {FULL_COVARIANCE_LIMIT} weights only the variance of each weight is adapted"
+        ));
+    }
+}
+
+/// Weight of every term of the fitness, 0 leaves a term out
+fn fitness_ui(ui: &mut Ui, fitness: &mut FitnessFunction) {
+    ui.collapsing("Fitness", |ui| {
+        for term in FitnessTerm::ALL {
+            let mut weight = fitness.weight(term);
+            ui.horizontal(|ui| {
+                if ui
+                    .add(egui::DragValue::new(&mut weight).speed(0.01))
+                    .changed()
+                {
+                    fitness.set_weight(term, weight);
+                }
+                ui.label(term.to_string());
+            });
+        }
+        ui.label(format!("Fitness = {fitness}"));
+    });
+}
+
+/// Games played by each model per generation and how their fitness are combined
+fn episodes_ui(ui: &mut Ui, app_config: &mut AppConfig) {
+    ui.add(egui::Slider::new(&mut app_config.episodes, 1..=20).text("Episodes per generation"));
+    if app_config.episodes > 1 {
+        ui.checkbox(&mut app_config.common_seeds, "Same food for every model");
+        egui::ComboBox::from_label("Aggregation")
+            .selected_text(app_config.aggregation.to_string())
+            .show_ui(ui, |ui| {
+                for aggregation in Aggregation::ALL {
+                    ui.selectable_value(
+                        &mut app_config.aggregation,
+                        aggregation,
+                        aggregation.to_string(),
+                    );
+                }
+            });
+    }
+}
+
+fn islands_ui(ui: &mut Ui, app_config: &mut AppConfig) {
+    ui.collapsing("Islands", |ui| {
+        ui.add(egui::Slider::new(&mut app_config.islands, 1..=16).text("Islands"));
+        if app_config.islands < 2 {
+            return;
+        }
+        ui.add(
+            egui::Slider::new(&mut app_config.migration_interval, 0..=100)
+                .text("Generations between migrations (0: never)"),
+        );
+        ui.add(egui::Slider::new(&mut app_config.migration_size, 0..=20).text("Migrants"));
+        egui::ComboBox::from_label("Topology")
+            .selected_text(app_config.migration_topology.to_string())
+            .show_ui(ui, |ui| {
+                for topology in MigrationTopology::ALL {
+                    ui.selectable_value(
+                        &mut app_config.migration_topology,
+                        topology,
+                        topology.to_string(),
+                    );
+                }
+            });
+        app_config
+            .island_settings
+            .resize_with(app_config.islands, Default::default);
+        let (mutation_factor, selection) = (app_config.mutation_factor, app_config.selection);
+        for (i, settings) in app_config.island_settings.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.colored_label(island_color(i), format!("Island {}", i + 1));
+
+                let mut own_factor = settings.mutation_factor.is_some();
+                ui.checkbox(&mut own_factor, "mutation factor");
+                settings.mutation_factor =
+                    own_factor.then(|| settings.mutation_factor.unwrap_or(mutation_factor));
+                if let Some(factor) = &mut settings.mutation_factor {
+                    ui.add(
+                        egui::DragValue::new(factor)
+                            .speed(0.01)
+                            .clamp_range(0.0..=1.0),
+                    );
+                }
+
+                let mut own_selection = settings.selection.is_some();
+                ui.checkbox(&mut own_selection, "selection");
+                settings.selection = own_selection.then(|| settings.selection.unwrap_or(selection));
+                if let Some(selection) = &mut settings.selection {
+                    egui::ComboBox::from_id_source(("island selection", i))
+                        .selected_text(selection.to_string())
+                        .show_ui(ui, |ui| {
+                            for strategy in SelectionStrategy::ALL {
+                                ui.selectable_value(selection, strategy, strategy.to_string());
+                            }
+                        });
+                }
+            });
+        }
+    });
+}
+
+/// Color of the snakes of `island` in the grids
+fn island_color(island: usize) -> egui::Color32 {
+    let [r, g, b] = ISLAND_COLORS[island % ISLAND_COLORS.len()];
+    egui::Color32::from_rgb(r, g, b)
+}
+
+fn mutation_ui(ui: &mut Ui, app_config: &mut AppConfig) {
     ui.add(egui::Slider::new(&mut app_config.mutation_factor, 0.0..=1.0).text("Mutation factor"));
+    egui::ComboBox::from_label("Mutation")
+        .selected_text(app_config.mutation_operator.to_string())
+        .show_ui(ui, |ui| {
+            for operator in MutationOperator::ALL {
+                ui.selectable_value(
+                    &mut app_config.mutation_operator,
+                    operator,
+                    operator.to_string(),
+                );
+            }
+        });
+    if app_config.mutation_operator.uses_sigma() {
+        ui.add(
+            egui::Slider::new(&mut app_config.mutation_sigma, 0.001..=2.0)
+                .logarithmic(true)
+                .text("Sigma"),
+        );
+    }
+    egui::ComboBox::from_label("Mutation schedule")
+        .selected_text(app_config.mutation_schedule.to_string())
+        .show_ui(ui, |ui| {
+            for schedule in MutationSchedule::ALL {
+                ui.selectable_value(
+                    &mut app_config.mutation_schedule,
+                    schedule,
+                    schedule.to_string(),
+                );
+            }
+        });
+    if app_config.mutation_schedule == MutationSchedule::Decay {
+        ui.add(egui::Slider::new(&mut app_config.mutation_decay, 0.9..=1.0).text("Decay"));
+    }
+
+    let island_size = app_config.population_size as usize / app_config.islands.max(1);
+    let mutation = Mutation::from_config(&app_config.mutation_config(), island_size);
+    ui.label(format!("Effective per-weight rate: {:.5}", mutation.rate));
+    if app_config.mutation_operator.uses_sigma() {
+        if mutation.self_adaptive {
+            ui.label("Effective sigma: evolved by each genome");
+        } else {
+            ui.label(format!("Effective sigma: {:.4}", mutation.sigma));
+        }
+    }
+}
+
+fn hall_of_fame_ui(ui: &mut Ui, app_config: &mut AppConfig, hall_of_fame: &HallOfFame) {
+    ui.collapsing("Hall of fame", |ui| {
+        ui.text_edit_singleline(&mut app_config.hall_of_fame_path);
+        let dir = Path::new(&app_config.hall_of_fame_path).to_owned();
+        if ui.button("Export all").clicked() {
+            app_config.file_status = match hall_of_fame.export(&dir, BrainFormat::Json) {
+                Ok(paths) => format!("Exported {} brains to {}", paths.len(), dir.display()),
+                Err(e) => e.to_string(),
+            };
+        }
+        for (rank, champion) in hall_of_fame.entries.iter().enumerate() {
+            ui.horizontal(|ui| {
+                let selected = app_config.selected_champion == Some(rank);
+                let text = format!(
+                    "#{} generation {}, score {}, fitness {:.2}",
+                    rank + 1,
+                    champion.generation,
+                    champion.score,
+                    champion.fitness
+                );
+                if ui.selectable_label(selected, text).clicked() {
+                    app_config.selected_champion = if selected { None } else { Some(rank) };
+                }
+                if ui.button("Export").clicked() {
+                    app_config.file_status =
+                        match hall_of_fame.export_one(rank, &dir, BrainFormat::Json) {
+                            Ok(path) => format!("Exported to {}", path.display()),
+                            Err(e) => e.to_string(),
+                        };
+                }
+            });
+        }
+        if let Some(champion) = app_config
+            .selected_champion
+            .and_then(|rank| hall_of_fame.entries.get(rank))
+        {
+            egui::ScrollArea::vertical()
+                .max_height(300.)
+                .show(ui, |ui| ui.monospace(champion.brain.to_string()));
+        }
+        ui.label(&app_config.file_status);
+    });
+}
+
+fn config_file_ui(ui: &mut Ui, app_config: &mut AppConfig) {
+    ui.collapsing("Config file", |ui| {
+        ui.text_edit_singleline(&mut app_config.config_path);
+        ui.horizontal(|ui| {
+            let path = Path::new(&app_config.config_path).to_owned();
+            if ui.button("Save config").clicked() {
+                app_config.file_status = match app_config.save(&path) {
+                    Ok(()) => format!("Saved to {}", path.display()),
+                    Err(e) => e.to_string(),
+                };
+            }
+            if ui.button("Load config").clicked() {
+                match AppConfig::load(&path) {
+                    Ok(loaded) => {
+                        app_config.replace(loaded);
+                        app_config.file_status = format!("Loaded {}", path.display());
+                    }
+                    Err(e) => app_config.file_status = e.to_string(),
+                }
+            }
+        });
+        ui.label(&app_config.file_status);
+    });
+}
+
+/// Sensors making up the input of the brains, they cannot change during a run
+fn sensors_ui(ui: &mut Ui, sensors: &mut SensorConfig, grid_size: u64, editable: bool) {
+    ui.collapsing("Sensors", |ui| {
+        ui.add_enabled_ui(editable, |ui| {
+            for kind in SensorKind::ALL {
+                let mut enabled = sensors.is_enabled(kind);
+                if ui.checkbox(&mut enabled, kind.to_string()).changed() {
+                    sensors.set_enabled(kind, enabled);
+                }
+            }
+            egui::ComboBox::from_label("Frame")
+                .selected_text(sensors.frame.to_string())
+                .show_ui(ui, |ui| {
+                    for frame in Frame::ALL {
+                        ui.selectable_value(&mut sensors.frame, frame, frame.to_string());
+                    }
+                });
+            if sensors.is_enabled(SensorKind::Occupancy) {
+                ui.add(egui::Slider::new(&mut sensors.window, 1..=15).text("Window size"));
+            }
+            if sensors.is_enabled(SensorKind::Planes) {
+                egui::ComboBox::from_label("Planes")
+                    .selected_text(sensors.planes.to_string())
+                    .show_ui(ui, |ui| {
+                        for view in PlaneView::ALL {
+                            let picked = ui
+                                .selectable_value(&mut sensors.planes, view, view.to_string())
+                                .changed();
+                            if picked && view == PlaneView::Board {
+                                sensors.show_board(grid_size);
+                            }
+                        }
+                    });
+                if sensors.planes == PlaneView::Window {
+                    ui.add(egui::Slider::new(&mut sensors.plane_size, 3..=31).text("Plane size"));
+                }
+            }
+        });
+    });
+}
+
+fn architecture_ui(
+    ui: &mut Ui,
+    architecture: &mut Architecture,
+    neat: &mut NeatConfig,
+    input: Shape,
+    actions: &mut ActionSpace,
+    editable: bool,
+) {
+    ui.collapsing("Network", |ui| {
+        ui.add_enabled_ui(editable, |ui| {
+            if input.height * input.width > 1 {
+                ui.label(format!("Input: {input} planes"));
+            } else {
+                ui.label(format!("Input: {} values", input.len()));
+            }
+
+            egui::ComboBox::from_label("Brain")
+                .selected_text(architecture.kind.to_string())
+                .show_ui(ui, |ui| {
+                    for kind in BrainKind::ALL {
+                        ui.selectable_value(&mut architecture.kind, kind, kind.to_string());
+                    }
+                });
 
+            match architecture.kind {
+                BrainKind::Layered => {
+                    let mut removed = None;
+                    for (i, layer) in architecture.hidden_layers.iter_mut().enumerate() {
+                        ui.horizontal(|ui| {
+                            egui::ComboBox::from_id_source(("layer type", i))
+                                .selected_text(layer.kind.to_string())
+                                .show_ui(ui, |ui| {
+                                    for kind in LayerType::ALL {
+                                        ui.selectable_value(
+                                            &mut layer.kind,
+                                            kind,
+                                            kind.to_string(),
+                                        );
+                                    }
+                                });
+                            if matches!(layer.kind, LayerType::Dense | LayerType::Conv2d) {
+                                ui.add(egui::DragValue::new(&mut layer.width).clamp_range(1..=256));
+                            }
+                            if matches!(layer.kind, LayerType::Conv2d | LayerType::MaxPool) {
+                                ui.add(
+                                    egui::DragValue::new(&mut layer.kernel)
+                                        .clamp_range(1..=9)
+                                        .prefix("size "),
+                                );
+                            }
+                            if matches!(layer.kind, LayerType::Dense | LayerType::Conv2d) {
+                                activation_combo_box(ui, ("hidden", i), &mut layer.activation);
+                                ui.checkbox(&mut layer.use_bias, "bias");
+                            }
+                            if ui.button("Remove").clicked() {
+                                removed = Some(i);
+                            }
+                        });
+                    }
+                    if let Some(i) = removed {
+                        architecture.hidden_layers.remove(i);
+                    }
+                    if ui.button("Add hidden layer").clicked() {
+                        architecture.hidden_layers.push(LayerSpec::default());
+                    }
+                }
+                BrainKind::Neat => neat_ui(ui, neat),
+            }
+
+            egui::ComboBox::from_label("Actions")
+                .selected_text(actions.to_string())
+                .show_ui(ui, |ui| {
+                    for space in ActionSpace::ALL {
+                        ui.selectable_value(actions, space, space.to_string());
+                    }
+                });
+            ui.horizontal(|ui| {
+                ui.label(format!("Output: {} values", actions.size()));
+                activation_combo_box(ui, "output", &mut architecture.output_activation);
+                if architecture.kind == BrainKind::Layered {
+                    ui.checkbox(&mut architecture.output_bias, "bias");
+                }
+            });
+
+            if architecture.kind == BrainKind::Layered {
+                egui::ComboBox::from_label("Initialisation")
+                    .selected_text(architecture.init.to_string())
+                    .show_ui(ui, |ui| {
+                        for init in InitScheme::ALL {
+                            ui.selectable_value(&mut architecture.init, init, init.to_string());
+                        }
+                    });
+            }
+        });
+    });
+}
+
+fn neat_ui(ui: &mut Ui, neat: &mut NeatConfig) {
+    ui.horizontal(|ui| {
+        ui.label("Hidden nodes");
+        activation_combo_box(ui, "neat hidden", &mut neat.hidden_activation);
+    });
+    ui.add(egui::Slider::new(&mut neat.add_connection, 0.0..=1.0).text("Add connection"));
+    ui.add(egui::Slider::new(&mut neat.add_node, 0.0..=1.0).text("Add node"));
     ui.add(
-        egui::Slider::new(&mut app_config.keep_x_best, 0.0..=0.2)
-            .text("Selection factor (merged if score > x*best_score)"),
+        egui::Slider::new(&mut neat.compatibility_threshold, 0.1..=10.0)
+            .text("Compatibility threshold"),
     );
-    ui.add(egui::Slider::new(&mut app_config.vision_range, 0..=256).text("Vision range"));
-    ui.add(egui::Slider::new(&mut app_config.food_amount, 0..=256).text("Food ammount"));
+}
+
+fn activation_combo_box(
+    ui: &mut Ui,
+    id: impl std::hash::Hash,
+    activation: &mut ActivationFunction,
+) {
+    egui::ComboBox::from_id_source(id)
+        .selected_text(activation.to_string())
+        .show_ui(ui, |ui| {
+            for function in ActivationFunction::ALL {
+                ui.selectable_value(activation, function, function.to_string());
+            }
+        });
 }
 
 fn running_ui(
     ui: &mut Ui,
     next_state: &mut NextState<SimulationState>,
-    app_config: &ResMut<AppConfig>,
+    app_config: &mut AppConfig,
 ) {
     ui.heading("Running");
     if ui.button("Pause").clicked() {
@@ -181,11 +1011,54 @@ fn running_ui(
     ui.label("Generation #".to_owned() + &app_config.generation_number.to_string());
     ui.label("Best Score: ".to_owned() + &app_config.best_score.to_string());
     ui.label("Average Score: ".to_owned() + &app_config.average_score.to_string());
+    ui.label(format!("Best Fitness: {:.2}", app_config.best_fitness));
+    ui.label(format!(
+        "Average Fitness: {:.2}",
+        app_config.average_fitness
+    ));
+    if app_config.episodes > 1 {
+        ui.label(format!(
+            "Fitness variance between episodes: {:.2}",
+            app_config.fitness_variance
+        ));
+    }
     ui.label("Last Merged: ".to_owned() + &app_config.last_merged.to_string());
+    ui.label("Species: ".to_owned() + &app_config.diversity.species.to_string());
+    ui.label(format!(
+        "Mean distance: {:.4}",
+        app_config.diversity.mean_distance
+    ));
+    ui.label(
+        "Unique behaviours: ".to_owned() + &app_config.diversity.unique_behaviours.to_string(),
+    );
+    if app_config.island_scores.len() > 1 {
+        for (i, (best, average)) in app_config.island_scores.iter().enumerate() {
+            ui.colored_label(
+                island_color(i),
+                format!("Island {}: best {best}, average {average}", i + 1),
+            );
+        }
+    }
 
     ui.add(egui::ProgressBar::new(
-        app_config.current_moves as f32 / app_config.allowed_moves as f32,
+        app_config.current_moves as f32
+            / (app_config.allowed_moves * app_config.episodes.max(1) as u64) as f32,
     ));
+    speed_ui(ui, app_config);
+}
+
+fn speed_ui(ui: &mut Ui, app_config: &mut AppConfig) {
+    ui.checkbox(&mut app_config.turbo, "Turbo");
+    ui.add_enabled(
+        !app_config.turbo,
+        egui::Slider::new(&mut app_config.steps_per_frame, 1..=1000)
+            .logarithmic(true)
+            .text("Steps per tick"),
+    );
+    ui.add(
+        egui::Slider::new(&mut app_config.sprite_refresh_rate, 1.0..=60.0)
+            .text("Sprites refresh rate (Hz)"),
+    );
 }
 
 fn ui_controls(
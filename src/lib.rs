@@ -0,0 +1,3 @@
+pub mod ai_snake;
+pub mod snake_core;
+pub mod snake_game;
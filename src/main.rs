@@ -1,10 +1,87 @@
-use ai_snake::ai_snake_plugin::AISnakePlugin;
+use std::path::PathBuf;
+
+use ai_snake::ai_snake::{
+    ai_snake_plugin::AISnakePlugin, headless, neural_network::sensor::SensorConfig,
+};
+use ai_snake::snake_game::{
+    game::{SnakeGamePlugin, GRID_SIZE},
+    recorder::Recorder,
+};
 use bevy::prelude::PluginGroup;
 use bevy::{app::App, render::texture::ImagePlugin, DefaultPlugins};
-mod ai_snake;
-mod snake_core;
-mod snake_game;
+
+const PLAY_USAGE: &str =
+    "Usage: ai_snake play [--record <path> [--sensors <list>] [--frame <name>]]
+
+Options:
+    --record <path>    append every game to a dataset for imitation learning
+    --sensors <list>   what the recorded snake perceives, a comma separated list
+                       of rays, head-direction, tail-direction, length,
+                       food-vector, wall-distance, occupancy and planes
+                       (default: rays),
+                       ignored when appending to an existing dataset
+    --frame <name>     absolute or egocentric, egocentric sensors turn with the
+                       head so that it always faces up (default: absolute),
+                       ignored when appending to an existing dataset";
+
+/// Sensors given after `--record <path>`, `None` on unknown flags or values
+fn recorded_sensors(args: &[String]) -> Option<SensorConfig> {
+    let mut sensors = SensorConfig::default();
+    for pair in args.chunks(2) {
+        match pair {
+            [flag, list] if flag == "--sensors" => {
+                sensors = SensorConfig {
+                    frame: sensors.frame,
+                    ..list.parse().ok()?
+                }
+            }
+            [flag, frame] if flag == "--frame" => sensors.frame = frame.parse().ok()?,
+            _ => return None,
+        }
+    }
+    Some(sensors)
+}
+
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("train") {
+        if let Err(e) = headless::run(&args[1..]) {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+    if args.first().map(String::as_str) == Some("play") {
+        let recorder = match &args[1..] {
+            [] => None,
+            [flag, path, rest @ ..] if flag == "--record" => {
+                let Some(sensors) = recorded_sensors(rest) else {
+                    println!("{PLAY_USAGE}");
+                    return;
+                };
+                match Recorder::open(PathBuf::from(path), GRID_SIZE as i64, sensors) {
+                    Ok(recorder) => Some(recorder),
+                    Err(e) => {
+                        eprintln!("{e}");
+                        std::process::exit(1);
+                    }
+                }
+            }
+            _ => {
+                println!("{PLAY_USAGE}");
+                return;
+            }
+        };
+        let mut app = App::new();
+        app.add_plugins(DefaultPlugins.set(ImagePlugin::default_nearest()))
+            .add_plugins(SnakeGamePlugin);
+        if let Some(recorder) = recorder {
+            app.insert_resource(recorder);
+        }
+        app.run();
+        return;
+    }
+
     App::new()
         .add_plugins(DefaultPlugins.set(ImagePlugin::default_nearest()))
         .add_plugins(AISnakePlugin)
@@ -1,10 +1,11 @@
 use std::fmt::Display;
 
 use bevy::ecs::system::Resource;
+use serde::{Deserialize, Serialize};
 
 use super::universe::Direction;
 
-#[derive(Debug, Resource)]
+#[derive(Debug, Resource, Serialize, Deserialize)]
 pub struct Snake {
     pub id: usize,
     pub direction: Direction,
@@ -25,6 +26,7 @@ impl Snake {
         }
     }
 
+    #[allow(clippy::manual_is_multiple_of)]
     pub fn move_head(
         &mut self,
         direction: Direction,
@@ -2,14 +2,15 @@ use std::fmt::Display;
 
 use bevy::ecs::system::Resource;
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 
 use super::snake::{Snake, SnakeException};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 
 pub struct Food(pub u64, pub u64);
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Direction {
     Up,
     Down,
@@ -17,7 +18,7 @@ pub enum Direction {
     Right,
 }
 
-#[derive(Debug, Resource)]
+#[derive(Debug, Resource, Serialize, Deserialize)]
 
 pub struct Universe {
     pub width: u64,
@@ -46,14 +47,13 @@ impl Universe {
     pub fn add_snake(&mut self, snake: Snake) {
         self.snakes.push(snake);
     }
-    pub fn spawn_food(&mut self) -> (u64, u64) {
-        let mut rng = rand::thread_rng();
+    pub fn spawn_food(&mut self, rng: &mut impl Rng) -> (u64, u64) {
         let x = rng.gen_range(0..self.width);
         let y = rng.gen_range(0..self.height);
 
         for snake in self.snakes.iter() {
             if snake.is_in_pos((x, y)) {
-                return self.spawn_food();
+                return self.spawn_food(rng);
             }
         }
 
@@ -5,6 +5,7 @@ use bevy::{prelude::*, time::common_conditions::on_timer};
 use super::{
     camera::{camera_controls, spawn_camera},
     game_rendering::sprites::RenderSpritePlugin,
+    recorder::Recorder,
 };
 use crate::snake_core::{snake::Snake, universe::Universe};
 #[derive(Resource)]
@@ -29,8 +30,8 @@ impl Plugin for SnakeGamePlugin {
 }
 
 fn setup_game(mut commands: Commands) {
-    let width = 32;
-    let height = 32;
+    let width = GRID_SIZE;
+    let height = GRID_SIZE;
 
     let config = Configuration {
         width,
@@ -40,7 +41,7 @@ fn setup_game(mut commands: Commands) {
 
     let snake = Snake::new(width, height, 0);
     let mut universe = Universe::new(width, height, vec![snake]);
-    universe.spawn_food();
+    universe.spawn_food(&mut rand::thread_rng());
     commands.insert_resource(universe);
     commands.insert_resource(config);
 }
@@ -80,7 +81,14 @@ fn display_grid(config: Res<Configuration>, universe: ResMut<Universe>, mut gizm
     }
 }
 
-fn snake_controls(keys: Res<ButtonInput<KeyCode>>, mut universe: ResMut<Universe>) {
+/// Width and height of the grid, also the vision range of recorded observations
+pub const GRID_SIZE: u64 = 32;
+
+fn snake_controls(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut universe: ResMut<Universe>,
+    mut recorder: Option<ResMut<Recorder>>,
+) {
     if let Some(snake) = universe.get_snake(0) {
         let current_direction = snake.direction.clone();
         let direction = if keys.pressed(KeyCode::KeyW)
@@ -114,10 +122,31 @@ fn snake_controls(keys: Res<ButtonInput<KeyCode>>, mut universe: ResMut<Universe
             current_direction
         };
 
-        if let Ok(ate) = universe.move_snake(0, direction) {
-            if ate {
-                universe.spawn_food();
+        if let Some(recorder) = &mut recorder {
+            recorder.record(&universe, &direction);
+        }
+        match universe.move_snake(0, direction) {
+            Ok(true) => {
+                universe.spawn_food(&mut rand::thread_rng());
             }
-        };
+            Ok(false) => (),
+            Err(_) => {
+                // recorded games are saved when they end, then a new one starts
+                if let Some(recorder) = &recorder {
+                    match recorder.save() {
+                        Ok(()) => println!(
+                            "Recorded {} moves to {}",
+                            recorder.dataset.demonstrations.len(),
+                            recorder.path.display()
+                        ),
+                        Err(e) => eprintln!("{e}"),
+                    }
+                    let (width, height) = (universe.width, universe.height);
+                    universe.add_snake(Snake::new(width, height, 0));
+                    universe.food.clear();
+                    universe.spawn_food(&mut rand::thread_rng());
+                }
+            }
+        }
     }
 }
@@ -44,9 +44,12 @@ fn update_sprites(
 ) {
     // update snakes
     for (entity, sprite_id, mut transform) in query_body_sprites.iter_mut() {
-        match universe.get_snake(sprite_id.snake_id) {
-            Some(snake) => {
-                let (new_pos_x, new_pos_y) = snake.positions[sprite_id.body_id];
+        // a new game may start with a shorter snake
+        match universe
+            .get_snake(sprite_id.snake_id)
+            .and_then(|snake| snake.positions.get(sprite_id.body_id))
+        {
+            Some(&(new_pos_x, new_pos_y)) => {
                 transform.translation = Vec3::new(
                     new_pos_x as f32 * config.cell_size
                         - config.cell_size * universe.width as f32 / 2.0,
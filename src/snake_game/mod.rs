@@ -1,3 +1,4 @@
 mod camera;
 pub mod game;
 mod game_rendering;
+pub mod recorder;
@@ -0,0 +1,55 @@
+use std::path::PathBuf;
+
+use bevy::ecs::system::Resource;
+
+use crate::{
+    ai_snake::neural_network::{
+        imitation::{Dataset, DatasetError, Demonstration},
+        sensor::{SensorConfig, Sensors},
+    },
+    snake_core::universe::{Direction, Universe},
+};
+
+/// Records the moves of the player into a dataset for imitation learning
+#[derive(Resource)]
+pub struct Recorder {
+    pub path: PathBuf,
+    pub dataset: Dataset,
+    sensors: Sensors,
+}
+
+impl Recorder {
+    /// Appends to the dataset at `path` if it exists, with the sensors it was recorded with
+    pub fn open(
+        path: PathBuf,
+        vision_range: i64,
+        sensors: SensorConfig,
+    ) -> Result<Self, DatasetError> {
+        let dataset = if path.exists() {
+            Dataset::load(&path)?
+        } else {
+            Dataset::new(vision_range, sensors)
+        };
+        let sensors = dataset.sensors.build(dataset.vision_range);
+        Ok(Recorder {
+            path,
+            dataset,
+            sensors,
+        })
+    }
+
+    /// Keeps what the snake sees before moving towards `direction`
+    pub fn record(&mut self, universe: &Universe, direction: &Direction) {
+        if let Some(observation) = self.sensors.observe(universe) {
+            self.dataset.demonstrations.push(Demonstration {
+                observation,
+                direction: direction.clone(),
+                heading: universe.get_snake(0).map(|snake| snake.direction.clone()),
+            });
+        }
+    }
+
+    pub fn save(&self) -> Result<(), DatasetError> {
+        self.dataset.save(&self.path)
+    }
+}